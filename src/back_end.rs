@@ -1,11 +1,25 @@
 extern crate gfx;
 
+use std::error::Error as StdError;
+use std::fmt;
+use std::mem;
+
 use graphics::{ Context, DrawState, Graphics, Viewport };
 use graphics::BACK_END_MAX_VERTEX_COUNT as BUFFER_SIZE;
 use graphics::draw_state;
 use graphics::color::gamma_srgb_to_linear;
+use graphics::math::Matrix2d;
+use graphics::types::{Color, FontSize};
 use Texture;
-use gfx::format::{DepthStencil, Srgba8};
+use bitmap_font::BitmapFont;
+use camera::Camera2d;
+use label_cache::LabelCache;
+use layer::Layer;
+use ping_pong::PingPong;
+use sdf::{SdfGlyphCache, SdfStyle};
+use glsl;
+use gfx::format::{DepthStencil, Rgba16F, Srgba8};
+use gfx::memory::Typed;
 use gfx::pso::PipelineState;
 use shader_version::{ OpenGL, Shaders };
 use shader_version::glsl::GLSL;
@@ -15,6 +29,25 @@ use shader_version::glsl::GLSL;
 // `4` for bytes per f32, and `2 + 4` for position and color.
 const CHUNKS: usize = 100;
 
+// The bounds of segment counts chosen for curves, see `Gfx2d::curve_resolution`.
+const MIN_CURVE_SEGMENTS: u32 = 8;
+const MAX_CURVE_SEGMENTS: u32 = 1024;
+
+// The number of sprite instances per draw call, see `draw_instanced_sprites`.
+const MAX_INSTANCES: usize = 1024;
+
+// The maximum outline width in texels, see `image_outlined`.
+const MAX_OUTLINE_WIDTH: f32 = 16.0;
+
+// The maximum downsampling factor, see `downsample`.
+const MAX_DOWNSAMPLE_FACTOR: u32 = 16;
+
+// The number of cached PSOs with custom blending, see `tri_list_custom_blend`.
+const MAX_CUSTOM_BLENDS: usize = 16;
+
+// The maximum number of grid lines along each axis, see `grid`.
+const MAX_GRID_LINES: usize = 4096;
+
 gfx_defines! {
     vertex PositionFormat {
         pos: [f32; 2] = "pos",
@@ -27,12 +60,47 @@ gfx_defines! {
     vertex TexCoordsFormat {
         uv: [f32; 2] = "uv",
     }
+
+    /// A vertex of a mesh drawn by `GfxGraphics::draw_mesh`.
+    ///
+    /// Stored interleaved as 4 floats, the position followed by the texture coordinates.
+    vertex MeshVertex {
+        /// The position, transformed by the transform passed to `draw_mesh`.
+        pos: [f32; 2] = "pos",
+        /// The texture coordinates.
+        uv: [f32; 2] = "uv",
+    }
+
+    /// A vertex position of a colored mesh drawn by `GfxGraphics::draw_mesh_colored`.
+    vertex MeshPosition {
+        /// The position, transformed by the transform passed to `draw_mesh_colored`.
+        pos: [f32; 2] = "pos",
+    }
+
+    /// A vertex color of a colored mesh drawn by `GfxGraphics::draw_mesh_colored`.
+    vertex MeshColor {
+        /// The linear RGBA color.
+        color: [f32; 4] = "color",
+    }
+
+    /// A vertex of the full-screen triangle drawn by `GfxGraphics::full_screen_pass`.
+    vertex FullScreenVertex {
+        /// The position in normalized device coordinates.
+        pos: [f32; 2] = "pos",
+    }
+
+    vertex SpriteInstanceFormat {
+        transform_x: [f32; 3] = "transform_x",
+        transform_y: [f32; 3] = "transform_y",
+        uv_rect: [f32; 4] = "uv_rect",
+        tint: [f32; 4] = "tint",
+    }
 }
 
 gfx_pipeline_base!( pipe_colored {
     pos: gfx::VertexBuffer<PositionFormat>,
     color: gfx::VertexBuffer<ColorFormat>,
-    blend_target: gfx::BlendTarget<gfx::format::Srgba8>,
+    blend_target: gfx::RawRenderTarget,
     stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
     blend_ref: gfx::BlendRef,
     scissor: gfx::Scissor,
@@ -43,360 +111,6603 @@ gfx_pipeline_base!( pipe_textured {
     uv: gfx::VertexBuffer<TexCoordsFormat>,
     color: gfx::Global<[f32; 4]>,
     texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_textured_color {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::VertexBuffer<ColorFormat>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_mul_add {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    mul: gfx::Global<[f32; 4]>,
+    add: gfx::Global<[f32; 4]>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_color_key {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    key: gfx::Global<[f32; 3]>,
+    tolerance: gfx::Global<f32>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_chroma_key {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    key_dir: gfx::Global<[f32; 2]>,
+    tolerance: gfx::Global<f32>,
+    softness: gfx::Global<f32>,
+    despill: gfx::Global<f32>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_textured_lod {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    level: gfx::Global<f32>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_sdf {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    outline_color: gfx::Global<[f32; 4]>,
+    outline_width: gfx::Global<f32>,
+    glow_color: gfx::Global<[f32; 4]>,
+    glow_width: gfx::Global<f32>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_pattern {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    color: gfx::Global<[f32; 4]>,
+    pattern_transform: gfx::Global<[[f32; 3]; 3]>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_aa_line {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    half_width: gfx::Global<f32>,
+    alpha_scale: gfx::Global<f32>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_checkerboard {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    color_a: gfx::Global<[f32; 4]>,
+    color_b: gfx::Global<[f32; 4]>,
+    cell_size: gfx::Global<f32>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_dissolve {
+    pos: gfx::VertexBuffer<FullScreenVertex>,
+    color: gfx::Global<[f32; 4]>,
+    edge_color: gfx::Global<[f32; 4]>,
+    threshold: gfx::Global<f32>,
+    edge_width: gfx::Global<f32>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    noise: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_downsample {
+    pos: gfx::VertexBuffer<FullScreenVertex>,
+    factor: gfx::Global<i32>,
+    src_size: gfx::Global<[f32; 2]>,
+    texture: gfx::TextureSampler<[f32; 4]>,
     blend_target: gfx::BlendTarget<gfx::format::Srgba8>,
     stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
     blend_ref: gfx::BlendRef,
     scissor: gfx::Scissor,
 });
 
-// Stores one PSO per blend setting.
-struct PsoBlend<T> {
-    alpha: T,
-    add: T,
-    multiply: T,
-    invert: T,
-    none: T,
-    lighter: T,
+gfx_pipeline_base!( pipe_grain {
+    pos: gfx::VertexBuffer<FullScreenVertex>,
+    intensity: gfx::Global<f32>,
+    seed: gfx::Global<f32>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_vignette {
+    pos: gfx::VertexBuffer<FullScreenVertex>,
+    color: gfx::Global<[f32; 4]>,
+    size: gfx::Global<[f32; 2]>,
+    radius: gfx::Global<f32>,
+    smoothness: gfx::Global<f32>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_gradient {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color_upper_left: gfx::Global<[f32; 4]>,
+    color_upper_right: gfx::Global<[f32; 4]>,
+    color_lower_left: gfx::Global<[f32; 4]>,
+    color_lower_right: gfx::Global<[f32; 4]>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_outline {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    outline_color: gfx::Global<[f32; 4]>,
+    texel: gfx::Global<[f32; 2]>,
+    width: gfx::Global<f32>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_rounded_image {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    size: gfx::Global<[f32; 2]>,
+    radii: gfx::Global<[f32; 4]>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_instanced_sprite {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    instance: gfx::InstanceBuffer<SpriteInstanceFormat>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_mesh {
+    vbuf: gfx::VertexBuffer<MeshVertex>,
+    transform_x: gfx::Global<[f32; 3]>,
+    transform_y: gfx::Global<[f32; 3]>,
+    color: gfx::Global<[f32; 4]>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_mesh_colored {
+    pos: gfx::VertexBuffer<MeshPosition>,
+    color: gfx::VertexBuffer<MeshColor>,
+    transform_x: gfx::Global<[f32; 3]>,
+    transform_y: gfx::Global<[f32; 3]>,
+    tint: gfx::Global<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_depth_colored {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    depth: gfx::Global<f32>,
+    color: gfx::Global<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    depth_stencil_target: gfx::DepthStencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_depth_textured {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    depth: gfx::Global<f32>,
+    color: gfx::Global<[f32; 4]>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    depth_stencil_target: gfx::DepthStencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_masked {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    texture: gfx::TextureSampler<[f32; 4]>,
+    mask: gfx::TextureSampler<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+gfx_pipeline_base!( pipe_soft_point {
+    pos: gfx::VertexBuffer<PositionFormat>,
+    uv: gfx::VertexBuffer<TexCoordsFormat>,
+    color: gfx::Global<[f32; 4]>,
+    blend_target: gfx::RawRenderTarget,
+    stencil_target: gfx::StencilTarget<gfx::format::DepthStencil>,
+    blend_ref: gfx::BlendRef,
+    scissor: gfx::Scissor,
+});
+
+/// Which space a fill pattern is anchored to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PatternAnchor {
+    /// The pattern stays fixed on screen, in pixels.
+    Screen,
+    /// The pattern moves with the shape, in shape coordinates.
+    Shape,
+}
+
+/// The origin of the coordinate system of the context passed to drawing closures.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// The origin is in the upper left corner with the y axis pointing down.
+    UpperLeft,
+    /// The origin is in the lower left corner with the y axis pointing up.
+    ///
+    /// This matches framebuffer coordinates on OpenGL, used for scissor rectangles.
+    LowerLeft,
 }
 
-impl<T> PsoBlend<T> {
-    fn blend(&mut self, blend: Option<draw_state::Blend>) -> &mut T {
-        use graphics::draw_state::Blend;
+/// The stencil bits used for clipping, see `GfxGraphics::set_clip_plane`.
+///
+/// The lower and upper planes split the 8 stencil bits in two,
+/// so two clip regions can be active independently, e.g. a window and a scroll view.
+/// Stencil values of draw states are from `0` to `15` for these planes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClipPlane {
+    /// All 8 bits are used.
+    All,
+    /// The lower 4 bits are used.
+    Lower,
+    /// The upper 4 bits are used.
+    Upper,
+}
+
+impl ClipPlane {
+    /// Returns the stencil bits of the plane.
+    pub fn bits(self) -> u8 {
+        match self {
+            ClipPlane::All => 0xff,
+            ClipPlane::Lower => 0x0f,
+            ClipPlane::Upper => 0xf0,
+        }
+    }
 
-        match blend {
-            Some(Blend::Alpha) => &mut self.alpha,
-            Some(Blend::Add) => &mut self.add,
-            Some(Blend::Multiply) => &mut self.multiply,
-            Some(Blend::Invert) => &mut self.invert,
-            Some(Blend::Lighter) => &mut self.lighter,
-            None => &mut self.none,
+    // Moves a stencil value into the bits of the plane.
+    fn shift(self, val: u8) -> u8 {
+        match self {
+            ClipPlane::All => val,
+            ClipPlane::Lower => val & 0x0f,
+            ClipPlane::Upper => val << 4,
         }
     }
 }
 
-// Stores one `PsoBlend` per clip setting.
-struct PsoStencil<T> {
-    none: PsoBlend<T>,
-    clip: PsoBlend<T>,
-    inside: PsoBlend<T>,
-    outside: PsoBlend<T>,
+/// An error when resolving a render target, see `Gfx2d::resolve`.
+#[derive(Debug)]
+pub enum ResolveError {
+    /// The formats of the source and destination differ.
+    FormatMismatch {
+        /// The source format.
+        src: gfx::format::SurfaceType,
+        /// The destination format.
+        dst: gfx::format::SurfaceType,
+    },
+    /// The sizes of the source and destination differ.
+    SizeMismatch {
+        /// The source size in pixels.
+        src: [u16; 2],
+        /// The destination size in pixels.
+        dst: [u16; 2],
+    },
+    /// The source is multisampled, which requires a blit that gfx does not expose.
+    Multisampled,
+    /// An error when copying, e.g. a missing bind flag.
+    Copy(gfx::CopyError<[u16; 3], [u16; 3]>),
 }
 
-impl<T> PsoStencil<T> {
-    fn new<Fact, F>(factory: &mut Fact, f: F) -> PsoStencil<T>
-        where F: Fn(
-            &mut Fact,
-            gfx::state::Blend,
-            gfx::state::Stencil,
-            gfx::state::ColorMask
-        ) -> T
-    {
-        use gfx::state::{Blend, BlendChannel, Comparison, Equation, Factor,
-            Stencil, StencilOp};
-        use gfx::preset::blend;
-
-        let stencil = Stencil::new(Comparison::Always, 0,
-            (StencilOp::Keep, StencilOp::Keep, StencilOp::Keep));
-        let stencil_clip = Stencil::new(Comparison::Never, 255,
-            (StencilOp::Replace, StencilOp::Keep, StencilOp::Keep));
-        let stencil_inside = Stencil::new(Comparison::Equal, 255,
-            (StencilOp::Keep, StencilOp::Keep, StencilOp::Keep));
-        let stencil_outside = Stencil::new(Comparison::NotEqual, 255,
-            (StencilOp::Keep, StencilOp::Keep, StencilOp::Keep));
-
-        // Channel color masks.
-        let mask_all = gfx::state::ColorMask::all();
-        let mask_none = gfx::state::ColorMask::empty();
-
-        // Fake disabled blending using the same pipeline.
-        let no_blend = Blend {
-            color: BlendChannel {
-                equation: Equation::Add,
-                source: Factor::One,
-                destination: Factor::Zero,
-            },
-            alpha: BlendChannel {
-                equation: Equation::Add,
-                source: Factor::One,
-                destination: Factor::Zero,
-            },
-        };
+impl fmt::Display for ResolveError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResolveError::FormatMismatch { src, dst } =>
+                write!(w, "Source format {:?} does not match destination format {:?}", src, dst),
+            ResolveError::SizeMismatch { src, dst } =>
+                write!(w, "Source size {}x{} does not match destination size {}x{}",
+                    src[0], src[1], dst[0], dst[1]),
+            ResolveError::Multisampled =>
+                write!(w, "Resolving multisampled textures is not supported"),
+            ResolveError::Copy(ref err) => write!(w, "Could not copy texture: {:?}", err),
+        }
+    }
+}
 
-        use gfx::state::BlendValue;
+impl StdError for ResolveError {}
 
-        const BLEND_LIGHTER: Blend = Blend {
-                    color: BlendChannel {
-                        equation: Equation::Add,
-                        source: Factor::ZeroPlus(BlendValue::SourceAlpha),
-                        destination: Factor::One,
-                    },
-                    alpha: BlendChannel {
-                        equation: Equation::Add,
-                        source: Factor::Zero,
-                        destination: Factor::One,
-                    },
-                };
+/// An error when creating a `Gfx2d` object or a custom pipeline.
+#[derive(Debug)]
+pub enum Gfx2dError {
+    /// A pipeline could neither be created with alpha blending nor without blending.
+    Pipeline {
+        /// The name of the pipeline, e.g. `"textured"`.
+        name: &'static str,
+        /// The error from creating the pipeline.
+        message: String,
+    },
+    /// An error when creating vertex buffers.
+    Buffer(gfx::buffer::CreationError),
+}
 
-        PsoStencil {
-            none: PsoBlend {
-                alpha: f(factory, blend::ALPHA, stencil, mask_all),
-                add: f(factory, blend::ADD, stencil, mask_all),
-                multiply: f(factory, blend::MULTIPLY, stencil, mask_all),
-                invert: f(factory, blend::INVERT, stencil, mask_all),
-                lighter: f(factory, BLEND_LIGHTER, stencil, mask_all),
-                none: f(factory, no_blend, stencil, mask_all),
-            },
-            clip: PsoBlend {
-                alpha: f(factory, blend::ALPHA, stencil_clip, mask_none),
-                add: f(factory, blend::ADD, stencil_clip, mask_none),
-                multiply: f(factory, blend::MULTIPLY, stencil_clip, mask_none),
-                invert: f(factory, blend::INVERT, stencil_clip, mask_none),
-                lighter: f(factory, BLEND_LIGHTER, stencil_clip, mask_none),
-                none: f(factory, no_blend, stencil_clip, mask_none),
-            },
-            inside: PsoBlend {
-                alpha: f(factory, blend::ALPHA, stencil_inside, mask_all),
-                add: f(factory, blend::ADD, stencil_inside, mask_all),
-                multiply: f(factory, blend::MULTIPLY, stencil_inside, mask_all),
-                invert: f(factory, blend::INVERT, stencil_inside, mask_all),
-                lighter: f(factory, BLEND_LIGHTER, stencil_inside, mask_all),
-                none: f(factory, no_blend, stencil_inside, mask_all),
-            },
-            outside: PsoBlend {
-                alpha: f(factory, blend::ALPHA, stencil_outside, mask_all),
-                add: f(factory, blend::ADD, stencil_outside, mask_all),
-                multiply: f(factory, blend::MULTIPLY, stencil_outside, mask_all),
-                invert: f(factory, blend::INVERT, stencil_outside, mask_all),
-                lighter: f(factory, BLEND_LIGHTER, stencil_outside, mask_all),
-                none: f(factory, no_blend, stencil_outside, mask_all),
-            }
+impl From<gfx::buffer::CreationError> for Gfx2dError {
+    fn from(val: gfx::buffer::CreationError) -> Gfx2dError {
+        Gfx2dError::Buffer(val)
+    }
+}
+
+impl fmt::Display for Gfx2dError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Gfx2dError::Pipeline { name, ref message } =>
+                write!(w, "Could not create pipeline `{}`: {}", name, message),
+            Gfx2dError::Buffer(ref err) => write!(w, "Could not create buffers: {}", err),
         }
     }
+}
 
-    // Returns a PSO and stencil reference given a stencil and blend setting.
-    fn stencil_blend(
-        &mut self,
-        stencil: Option<draw_state::Stencil>,
-        blend: Option<draw_state::Blend>
-    ) -> (&mut T, u8) {
-        use graphics::draw_state::Stencil;
+impl StdError for Gfx2dError {}
 
-        match stencil {
-            None => (self.none.blend(blend), 0),
-            Some(Stencil::Clip(val)) => (self.clip.blend(blend), val),
-            Some(Stencil::Inside(val)) => (self.inside.blend(blend), val),
-            Some(Stencil::Outside(val)) => (self.outside.blend(blend), val),
-        }
+/// An error when drawing to a render target of another format than `Gfx2d`,
+/// e.g. with `draw_linear` when created with `new`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FormatError {
+    /// The surface type of `Gfx2d`.
+    pub expected: gfx::format::SurfaceType,
+    /// The surface type of the render target.
+    pub found: gfx::format::SurfaceType,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "Render target format {:?} does not match the format {:?} of `Gfx2d`",
+            self.found, self.expected)
     }
 }
 
-/// The data used for drawing 2D graphics.
-///
-/// Stores buffers and PSO objects needed for rendering 2D graphics.
-pub struct Gfx2d<R: gfx::Resources> {
-    // The offset in vertices for colored rendering.
-    colored_offset: usize,
-    // The current draw state for colored rendering.
-    colored_draw_state: DrawState,
-    buffer_pos: gfx::handle::Buffer<R, PositionFormat>,
-    buffer_color: gfx::handle::Buffer<R, ColorFormat>,
-    buffer_uv: gfx::handle::Buffer<R, TexCoordsFormat>,
-    buffer_indices: gfx::handle::Buffer<R, u16>,
-    colored: PsoStencil<PipelineState<R, pipe_colored::Meta>>,
-    textured: PsoStencil<PipelineState<R, pipe_textured::Meta>>,
+impl StdError for FormatError {}
+
+// Converts a half precision float to `f32`.
+pub fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 {-1.0} else {1.0};
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 => if mantissa == 0.0 {sign * f32::INFINITY} else {f32::NAN},
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
 }
 
-impl<R: gfx::Resources> Gfx2d<R> {
-    /// Creates a new Gfx2d object.
-    pub fn new<F>(opengl: OpenGL, factory: &mut F) -> Self
+// Clears a render target of any color format.
+fn clear_raw<R, C>(
+    encoder: &mut gfx::Encoder<R, C>,
+    output_color: &gfx::handle::RawRenderTargetView<R>,
+    color: [f32; 4]
+)
+    where R: gfx::Resources,
+          C: gfx::CommandBuffer<R>
+{
+    // Only the raw view is used for clearing, so any float format works.
+    let view: gfx::handle::RenderTargetView<R, Srgba8> = Typed::new(output_color.clone());
+    encoder.clear(&view, color);
+}
+
+// Returns the copy region of one pixel of a 2D texture.
+fn pixel_info(pos: [u32; 2], format: gfx::format::Format) -> gfx::texture::RawImageInfo {
+    gfx::texture::RawImageInfo {
+        xoffset: pos[0] as u16,
+        yoffset: pos[1] as u16,
+        zoffset: 0,
+        width: 1,
+        height: 1,
+        // 2D textures have a depth of `0` in `Kind::get_dimensions`.
+        depth: 0,
+        format,
+        mipmap: 0,
+    }
+}
+
+// Converts a depth where larger values are in front to normalized device coordinates,
+// where `z = 0.0` is at the far plane.
+fn depth_ndc(z: f32) -> f32 {
+    1.0 - 2.0 * z.clamp(0.0, 1.0)
+}
+
+// Flips the y axis of a transform into normalized device coordinates.
+fn flip_y(m: Matrix2d) -> Matrix2d {
+    [m[0], [-m[1][0], -m[1][1], -m[1][2]]]
+}
+
+/// Options for drawing a sprite.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpriteOptions {
+    /// The color multiplied with the texture.
+    pub tint: Color,
+    /// Whether to flip the texture horizontally.
+    pub flip_x: bool,
+    /// Whether to flip the texture vertically.
+    pub flip_y: bool,
+    /// The rotation in radians, clockwise with the y axis pointing down.
+    pub rotation: f64,
+    /// The point to rotate around, relative to the upper left corner of the rectangle.
+    pub origin: [f64; 2],
+}
+
+/// Style settings for `GfxGraphics::grid`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GridStyle {
+    /// The size of a cell.
+    pub cell_size: [f64; 2],
+    /// The line width in pixels.
+    pub width: f64,
+    /// The color of minor lines.
+    pub color: Color,
+    /// The number of cells between major lines, or `0` for no major lines.
+    pub major_every: u32,
+    /// The color of major lines.
+    pub major_color: Color,
+}
+
+// Returns the index of the first multiple of a cell size within a range
+// and the number of multiples, at most `MAX_GRID_LINES`.
+fn grid_lines(start: f64, len: f64, cell: f64) -> (f64, usize) {
+    let first = (start / cell).ceil();
+    let last = ((start + len) / cell).floor();
+    if last >= first {
+        (first, (last - first + 1.0).min(MAX_GRID_LINES as f64) as usize)
+    } else {
+        (first, 0)
+    }
+}
+
+impl GridStyle {
+    /// Creates a new style with minor lines one pixel wide.
+    pub fn new(cell_size: [f64; 2], color: Color) -> GridStyle {
+        GridStyle {
+            cell_size,
+            width: 1.0,
+            color,
+            major_every: 0,
+            major_color: color,
+        }
+    }
+
+    /// Sets the line width in pixels.
+    pub fn width(mut self, width: f64) -> GridStyle {
+        self.width = width;
+        self
+    }
+
+    /// Draws every `n`th line as a major line with a different color.
+    pub fn major(mut self, every: u32, color: Color) -> GridStyle {
+        self.major_every = every;
+        self.major_color = color;
+        self
+    }
+}
+
+/// A region to clip drawing to, see `GfxGraphics::clip`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClipRegion<'a> {
+    /// The rectangle `[x, y, w, h]`.
+    Rect([f64; 4]),
+    /// A shape given as triangle list.
+    Shape(&'a [[f64; 2]]),
+}
+
+/// A sprite drawn by `GfxGraphics::draw_instanced_sprites`.
+///
+/// Each instance is uploaded as 14 floats: the two rows of the transform,
+/// the texture coordinates and the tint.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpriteInstance {
+    /// Maps the unit square from `[0, 0]` to `[1, 1]` to normalized device coordinates,
+    /// e.g. `c.transform.trans(x, y).scale(w, h)` for the rectangle `[x, y, w, h]`.
+    pub transform: Matrix2d,
+    /// The texture coordinates `[u1, v1, u2, v2]`.
+    pub uv: [f32; 4],
+    /// The color multiplied with the texture.
+    pub tint: Color,
+}
+
+/// Settings for `GfxGraphics::chroma_key`.
+///
+/// Hues are angles in degrees in the Oklab color space, where green screens
+/// are at about 142 degrees and blue screens at about 264 degrees.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChromaKey {
+    /// The hue to remove.
+    pub hue: f32,
+    /// The hue difference up to which texels are removed.
+    pub tolerance: f32,
+    /// The hue difference over which texels fade in after the tolerance.
+    pub softness: f32,
+    /// How much of the key hue is removed from the remaining texels,
+    /// in the range `0.0..=1.0`.
+    pub despill: f32,
+}
+
+impl ChromaKey {
+    /// Creates new settings removing hues within 30 degrees of a hue,
+    /// fading in over 10 degrees and with half despill.
+    pub fn new(hue: f32) -> ChromaKey {
+        ChromaKey {
+            hue,
+            tolerance: 30.0,
+            softness: 10.0,
+            despill: 0.5,
+        }
+    }
+
+    /// Sets the tolerance and softness in degrees.
+    pub fn tolerance(mut self, tolerance: f32, softness: f32) -> ChromaKey {
+        self.tolerance = tolerance;
+        self.softness = softness;
+        self
+    }
+
+    /// Sets the despill strength.
+    pub fn despill(mut self, despill: f32) -> ChromaKey {
+        self.despill = despill;
+        self
+    }
+}
+
+/// The point of a sprite placed at its position, see `SpritePlacement`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpriteAnchor {
+    /// A point relative to the size of the texture,
+    /// from `[0.0, 0.0]` in the upper left to `[1.0, 1.0]` in the lower right corner.
+    Normalized([f64; 2]),
+    /// A point in texture pixels, from the upper left corner.
+    Pixels([f64; 2]),
+}
+
+impl SpriteAnchor {
+    /// The center of the sprite.
+    pub const CENTER: SpriteAnchor = SpriteAnchor::Normalized([0.5, 0.5]);
+    /// The middle of the bottom edge, e.g. the feet of a character.
+    pub const BOTTOM_CENTER: SpriteAnchor = SpriteAnchor::Normalized([0.5, 1.0]);
+    /// The upper left corner.
+    pub const UPPER_LEFT: SpriteAnchor = SpriteAnchor::Normalized([0.0, 0.0]);
+
+    /// Returns the anchor in texture pixels for a texture size.
+    pub fn to_pixels(self, size: [f64; 2]) -> [f64; 2] {
+        match self {
+            SpriteAnchor::Normalized([u, v]) => [u * size[0], v * size[1]],
+            SpriteAnchor::Pixels(p) => p,
+        }
+    }
+}
+
+/// Where to draw a sprite, see `GfxGraphics::draw_sprite_anchored`.
+///
+/// The anchor of the sprite is placed at the position,
+/// and the sprite is scaled and rotated around the anchor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpritePlacement {
+    /// The position of the anchor.
+    pub pos: [f64; 2],
+    /// The point of the sprite at the position, which is also the pivot.
+    pub anchor: SpriteAnchor,
+    /// The scale of the texture size, where negative values flip the sprite.
+    pub scale: [f64; 2],
+    /// The rotation in radians, clockwise with the y axis pointing down.
+    pub rotation: f64,
+}
+
+impl SpritePlacement {
+    /// Creates a placement with the center at a position, without scale or rotation.
+    pub fn new(pos: [f64; 2]) -> SpritePlacement {
+        SpritePlacement {
+            pos,
+            anchor: SpriteAnchor::CENTER,
+            scale: [1.0, 1.0],
+            rotation: 0.0,
+        }
+    }
+
+    /// Sets the anchor.
+    pub fn anchor(mut self, anchor: SpriteAnchor) -> SpritePlacement {
+        self.anchor = anchor;
+        self
+    }
+
+    /// Sets the scale.
+    pub fn scale(mut self, sx: f64, sy: f64) -> SpritePlacement {
+        self.scale = [sx, sy];
+        self
+    }
+
+    /// Sets the rotation in radians.
+    pub fn rotation(mut self, rotation: f64) -> SpritePlacement {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Returns the transform from texture pixels of a texture size to the coordinates
+    /// of the position, e.g. to test if a point hits the sprite.
+    pub fn transform(&self, size: [f64; 2]) -> Matrix2d {
+        use graphics::math::{multiply, rotate_radians, scale, translate};
+
+        let [ax, ay] = self.anchor.to_pixels(size);
+        multiply(multiply(multiply(
+            translate(self.pos),
+            rotate_radians(self.rotation)),
+            scale(self.scale[0], self.scale[1])),
+            translate([-ax, -ay]))
+    }
+}
+
+impl Default for SpriteOptions {
+    fn default() -> SpriteOptions {
+        SpriteOptions {
+            tint: [1.0; 4],
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.0,
+            origin: [0.0; 2],
+        }
+    }
+}
+
+impl SpriteOptions {
+    // Returns the vertices and texture coordinates of the two triangles of a sprite.
+    fn quad(&self, dst_rect: [f64; 4], transform: Matrix2d) -> ([[f32; 2]; 6], [[f32; 2]; 6]) {
+        use graphics::Transformed;
+        use graphics::math::transform_pos;
+
+        let [x, y, w, h] = dst_rect;
+        let [ox, oy] = self.origin;
+        let transform = transform
+            .trans(x + ox, y + oy)
+            .rot_rad(self.rotation);
+        let corner = |u: f64, v: f64| {
+            let p = transform_pos(transform, [u * w - ox, v * h - oy]);
+            [p[0] as f32, p[1] as f32]
+        };
+        let vertices = [
+            corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0),
+            corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)
+        ];
+        let (u1, u2) = if self.flip_x {(1.0, 0.0)} else {(0.0, 1.0)};
+        let (v1, v2) = if self.flip_y {(1.0, 0.0)} else {(0.0, 1.0)};
+        let uvs = [
+            [u1, v1], [u2, v1], [u1, v2],
+            [u2, v1], [u2, v2], [u1, v2]
+        ];
+        (vertices, uvs)
+    }
+}
+
+// Inverts an affine transform, returning `None` if it is degenerate.
+fn invert_transform(m: Matrix2d) -> Option<Matrix2d> {
+    let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+    if det.abs() < f64::EPSILON {return None};
+    Some([
+        [m[1][1] / det, -m[0][1] / det, (m[0][1] * m[1][2] - m[0][2] * m[1][1]) / det],
+        [-m[1][0] / det, m[0][0] / det, (m[0][2] * m[1][0] - m[0][0] * m[1][2]) / det],
+    ])
+}
+
+// Converts an affine transform to a column major 3x3 matrix.
+fn transform_to_mat3(m: Matrix2d) -> [[f32; 3]; 3] {
+    [
+        [m[0][0] as f32, m[1][0] as f32, 0.0],
+        [m[0][1] as f32, m[1][1] as f32, 0.0],
+        [m[0][2] as f32, m[1][2] as f32, 1.0],
+    ]
+}
+
+// The slice of the full-screen triangle.
+fn full_screen_slice<R: gfx::Resources>() -> gfx::Slice<R> {
+    gfx::Slice {
+        instances: None,
+        start: 0,
+        end: 3,
+        buffer: gfx::IndexBuffer::Auto,
+        base_vertex: 0,
+    }
+}
+
+// Draws the first `n` vertices of the vertex buffers with a PSO and counts the draw call.
+fn draw_slice<R, C, D>(
+    encoder: &mut gfx::Encoder<R, C>,
+    n: usize,
+    pso: &gfx::PipelineState<R, D::Meta>,
+    data: &D,
+    stats: &mut DrawStats,
+    pso_key: usize
+)
+    where R: gfx::Resources, C: gfx::CommandBuffer<R>, D: gfx::pso::PipelineData<R>
+{
+    let slice = gfx::Slice {
+        instances: None,
+        start: 0,
+        end: n as u32,
+        buffer: gfx::IndexBuffer::Auto,
+        base_vertex: 0,
+    };
+    encoder.draw(&slice, pso, data);
+    stats.record(pso_key, n);
+}
+
+// Returns a triangle fan per contour, as a triangle list of transformed vertices.
+// Inverting the stencil per triangle leaves the points covered by an odd number
+// of contours set, see `clip_even_odd`.
+fn contour_fans(contours: &[&[[f64; 2]]], transform: Matrix2d) -> Vec<[f32; 2]> {
+    use graphics::math::transform_pos;
+
+    let mut vertices: Vec<[f32; 2]> = vec![];
+    for contour in contours {
+        let pos = |i: usize| {
+            let p = transform_pos(transform, contour[i]);
+            [p[0] as f32, p[1] as f32]
+        };
+        for i in 1..contour.len().saturating_sub(1) {
+            vertices.extend_from_slice(&[pos(0), pos(i), pos(i + 1)]);
+        }
+    }
+    vertices
+}
+
+// Returns the vertices of the two triangles of a rectangle `[x, y, w, h]`,
+// with texture coordinates from `[0, 0]` in the upper left corner to `[1, 1]`.
+fn quad(dst_rect: [f64; 4], transform: Matrix2d)
+    -> ([PositionFormat; 6], [TexCoordsFormat; 6])
+{
+    use graphics::math::transform_pos;
+
+    let [x, y, w, h] = dst_rect;
+    let corner = |u: f64, v: f64| {
+        let p = transform_pos(transform, [x + u * w, y + v * h]);
+        PositionFormat { pos: [p[0] as f32, p[1] as f32] }
+    };
+    let positions = [
+        corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0),
+        corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)
+    ];
+    let uvs = [
+        TexCoordsFormat { uv: [0.0, 0.0] }, TexCoordsFormat { uv: [1.0, 0.0] },
+        TexCoordsFormat { uv: [0.0, 1.0] }, TexCoordsFormat { uv: [1.0, 0.0] },
+        TexCoordsFormat { uv: [1.0, 1.0] }, TexCoordsFormat { uv: [0.0, 1.0] }
+    ];
+    (positions, uvs)
+}
+
+// Returns the points of a convex polygon in pixels moved half a pixel inwards
+// and outwards along the miters, see `fill_polygon_aa`.
+// Returns no points if the polygon has no area.
+fn aa_fringe(points: &[[f64; 2]]) -> (Vec<[f64; 2]>, Vec<[f64; 2]>) {
+    let n = points.len();
+    let area: f64 = (0..n).map(|i| {
+        let (a, b) = (points[i], points[(i + 1) % n]);
+        a[0] * b[1] - b[0] * a[1]
+    }).sum();
+    if area == 0.0 {return (vec![], vec![])};
+
+    // The outward unit normals of the edges from each point to the next.
+    let normals: Vec<[f64; 2]> = (0..n).map(|i| {
+        let (a, b) = (points[i], points[(i + 1) % n]);
+        let d = [b[0] - a[0], b[1] - a[1]];
+        let len = (d[0] * d[0] + d[1] * d[1]).sqrt().max(f64::EPSILON);
+        [area.signum() * d[1] / len, -area.signum() * d[0] / len]
+    }).collect();
+    let mut inner = Vec::with_capacity(n);
+    let mut outer = Vec::with_capacity(n);
+    for i in 0..n {
+        let (a, b) = (normals[(i + n - 1) % n], normals[i]);
+        let m = [a[0] + b[0], a[1] + b[1]];
+        let len = (m[0] * m[0] + m[1] * m[1]).sqrt().max(f64::EPSILON);
+        let m = [m[0] / len, m[1] / len];
+        // Miters are limited, so sharp corners don't spike.
+        let s = 0.5 / (m[0] * b[0] + m[1] * b[1]).max(0.25);
+        let p = points[i];
+        inner.push([p[0] - m[0] * s, p[1] - m[1] * s]);
+        outer.push([p[0] + m[0] * s, p[1] + m[1] * s]);
+    }
+    (inner, outer)
+}
+
+// Returns the color and smoothness passed to the vignette shader,
+// with the intensity multiplied into the alpha of the color.
+fn vignette_uniforms(color: [f32; 4], intensity: f32, smoothness: f32) -> ([f32; 4], f32) {
+    let alpha = color[3] * intensity.clamp(0.0, 1.0);
+    // Keeps `smoothstep` defined for a hard edge.
+    ([color[0], color[1], color[2], alpha], smoothness.max(1e-5))
+}
+
+// Converts the scissor of a draw state to a rectangle,
+// restricted to the clip region if there is one.
+fn scissor_rect(
+    scissor: Option<[u32; 4]>,
+    clip_region: Option<[u32; 4]>
+) -> ::draw_state::target::Rect {
+    use draw_state::target::Rect;
+
+    let scissor = match (scissor, clip_region) {
+        (Some(a), Some(b)) => {
+            let x = a[0].max(b[0]);
+            let y = a[1].max(b[1]);
+            let w = (a[0] + a[2]).min(b[0] + b[2]).saturating_sub(x);
+            let h = (a[1] + a[3]).min(b[1] + b[3]).saturating_sub(y);
+            Some([x, y, w, h])
+        }
+        (a, b) => a.or(b),
+    };
+    match scissor {
+        None => Rect { x: 0, y: 0, w: u16::MAX, h: u16::MAX },
+        Some(r) => Rect { x: r[0] as u16, y: r[1] as u16,
+            w: r[2] as u16, h: r[3] as u16 }
+    }
+}
+
+// Fake disabled blending using the same pipeline.
+const NO_BLEND: gfx::state::Blend = gfx::state::Blend {
+    color: gfx::state::BlendChannel {
+        equation: gfx::state::Equation::Add,
+        source: gfx::state::Factor::One,
+        destination: gfx::state::Factor::Zero,
+    },
+    alpha: gfx::state::BlendChannel {
+        equation: gfx::state::Equation::Add,
+        source: gfx::state::Factor::One,
+        destination: gfx::state::Factor::Zero,
+    },
+};
+
+// Alpha blending that also composites alpha correctly, unlike `gfx::preset::blend::ALPHA`,
+// so layers drawn from transparent store premultiplied colors, see `PsoStencil::in_layer`.
+const BLEND_ALPHA: gfx::state::Blend = gfx::state::Blend {
+    color: gfx::state::BlendChannel {
+        equation: gfx::state::Equation::Add,
+        source: gfx::state::Factor::ZeroPlus(gfx::state::BlendValue::SourceAlpha),
+        destination: gfx::state::Factor::OneMinus(gfx::state::BlendValue::SourceAlpha),
+    },
+    alpha: gfx::state::BlendChannel {
+        equation: gfx::state::Equation::Add,
+        source: gfx::state::Factor::One,
+        destination: gfx::state::Factor::OneMinus(gfx::state::BlendValue::SourceAlpha),
+    },
+};
+
+const BLEND_LIGHTER: gfx::state::Blend = gfx::state::Blend {
+    color: gfx::state::BlendChannel {
+        equation: gfx::state::Equation::Add,
+        source: gfx::state::Factor::ZeroPlus(gfx::state::BlendValue::SourceAlpha),
+        destination: gfx::state::Factor::One,
+    },
+    alpha: gfx::state::BlendChannel {
+        equation: gfx::state::Equation::Add,
+        source: gfx::state::Factor::Zero,
+        destination: gfx::state::Factor::One,
+    },
+};
+
+// Returns the blend factors of a blend setting of draw states.
+fn preset_blend(blend: Option<draw_state::Blend>) -> gfx::state::Blend {
+    use gfx::preset::blend;
+
+    match blend {
+        Some(draw_state::Blend::Alpha) => blend::ALPHA,
+        Some(draw_state::Blend::Add) => blend::ADD,
+        Some(draw_state::Blend::Multiply) => blend::MULTIPLY,
+        Some(draw_state::Blend::Invert) => blend::INVERT,
+        Some(draw_state::Blend::Lighter) => BLEND_LIGHTER,
+        None => NO_BLEND,
+    }
+}
+
+// Returns the blend factors drawing premultiplied colors like a blend preset
+// draws straight colors.
+fn premultiplied_blend(blend: gfx::state::Blend) -> gfx::state::Blend {
+    use gfx::preset::blend;
+    use gfx::state::{BlendChannel, BlendValue, Equation, Factor};
+
+    let over = BlendChannel {
+        equation: Equation::Add,
+        source: Factor::One,
+        destination: Factor::OneMinus(BlendValue::SourceAlpha),
+    };
+    if blend == blend::ALPHA || blend == BLEND_ALPHA {
+        gfx::state::Blend { color: over, alpha: over }
+    } else if blend == blend::MULTIPLY {
+        gfx::state::Blend {
+            color: BlendChannel { source: Factor::ZeroPlus(BlendValue::DestColor), ..over },
+            alpha: over,
+        }
+    } else if blend == BLEND_LIGHTER {
+        gfx::state::Blend {
+            color: BlendChannel { destination: Factor::One, ..over },
+            ..blend
+        }
+    } else {
+        // Adding, inverting and replacing already weigh colors by alpha or not at all.
+        blend
+    }
+}
+
+// The buffers updated during a frame, one set per frame in flight.
+struct BufferSet<R: gfx::Resources> {
+    pos: gfx::handle::Buffer<R, PositionFormat>,
+    color: gfx::handle::Buffer<R, ColorFormat>,
+    uv: gfx::handle::Buffer<R, TexCoordsFormat>,
+    indices: gfx::handle::Buffer<R, u16>,
+    instances: gfx::handle::Buffer<R, SpriteInstanceFormat>,
+}
+
+impl<R: gfx::Resources> BufferSet<R> {
+    fn new<F>(factory: &mut F) -> Result<BufferSet<R>, gfx::buffer::CreationError>
         where F: gfx::Factory<R>
     {
-        use gfx::Primitive;
-        use gfx::state::Rasterizer;
-        use gfx::state::{Blend, Stencil};
-        use gfx::traits::*;
-        use shaders::{ colored, textured };
+        use gfx::buffer::Role::Vertex;
+        use gfx::memory::{Bind, Usage};
+
+        let (usage, bind) = (Usage::Dynamic, Bind::empty());
+        Ok(BufferSet {
+            pos: factory.create_buffer(BUFFER_SIZE * CHUNKS, Vertex, usage, bind)?,
+            color: factory.create_buffer(BUFFER_SIZE * CHUNKS, Vertex, usage, bind)?,
+            uv: factory.create_buffer(BUFFER_SIZE, Vertex, usage, bind)?,
+            indices: factory.create_buffer(BUFFER_SIZE, Vertex, usage, bind)?,
+            instances: factory.create_buffer(MAX_INSTANCES, Vertex, usage, bind)?,
+        })
+    }
+}
+
+// A colored PSO with custom blending or stencil state, cached by these settings.
+struct CustomBlendPso<R: gfx::Resources> {
+    blend: gfx::state::Blend,
+    stencil: gfx::state::Stencil,
+    color_mask: gfx::state::ColorMask,
+    pso: PipelineState<R, pipe_colored::Meta>,
+    // The key of the PSO, see `next_pso_keys`.
+    key: usize,
+}
+
+/// A stencil test and write setting with explicit masks,
+/// see `GfxGraphics::tri_list_stencil`.
+///
+/// Unlike the stencil settings of draw states, the masks are not limited
+/// to the bits of the clip plane, so specific bits can be read and written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StencilConfig {
+    /// The comparison of the reference value with the stencil value.
+    pub compare: gfx::state::Comparison,
+    /// The reference value.
+    pub reference: u8,
+    /// The mask of bits compared, applied to both the reference and stencil value.
+    pub read_mask: u8,
+    /// The mask of bits written.
+    pub write_mask: u8,
+    /// The operations when the stencil test fails, when the depth test fails,
+    /// and when both pass.
+    pub ops: (gfx::state::StencilOp, gfx::state::StencilOp, gfx::state::StencilOp),
+    /// Whether to write colors, or only the stencil buffer.
+    pub write_color: bool,
+}
+
+impl StencilConfig {
+    // Returns the stencil state without the reference value, which is not part of the PSO.
+    fn state(&self) -> (gfx::state::Stencil, gfx::state::ColorMask) {
+        use gfx::state::{ColorMask, Stencil, StencilSide};
+
+        let side = StencilSide {
+            fun: self.compare,
+            mask_read: self.read_mask,
+            mask_write: self.write_mask,
+            op_fail: self.ops.0,
+            op_depth_fail: self.ops.1,
+            op_pass: self.ops.2,
+        };
+        let color_mask = if self.write_color {ColorMask::all()} else {ColorMask::empty()};
+        (Stencil { front: side, back: side }, color_mask)
+    }
+}
+
+// The number of blend and stencil settings, see `PsoVariant::index`.
+const PSO_VARIANTS: usize = 60;
+
+// The number of stencil settings, each with a variant for alpha blending in layers.
+const LAYER_VARIANTS: usize = 10;
+
+// Stores one PSO per blend and stencil setting.
+struct PsoStencil<T> {
+    // The PSOs indexed by `PsoVariant::index`, followed by those for layers.
+    variants: Vec<Option<T>>,
+    // The PSO with alpha blending or no blending, used when a variant can not be created.
+    fallback: T,
+    // The variants used in the current frame, as bits indexed by `PsoVariant::index`.
+    used: u64,
+    // Whether draws go to a layer, blending alpha with `BLEND_ALPHA`.
+    in_layer: bool,
+    // The key of the first variant, see `next_pso_keys`.
+    key: usize,
+}
+
+// Returns the first of a number of keys identifying PSOs in `DrawStats`,
+// so switches are counted even when PSOs are moved, e.g. by the custom blend cache.
+fn next_pso_keys(n: usize) -> usize {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Starts at 1, since no PSO was used before the first draw call.
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    NEXT.fetch_add(n, Ordering::Relaxed)
+}
+
+/// A blend and stencil setting of a PSO, see `GfxGraphics::used_variants`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PsoVariant {
+    /// The clip plane, which is `ClipPlane::All` if there is no stencil test.
+    pub plane: ClipPlane,
+    /// The stencil setting. The stencil value is not part of the PSO and is set to `0`.
+    pub stencil: Option<draw_state::Stencil>,
+    /// The blend setting.
+    pub blend: Option<draw_state::Blend>,
+}
+
+impl PsoVariant {
+    // The blend settings in index order.
+    const BLENDS: [Option<draw_state::Blend>; 6] = [
+        Some(draw_state::Blend::Alpha),
+        Some(draw_state::Blend::Add),
+        Some(draw_state::Blend::Multiply),
+        Some(draw_state::Blend::Invert),
+        Some(draw_state::Blend::Lighter),
+        None,
+    ];
+
+    // Returns a unique index below 64, for tracking used variants in bits.
+    fn index(
+        plane: ClipPlane,
+        stencil: Option<draw_state::Stencil>,
+        blend: Option<draw_state::Blend>
+    ) -> u32 {
+        use graphics::draw_state::Stencil;
+
+        let plane = match plane {
+            ClipPlane::All => 0,
+            ClipPlane::Lower => 1,
+            ClipPlane::Upper => 2,
+        };
+        let stencil = match stencil {
+            None => 0,
+            Some(Stencil::Clip(_)) => 1 + plane * 3,
+            Some(Stencil::Inside(_)) => 2 + plane * 3,
+            Some(Stencil::Outside(_)) => 3 + plane * 3,
+        };
+        let blend = PsoVariant::BLENDS.iter().position(|&b| b == blend).unwrap() as u32;
+        stencil * 6 + blend
+    }
+
+    // Returns the variant of an index below `PSO_VARIANTS`.
+    fn from_index(i: usize) -> PsoVariant {
+        use graphics::draw_state::Stencil;
+
+        let planes = [ClipPlane::All, ClipPlane::Lower, ClipPlane::Upper];
+        let (stencil, blend) = (i / 6, PsoVariant::BLENDS[i % 6]);
+        let (plane, stencil) = match stencil {
+            0 => (ClipPlane::All, None),
+            s => (planes[(s - 1) / 3], Some(match (s - 1) % 3 {
+                0 => Stencil::Clip(0),
+                1 => Stencil::Inside(0),
+                _ => Stencil::Outside(0),
+            })),
+        };
+        PsoVariant { plane, stencil, blend }
+    }
+
+    // Returns the variants of the bits set in `used`.
+    fn from_bits(used: u64) -> Vec<PsoVariant> {
+        (0..PSO_VARIANTS).filter(|&i| used & (1 << i) != 0).map(PsoVariant::from_index).collect()
+    }
+}
+
+/// The state a draw resolves to, see `GfxGraphics::resolve_draw_state`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ResolvedState {
+    /// The PSO variant.
+    pub variant: PsoVariant,
+    /// The stencil reference value, moved into the bits of the clip plane.
+    pub stencil_ref: u8,
+    /// The scissor rectangle in pixels, intersected with the clip region.
+    pub scissor: ::draw_state::target::Rect,
+}
+
+impl fmt::Display for PsoVariant {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        use graphics::draw_state::Stencil;
+
+        let stencil = match self.stencil {
+            None => "none",
+            Some(Stencil::Clip(_)) => "clip",
+            Some(Stencil::Inside(_)) => "inside",
+            Some(Stencil::Outside(_)) => "outside",
+        };
+        match self.blend {
+            Some(blend) => write!(w, "blend {:?}, ", blend)?,
+            None => write!(w, "no blend, ")?,
+        }
+        write!(w, "stencil {} ({:?} plane)", stencil, self.plane)
+    }
+}
+
+// Returns the stencil state and color mask for a stencil setting,
+// using the stencil bits as read and write mask.
+fn stencil_state(
+    bits: u8,
+    stencil: Option<draw_state::Stencil>
+) -> (gfx::state::Stencil, gfx::state::ColorMask) {
+    use gfx::state::{ColorMask, Comparison, Stencil, StencilOp};
+
+    let keep = (StencilOp::Keep, StencilOp::Keep, StencilOp::Keep);
+    match stencil {
+        None => (Stencil::new(Comparison::Always, 0, keep), ColorMask::all()),
+        Some(draw_state::Stencil::Clip(_)) => (Stencil::new(Comparison::Never, bits,
+            (StencilOp::Replace, StencilOp::Keep, StencilOp::Keep)), ColorMask::empty()),
+        Some(draw_state::Stencil::Inside(_)) =>
+            (Stencil::new(Comparison::Equal, bits, keep), ColorMask::all()),
+        Some(draw_state::Stencil::Outside(_)) =>
+            (Stencil::new(Comparison::NotEqual, bits, keep), ColorMask::all()),
+    }
+}
+
+// Creates the PSO of a variant with the blend factors to use.
+fn create_variant<T, F>(create: &mut F, variant: PsoVariant, blend: gfx::state::Blend)
+    -> Result<T, String>
+    where F: FnMut(gfx::state::Blend, gfx::state::Stencil, gfx::state::ColorMask)
+        -> Result<T, String>
+{
+    let bits = if variant.stencil.is_some() {variant.plane.bits()} else {0};
+    let (stencil, mask) = stencil_state(bits, variant.stencil);
+    create(blend, stencil, mask)
+}
+
+impl<T: Clone> PsoStencil<T> {
+    // Creates the PSOs of all variants.
+    fn new<Fact, F>(factory: &mut Fact, name: &'static str, mut f: F)
+        -> Result<PsoStencil<T>, Gfx2dError>
+        where F: FnMut(
+            &mut Fact,
+            gfx::state::Blend,
+            gfx::state::Stencil,
+            gfx::state::ColorMask
+        ) -> Result<T, String>
+    {
+        let mut create = |blend, stencil, mask| f(factory, blend, stencil, mask);
+        let mut pso = PsoStencil::with_fallback(name, &mut create)?;
+        for i in 0..PSO_VARIANTS {
+            pso.ensure(PsoVariant::from_index(i), &mut create);
+        }
+        for i in 0..LAYER_VARIANTS {
+            pso.ensure_layer(i, &mut create);
+        }
+        Ok(pso)
+    }
+
+    // Creates the fallback PSO, with alpha blending or else without blending.
+    fn with_fallback<F>(name: &'static str, create: &mut F) -> Result<PsoStencil<T>, Gfx2dError>
+        where F: FnMut(gfx::state::Blend, gfx::state::Stencil, gfx::state::ColorMask)
+            -> Result<T, String>
+    {
+        let mut variants = vec![None; PSO_VARIANTS + LAYER_VARIANTS];
+        let alpha = PsoVariant {
+            plane: ClipPlane::All,
+            stencil: None,
+            blend: Some(draw_state::Blend::Alpha),
+        };
+        let none = PsoVariant { blend: None, ..alpha };
+        let fallback = create_variant(create, alpha, preset_blend(alpha.blend))
+            .map(|pso| (alpha, pso))
+            .or_else(|err| {
+                warn!("Failed to create PSO with {}, using a fallback: {}", alpha, err);
+                create_variant(create, none, NO_BLEND).map(|pso| (none, pso))
+            })
+            .map(|(variant, pso)| {
+                variants[PsoVariant::index(variant.plane, None, variant.blend) as usize] =
+                    Some(pso.clone());
+                pso
+            })
+            .map_err(|message| Gfx2dError::Pipeline { name, message })?;
+        Ok(PsoStencil {
+            variants,
+            fallback,
+            used: 0,
+            in_layer: false,
+            key: next_pso_keys(PSO_VARIANTS + LAYER_VARIANTS),
+        })
+    }
+
+    // Creates a variant if it is missing. A variant that fails, e.g. on a driver
+    // rejecting a state combination, falls back to alpha blending with the same
+    // stencil test, or else to the fallback PSO, with a warning.
+    fn ensure<F>(&mut self, variant: PsoVariant, create: &mut F)
+        where F: FnMut(gfx::state::Blend, gfx::state::Stencil, gfx::state::ColorMask)
+            -> Result<T, String>
+    {
+        let index = PsoVariant::index(variant.plane, variant.stencil, variant.blend) as usize;
+        if self.variants[index].is_some() {return};
+        // Clip writes no color, so one PSO per clip plane is shared by all blend settings.
+        let clip = PsoVariant { blend: Some(draw_state::Blend::Alpha), ..variant };
+        if let Some(draw_state::Stencil::Clip(_)) = variant.stencil {
+            if variant.blend != clip.blend {
+                self.ensure(clip, create);
+                let clip = PsoVariant::index(clip.plane, clip.stencil, clip.blend);
+                self.variants[index] = self.variants[clip as usize].clone();
+                return;
+            }
+        }
+        let pso = match create_variant(create, variant, preset_blend(variant.blend)) {
+            Ok(pso) => pso,
+            Err(err) => {
+                warn!("Failed to create PSO with {}, using a fallback: {}", variant, err);
+                let alpha = PsoVariant { blend: Some(draw_state::Blend::Alpha), ..variant };
+                if variant.blend != alpha.blend {
+                    self.ensure(alpha, create);
+                    let alpha = PsoVariant::index(alpha.plane, alpha.stencil, alpha.blend);
+                    self.variants[alpha as usize].clone().unwrap()
+                } else {
+                    self.fallback.clone()
+                }
+            }
+        };
+        self.variants[index] = Some(pso);
+    }
+
+    // Creates the variant for alpha blending in layers of a stencil setting,
+    // falling back to the alpha variant with the same stencil test with a warning.
+    // Must be called after the other variants were created.
+    fn ensure_layer<F>(&mut self, stencil: usize, create: &mut F)
+        where F: FnMut(gfx::state::Blend, gfx::state::Stencil, gfx::state::ColorMask)
+            -> Result<T, String>
+    {
+        let alpha = self.variants[stencil * 6].clone();
+        let variant = PsoVariant::from_index(stencil * 6);
+        let pso = match variant.stencil {
+            // Clip writes no color, so it shares the PSO of the clip plane.
+            Some(draw_state::Stencil::Clip(_)) => alpha,
+            _ => match create_variant(create, variant, BLEND_ALPHA) {
+                Ok(pso) => Some(pso),
+                Err(err) => {
+                    warn!("Failed to create PSO with {} for layers, using a fallback: {}",
+                        variant, err);
+                    alpha
+                }
+            }
+        };
+        self.variants[PSO_VARIANTS + stencil] = pso;
+    }
+}
+
+impl<T> PsoStencil<T> {
+    // Returns a PSO, stencil reference and PSO key given a clip plane, stencil and blend setting.
+    fn stencil_blend(
+        &mut self,
+        plane: ClipPlane,
+        stencil: Option<draw_state::Stencil>,
+        blend: Option<draw_state::Blend>
+    ) -> (&mut T, u8, usize) {
+        use graphics::draw_state::Stencil;
+
+        let index = PsoVariant::index(plane, stencil, blend) as usize;
+        self.used |= 1 << index;
+        let index = if self.in_layer && blend == Some(draw_state::Blend::Alpha) {
+            PSO_VARIANTS + index / 6
+        } else {
+            index
+        };
+        let stencil_ref = match stencil {
+            None => 0,
+            Some(Stencil::Clip(val)) | Some(Stencil::Inside(val)) |
+            Some(Stencil::Outside(val)) => plane.shift(val),
+        };
+        let key = self.key + index;
+        let pso = match self.variants[index] {
+            Some(ref mut pso) => pso,
+            None => &mut self.fallback,
+        };
+        (pso, stencil_ref, key)
+    }
+}
+
+#[cfg(feature = "debug")]
+impl<R: gfx::Resources, M> PsoStencil<PipelineState<R, M>> {
+    // Lists the raw handles of the created PSOs with their stencil and blend setting.
+    fn debug_pipelines(&self, program: &'static str, list: &mut Vec<DebugPipeline<R>>) {
+        for (i, pso) in self.variants.iter().enumerate() {
+            if let Some(ref pso) = *pso {
+                // The variants for layers are listed like those with alpha blending.
+                let i = if i < PSO_VARIANTS {i} else {(i - PSO_VARIANTS) * 6};
+                let variant = PsoVariant::from_index(i);
+                list.push(DebugPipeline {
+                    program,
+                    plane: variant.plane,
+                    stencil: variant.stencil,
+                    blend: variant.blend,
+                    handle: pso.get_handle().clone(),
+                });
+            }
+        }
+    }
+}
+
+/// A pipeline defined by the app, with one PSO per blend and stencil setting.
+///
+/// Created with `Gfx2d::custom_pipeline` and drawn with `GfxGraphics::draw_custom`.
+pub struct CustomPipeline<R: gfx::Resources, M>(PsoStencil<PipelineState<R, M>>);
+
+/// The render targets and scissor for the data of a custom pipeline,
+/// see `GfxGraphics::draw_custom`.
+pub struct CustomTargets<R: gfx::Resources> {
+    /// The color target, for a `gfx::RawRenderTarget`.
+    pub color: gfx::handle::RawRenderTargetView<R>,
+    /// The stencil target and stencil reference, for a `gfx::StencilTarget`.
+    pub stencil: (gfx::handle::DepthStencilView<R, DepthStencil>, (u8, u8)),
+    /// The scissor rectangle, for a `gfx::Scissor`.
+    pub scissor: gfx::Rect,
+}
+
+/// A PSO handle with the settings it is used for.
+///
+/// For tooling only, e.g. to set debug labels.
+#[cfg(feature = "debug")]
+#[derive(Clone, Debug)]
+pub struct DebugPipeline<R: gfx::Resources> {
+    /// The name of the shader program, e.g. `"colored"`.
+    pub program: &'static str,
+    /// The clip plane, which is `ClipPlane::All` if there is no stencil test.
+    pub plane: ClipPlane,
+    /// The stencil setting. The stencil value is not part of the PSO and is set to `0`.
+    pub stencil: Option<draw_state::Stencil>,
+    /// The blend setting.
+    pub blend: Option<draw_state::Blend>,
+    /// The raw PSO handle.
+    pub handle: gfx::handle::RawPipelineState<R>,
+}
+
+/// The vertex buffers used by `Gfx2d`.
+///
+/// For tooling only, e.g. to set debug labels.
+#[cfg(feature = "debug")]
+#[derive(Clone, Debug)]
+pub struct DebugBuffers<R: gfx::Resources> {
+    /// Vertex positions.
+    pub pos: gfx::handle::RawBuffer<R>,
+    /// Vertex colors.
+    pub color: gfx::handle::RawBuffer<R>,
+    /// Texture coordinates.
+    pub uv: gfx::handle::RawBuffer<R>,
+    /// Indices.
+    pub indices: gfx::handle::RawBuffer<R>,
+}
+
+/// Statistics about the work done during a `Gfx2d::draw` call.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    /// The number of draw calls issued.
+    pub draw_calls: usize,
+    /// The number of vertices drawn.
+    pub vertices: usize,
+    /// The wall-clock time spent on the CPU in the call,
+    /// including the closure, but not the time the GPU takes to draw.
+    ///
+    /// Set when the frame ends. Requires the `cpu_time` feature.
+    #[cfg(feature = "cpu_time")]
+    pub cpu_time: ::std::time::Duration,
+    /// The number of times a draw call used a different PSO than the previous one,
+    /// including the first draw call.
+    ///
+    /// Requires the `debug` feature, see `Gfx2d::set_pso_switch_warn_threshold`.
+    #[cfg(feature = "debug")]
+    pub pso_switches: usize,
+    // The key of the PSO of the previous draw call, see `next_pso_keys`.
+    #[cfg(feature = "debug")]
+    last_pso: usize,
+}
+
+impl DrawStats {
+    fn record(&mut self, pso: usize, vertices: usize) {
+        self.draw_calls += 1;
+        self.vertices += vertices;
+        #[cfg(feature = "debug")]
+        {
+            if pso != self.last_pso {
+                self.pso_switches += 1;
+                self.last_pso = pso;
+            }
+        }
+        #[cfg(not(feature = "debug"))]
+        let _ = pso;
+    }
+}
+
+/// A callback invoked at the start or end of `Gfx2d::draw`.
+pub type FrameCallback = Box<dyn FnMut(&DrawStats) + Send>;
+
+// Calls a frame callback, catching and logging panics so they can't break the render loop.
+fn call_frame_callback(callback: &mut Option<FrameCallback>, stats: &DrawStats, name: &str) {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    if let Some(ref mut callback) = *callback {
+        if catch_unwind(AssertUnwindSafe(|| callback(stats))).is_err() {
+            error!("Frame callback `{}` panicked", name);
+        }
+    }
+}
+
+/// A closure drawing one part of a frame, see `Gfx2d::draw_many`.
+pub type DrawFn<'a, R, C> = dyn FnMut(Context, &mut GfxGraphics<R, C>) + 'a;
+
+/// The data used for drawing 2D graphics.
+///
+/// Stores buffers and PSO objects needed for rendering 2D graphics.
+pub struct Gfx2d<R: gfx::Resources> {
+    // The offset in vertices for colored rendering.
+    colored_offset: usize,
+    // The current draw state for colored rendering.
+    colored_draw_state: DrawState,
+    buffer_pos: gfx::handle::Buffer<R, PositionFormat>,
+    buffer_color: gfx::handle::Buffer<R, ColorFormat>,
+    buffer_uv: gfx::handle::Buffer<R, TexCoordsFormat>,
+    buffer_indices: gfx::handle::Buffer<R, u16>,
+    colored: PsoStencil<PipelineState<R, pipe_colored::Meta>>,
+    textured: PsoStencil<PipelineState<R, pipe_textured::Meta>>,
+    colored_wireframe: PsoStencil<PipelineState<R, pipe_colored::Meta>>,
+    colored_dither: PsoStencil<PipelineState<R, pipe_colored::Meta>>,
+    textured_wireframe: PsoStencil<PipelineState<R, pipe_textured::Meta>>,
+    textured_lod: PsoStencil<PipelineState<R, pipe_textured_lod::Meta>>,
+    textured_color: PsoStencil<PipelineState<R, pipe_textured_color::Meta>>,
+    color_key: PsoStencil<PipelineState<R, pipe_color_key::Meta>>,
+    chroma_key: PsoStencil<PipelineState<R, pipe_chroma_key::Meta>>,
+    mul_add: PsoStencil<PipelineState<R, pipe_mul_add::Meta>>,
+    outline: PsoStencil<PipelineState<R, pipe_outline::Meta>>,
+    sdf: PsoStencil<PipelineState<R, pipe_sdf::Meta>>,
+    pattern: PsoStencil<PipelineState<R, pipe_pattern::Meta>>,
+    soft_point: PsoStencil<PipelineState<R, pipe_soft_point::Meta>>,
+    masked: PsoStencil<PipelineState<R, pipe_masked::Meta>>,
+    aa_line: PsoStencil<PipelineState<R, pipe_aa_line::Meta>>,
+    checkerboard: PsoStencil<PipelineState<R, pipe_checkerboard::Meta>>,
+    gradient: PsoStencil<PipelineState<R, pipe_gradient::Meta>>,
+    grain: PsoStencil<PipelineState<R, pipe_grain::Meta>>,
+    vignette: PsoStencil<PipelineState<R, pipe_vignette::Meta>>,
+    dissolve: PsoStencil<PipelineState<R, pipe_dissolve::Meta>>,
+    downsample: PsoStencil<PipelineState<R, pipe_downsample::Meta>>,
+    // The depth-stencil buffer of the last size downsampled to.
+    downsample_stencil: Option<gfx::handle::DepthStencilView<R, DepthStencil>>,
+    rounded_image: PsoStencil<PipelineState<R, pipe_rounded_image::Meta>>,
+    instanced_sprite: PsoStencil<PipelineState<R, pipe_instanced_sprite::Meta>>,
+    mesh: PsoStencil<PipelineState<R, pipe_mesh::Meta>>,
+    mesh_colored: PsoStencil<PipelineState<R, pipe_mesh_colored::Meta>>,
+    depth_colored: PsoStencil<PipelineState<R, pipe_depth_colored::Meta>>,
+    depth_textured: PsoStencil<PipelineState<R, pipe_depth_textured::Meta>>,
+    // Textured PSOs for premultiplied colors, see `with_layer`.
+    layer: PsoStencil<PipelineState<R, pipe_textured::Meta>>,
+    // Whether draws go to a layer, see `set_in_layer`.
+    in_layer: bool,
+    // The program of colored PSOs with custom blending.
+    colored_program: gfx::handle::Program<R>,
+    // PSOs with custom blending, the most recently used first.
+    custom_blends: Vec<CustomBlendPso<R>>,
+    // Whether colored triangles use the first custom blend PSO.
+    custom_blend: bool,
+    // The stencil reference value of the first custom blend PSO, if set by a `StencilConfig`.
+    custom_stencil_ref: Option<u8>,
+    // Colored PSOs inverting stencil values, for the clip planes in declaration order.
+    colored_invert: Vec<PipelineState<R, pipe_colored::Meta>>,
+    // The key of the first PSO of `colored_invert`, see `next_pso_keys`.
+    colored_invert_key: usize,
+    // Whether colored triangles use the PSO inverting stencil values.
+    stencil_invert: bool,
+    // The unit quad and the instances drawn by `draw_instanced_sprites`.
+    buffer_quad: gfx::handle::Buffer<R, PositionFormat>,
+    buffer_instances: gfx::handle::Buffer<R, SpriteInstanceFormat>,
+    // The buffers for each frame in flight, used in turn by the buffers above.
+    buffer_ring: Vec<BufferSet<R>>,
+    // The number of frames drawn, to pick the buffers of the next frame.
+    frame_index: usize,
+    // A triangle covering the viewport, for full-screen passes.
+    buffer_full_screen: gfx::handle::Buffer<R, FullScreenVertex>,
+    // Samplers that repeat textures, for nearest and linear filtering.
+    sampler_tile_nearest: gfx::handle::Sampler<R>,
+    sampler_tile_linear: gfx::handle::Sampler<R>,
+    // Statistics of the current or last frame.
+    stats: DrawStats,
+    // The start of the current frame.
+    #[cfg(feature = "cpu_time")]
+    frame_start: ::std::time::Instant,
+    // Warns when a frame switches PSOs more often, see `set_pso_switch_warn_threshold`.
+    #[cfg(feature = "debug")]
+    pso_switch_warn_threshold: Option<usize>,
+    on_frame_begin: Option<FrameCallback>,
+    on_frame_end: Option<FrameCallback>,
+    // Restricts drawing to a region of the target, see `draw_into_atlas_region`.
+    clip_region: Option<[u32; 4]>,
+    // Scale from logical to physical pixels for the drawing helpers.
+    dpi_scale: f32,
+    // Whether texture tint colors are linear instead of sRGB.
+    linear_tint: bool,
+    origin: Origin,
+    // Whether colored triangles are dithered.
+    dither: bool,
+    // The maximum distance in pixels between curves and their segments.
+    curve_tolerance: f32,
+    // Multiplied with the colors of all draws, in linear space.
+    global_tint: [f32; 4],
+    // The format of the render targets.
+    format: gfx::format::Format,
+    // The OpenGL version used to pick shaders, kept for `recreate`.
+    opengl: OpenGL,
+}
+
+impl<R: gfx::Resources> Gfx2d<R> {
+    /// Creates a new Gfx2d object.
+    ///
+    /// Panics if a pipeline can not be created, see `try_new`.
+    pub fn new<F>(opengl: OpenGL, factory: &mut F) -> Self
+        where F: gfx::Factory<R>
+    {
+        Gfx2d::try_new(opengl, factory).expect("Could not create Gfx2d")
+    }
+
+    /// Creates a new Gfx2d object, or returns the pipeline that could not be created.
+    ///
+    /// A pipeline variant that fails falls back to another variant with a warning.
+    pub fn try_new<F>(opengl: OpenGL, factory: &mut F) -> Result<Self, Gfx2dError>
+        where F: gfx::Factory<R>
+    {
+        use gfx::format::Formatted;
+
+        Gfx2d::with_format(opengl, factory, Srgba8::get_format())
+    }
+
+    /// Creates a new Gfx2d object drawing linear color to `Rgba16F` targets.
+    ///
+    /// Use `draw_linear` to render, e.g. for HDR rendering with tonemapping by the app.
+    /// Colors are converted from sRGB and blended in linear space like for sRGB targets,
+    /// which is physically correct, but the output is not encoded as sRGB.
+    /// Panics like `new` if a pipeline can not be created.
+    pub fn new_linear<F>(opengl: OpenGL, factory: &mut F) -> Self
+        where F: gfx::Factory<R>
+    {
+        use gfx::format::Formatted;
+
+        Gfx2d::with_format(opengl, factory, Rgba16F::get_format())
+            .expect("Could not create Gfx2d")
+    }
+
+    fn with_format<F>(opengl: OpenGL, factory: &mut F, format: gfx::format::Format)
+        -> Result<Self, Gfx2dError>
+        where F: gfx::Factory<R>
+    {
+        use gfx::Primitive;
+        use gfx::state::{RasterMethod, Rasterizer};
+        use gfx::state::{Blend, Stencil};
+        use gfx::traits::*;
+        use shaders::{ colored, textured };
+
+        let glsl = opengl.to_glsl();
+
+        let colored_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, colored::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, colored::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, colored::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, colored::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+            ).unwrap();
+
+        let colored_dither_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, colored::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, colored::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::dither::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::dither::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+            ).unwrap();
+
+        let colored_pipeline = |factory: &mut F,
+                                     program: &gfx::handle::Program<R>,
+                                     rasterizer: Rasterizer,
+                                     blend_preset: Blend,
+                                     stencil: Stencil,
+                                     color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_colored::Meta>, String> {
+            factory.create_pipeline_from_program(
+                program,
+                Primitive::TriangleList,
+                rasterizer,
+                pipe_colored::Init {
+                    pos: (),
+                    color: (),
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        // Draws the outlines of triangles, for debugging meshes.
+        let wireframe = Rasterizer {
+            method: RasterMethod::Line(1),
+            ..Rasterizer::new_fill()
+        };
+
+        let colored = PsoStencil::new(factory, "colored", |factory, blend, stencil, mask|
+            colored_pipeline(factory, &colored_program, Rasterizer::new_fill(),
+                blend, stencil, mask))?;
+        let colored_wireframe = PsoStencil::new(factory, "colored_wireframe",
+            |factory, blend, stencil, mask|
+                colored_pipeline(factory, &colored_program, wireframe, blend, stencil, mask))?;
+        let colored_dither = PsoStencil::new(factory, "colored_dither",
+            |factory, blend, stencil, mask|
+                colored_pipeline(factory, &colored_dither_program, Rasterizer::new_fill(),
+                    blend, stencil, mask))?;
+        // Toggles the stencil value `1` of each clip plane, see `clip_even_odd`.
+        let colored_invert = [ClipPlane::All, ClipPlane::Lower, ClipPlane::Upper].iter()
+            .map(|&plane| {
+                use gfx::state::{ColorMask, Comparison, StencilOp};
+
+                let stencil = Stencil::new(Comparison::Never, plane.shift(1),
+                    (StencilOp::Invert, StencilOp::Keep, StencilOp::Keep));
+                colored_pipeline(factory, &colored_program, Rasterizer::new_fill(),
+                    gfx::preset::blend::ALPHA, stencil, ColorMask::empty())
+            }).collect::<Result<_, _>>()
+            .map_err(|message| Gfx2dError::Pipeline { name: "colored_invert", message })?;
+
+        let textured_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, textured::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let textured_pipeline = |factory: &mut F,
+                                      rasterizer: Rasterizer,
+                                      blend_preset: Blend,
+                                      stencil: Stencil,
+                                      color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_textured::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &textured_program,
+                Primitive::TriangleList,
+                rasterizer,
+                pipe_textured::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let textured = PsoStencil::new(factory, "textured", |factory, blend, stencil, mask|
+            textured_pipeline(factory, Rasterizer::new_fill(), blend, stencil, mask))?;
+        let textured_wireframe = PsoStencil::new(factory, "textured_wireframe",
+            |factory, blend, stencil, mask|
+                textured_pipeline(factory, wireframe, blend, stencil, mask))?;
+        let layer = PsoStencil::new(factory, "layer", |factory, blend, stencil, mask|
+            textured_pipeline(factory, Rasterizer::new_fill(), premultiplied_blend(blend),
+                stencil, mask))?;
+
+        let textured_lod_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::textured_lod::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::textured_lod::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let textured_lod_pipeline = |factory: &mut F,
+                                          blend_preset: Blend,
+                                          stencil: Stencil,
+                                          color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_textured_lod::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &textured_lod_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_textured_lod::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    level: "level",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let textured_lod = PsoStencil::new(factory, "textured_lod", textured_lod_pipeline)?;
+
+        let textured_color_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::textured_color::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::textured_color::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::textured_color::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::textured_color::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let textured_color_pipeline = |factory: &mut F,
+                                            blend_preset: Blend,
+                                            stencil: Stencil,
+                                            color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_textured_color::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &textured_color_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_textured_color::Init {
+                    pos: (),
+                    uv: (),
+                    color: (),
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let textured_color = PsoStencil::new(
+            factory, "textured_color", textured_color_pipeline)?;
+
+        let color_key_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::color_key::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::color_key::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let color_key_pipeline = |factory: &mut F,
+                                       blend_preset: Blend,
+                                       stencil: Stencil,
+                                       color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_color_key::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &color_key_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_color_key::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    key: "key",
+                    tolerance: "tolerance",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let color_key = PsoStencil::new(factory, "color_key", color_key_pipeline)?;
+
+        let chroma_key_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::chroma_key::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::chroma_key::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let chroma_key_pipeline = |factory: &mut F,
+                                        blend_preset: Blend,
+                                        stencil: Stencil,
+                                        color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_chroma_key::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &chroma_key_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_chroma_key::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    key_dir: "key_dir",
+                    tolerance: "tolerance",
+                    softness: "softness",
+                    despill: "despill",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let chroma_key = PsoStencil::new(factory, "chroma_key", chroma_key_pipeline)?;
+
+        let mul_add_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::mul_add::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::mul_add::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let mul_add_pipeline = |factory: &mut F,
+                                     blend_preset: Blend,
+                                     stencil: Stencil,
+                                     color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_mul_add::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &mul_add_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_mul_add::Init {
+                    pos: (),
+                    uv: (),
+                    mul: "mul",
+                    add: "add",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let mul_add = PsoStencil::new(factory, "mul_add", mul_add_pipeline)?;
+
+        let outline_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::outline::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::outline::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let outline_pipeline = |factory: &mut F,
+                                     blend_preset: Blend,
+                                     stencil: Stencil,
+                                     color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_outline::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &outline_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_outline::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    outline_color: "outline_color",
+                    texel: "texel",
+                    width: "width",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let outline = PsoStencil::new(factory, "outline", outline_pipeline)?;
+
+        let sdf_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::sdf::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::sdf::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let sdf_pipeline = |factory: &mut F,
+                                 blend_preset: Blend,
+                                 stencil: Stencil,
+                                 color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_sdf::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &sdf_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_sdf::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    outline_color: "outline_color",
+                    outline_width: "outline_width",
+                    glow_color: "glow_color",
+                    glow_width: "glow_width",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let sdf = PsoStencil::new(factory, "sdf", sdf_pipeline)?;
+
+        let pattern_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::pattern::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::pattern::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, textured::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let pattern_pipeline = |factory: &mut F,
+                                     blend_preset: Blend,
+                                     stencil: Stencil,
+                                     color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_pattern::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &pattern_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_pattern::Init {
+                    pos: (),
+                    color: "color",
+                    pattern_transform: "pattern_transform",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let pattern = PsoStencil::new(factory, "pattern", pattern_pipeline)?;
+
+        let soft_point_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::soft_point::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::soft_point::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let soft_point_pipeline = |factory: &mut F,
+                                        blend_preset: Blend,
+                                        stencil: Stencil,
+                                        color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_soft_point::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &soft_point_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_soft_point::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let soft_point = PsoStencil::new(factory, "soft_point", soft_point_pipeline)?;
+
+        let masked_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::masked::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::masked::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let masked_pipeline = |factory: &mut F,
+                                    blend_preset: Blend,
+                                    stencil: Stencil,
+                                    color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_masked::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &masked_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_masked::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    texture: "s_texture",
+                    mask: "s_mask",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let masked = PsoStencil::new(factory, "masked", masked_pipeline)?;
+
+        let aa_line_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::aa_line::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::aa_line::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let aa_line_pipeline = |factory: &mut F,
+                                     blend_preset: Blend,
+                                     stencil: Stencil,
+                                     color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_aa_line::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &aa_line_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_aa_line::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    half_width: "half_width",
+                    alpha_scale: "alpha_scale",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let aa_line = PsoStencil::new(factory, "aa_line", aa_line_pipeline)?;
+
+        let checkerboard_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::checkerboard::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::checkerboard::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::checkerboard::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::checkerboard::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let checkerboard_pipeline = |factory: &mut F,
+                                          blend_preset: Blend,
+                                          stencil: Stencil,
+                                          color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_checkerboard::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &checkerboard_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_checkerboard::Init {
+                    pos: (),
+                    color_a: "color_a",
+                    color_b: "color_b",
+                    cell_size: "cell_size",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let checkerboard = PsoStencil::new(factory, "checkerboard", checkerboard_pipeline)?;
+
+        let gradient_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::gradient::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::gradient::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let gradient_pipeline = |factory: &mut F,
+                                      blend_preset: Blend,
+                                      stencil: Stencil,
+                                      color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_gradient::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &gradient_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_gradient::Init {
+                    pos: (),
+                    uv: (),
+                    color_upper_left: "color_upper_left",
+                    color_upper_right: "color_upper_right",
+                    color_lower_left: "color_lower_left",
+                    color_lower_right: "color_lower_right",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let gradient = PsoStencil::new(factory, "gradient", gradient_pipeline)?;
+
+        let grain_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::checkerboard::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::checkerboard::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::grain::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::grain::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let grain_pipeline = |factory: &mut F,
+                                   blend_preset: Blend,
+                                   stencil: Stencil,
+                                   color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_grain::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &grain_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_grain::Init {
+                    pos: (),
+                    intensity: "intensity",
+                    seed: "seed",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let grain = PsoStencil::new(factory, "grain", grain_pipeline)?;
+
+        let vignette_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::checkerboard::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::checkerboard::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::vignette::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::vignette::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let vignette_pipeline = |factory: &mut F,
+                                      blend_preset: Blend,
+                                      stencil: Stencil,
+                                      color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_vignette::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &vignette_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_vignette::Init {
+                    pos: (),
+                    color: "color",
+                    size: "size",
+                    radius: "radius",
+                    smoothness: "smoothness",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let vignette = PsoStencil::new(factory, "vignette", vignette_pipeline)?;
+
+        let dissolve_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::dissolve::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::dissolve::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::dissolve::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::dissolve::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let dissolve_pipeline = |factory: &mut F,
+                                      blend_preset: Blend,
+                                      stencil: Stencil,
+                                      color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_dissolve::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &dissolve_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_dissolve::Init {
+                    pos: (),
+                    color: "color",
+                    edge_color: "edge_color",
+                    threshold: "threshold",
+                    edge_width: "edge_width",
+                    texture: "s_texture",
+                    noise: "s_noise",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let dissolve = PsoStencil::new(factory, "dissolve", dissolve_pipeline)?;
+
+        let downsample_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::checkerboard::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::checkerboard::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::downsample::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::downsample::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let downsample_pipeline = |factory: &mut F,
+                                        blend_preset: Blend,
+                                        stencil: Stencil,
+                                        color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_downsample::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &downsample_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_downsample::Init {
+                    pos: (),
+                    factor: "factor",
+                    src_size: "src_size",
+                    texture: "s_texture",
+                    // Downsampled textures are always `Srgba8`, whatever the format of targets.
+                    blend_target: ("o_Color", color_mask, blend_preset),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let downsample = PsoStencil::new(factory, "downsample", downsample_pipeline)?;
+
+        let rounded_image_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::rounded_image::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::rounded_image::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let rounded_image_pipeline = |factory: &mut F,
+                                           blend_preset: Blend,
+                                           stencil: Stencil,
+                                           color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_rounded_image::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &rounded_image_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_rounded_image::Init {
+                    pos: (),
+                    uv: (),
+                    color: "color",
+                    size: "size",
+                    radii: "radii",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let rounded_image = PsoStencil::new(factory, "rounded_image", rounded_image_pipeline)?;
+
+        let instanced_sprite_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::instanced_sprite::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::instanced_sprite::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::instanced_sprite::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::instanced_sprite::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let instanced_sprite_pipeline = |factory: &mut F,
+                                              blend_preset: Blend,
+                                              stencil: Stencil,
+                                              color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_instanced_sprite::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &instanced_sprite_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_instanced_sprite::Init {
+                    pos: (),
+                    instance: (),
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let instanced_sprite =
+            PsoStencil::new(factory, "instanced_sprite", instanced_sprite_pipeline)?;
+
+        let mesh_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::mesh::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::mesh::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, textured::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, textured::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let mesh_pipeline = |factory: &mut F,
+                                  blend_preset: Blend,
+                                  stencil: Stencil,
+                                  color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_mesh::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &mesh_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_mesh::Init {
+                    vbuf: (),
+                    transform_x: "transform_x",
+                    transform_y: "transform_y",
+                    color: "color",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let mesh = PsoStencil::new(factory, "mesh", mesh_pipeline)?;
+
+        let mesh_colored_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::mesh_colored::VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::mesh_colored::VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::mesh_colored::FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::mesh_colored::FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let mesh_colored_pipeline = |factory: &mut F,
+                                          blend_preset: Blend,
+                                          stencil: Stencil,
+                                          color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_mesh_colored::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &mesh_colored_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_mesh_colored::Init {
+                    pos: (),
+                    color: (),
+                    transform_x: "transform_x",
+                    transform_y: "transform_y",
+                    tint: "tint",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    stencil_target: stencil,
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let mesh_colored = PsoStencil::new(factory, "mesh_colored", mesh_colored_pipeline)?;
+
+        let depth_colored_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::depth::COLORED_VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::depth::COLORED_VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::depth::COLORED_FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::depth::COLORED_FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let depth_colored_pipeline = |factory: &mut F,
+                                           blend_preset: Blend,
+                                           stencil: Stencil,
+                                           color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_depth_colored::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &depth_colored_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_depth_colored::Init {
+                    pos: (),
+                    depth: "depth",
+                    color: "color",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    depth_stencil_target: (gfx::preset::depth::LESS_EQUAL_WRITE, stencil),
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let depth_colored = PsoStencil::new(factory, "depth_colored", depth_colored_pipeline)?;
+
+        let depth_textured_program = factory.link_program(
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::depth::TEXTURED_VERTEX_GLSL_120)
+                    .set(GLSL::V1_50, glsl::depth::TEXTURED_VERTEX_GLSL_150_CORE)
+                    .get(glsl).unwrap(),
+                Shaders::new()
+                    .set(GLSL::V1_20, glsl::depth::TEXTURED_FRAGMENT_GLSL_120)
+                    .set(GLSL::V1_50, glsl::depth::TEXTURED_FRAGMENT_GLSL_150_CORE)
+                    .get(glsl).unwrap()
+            ).unwrap();
+
+        let depth_textured_pipeline = |factory: &mut F,
+                                            blend_preset: Blend,
+                                            stencil: Stencil,
+                                            color_mask: gfx::state::ColorMask|
+        -> Result<PipelineState<R, pipe_depth_textured::Meta>, String> {
+            factory.create_pipeline_from_program(
+                &depth_textured_program,
+                Primitive::TriangleList,
+                Rasterizer::new_fill(),
+                pipe_depth_textured::Init {
+                    pos: (),
+                    uv: (),
+                    depth: "depth",
+                    color: "color",
+                    texture: "s_texture",
+                    blend_target: ("o_Color", format, color_mask, Some(blend_preset)),
+                    depth_stencil_target: (gfx::preset::depth::LESS_EQUAL_WRITE, stencil),
+                    blend_ref: (),
+                    scissor: (),
+                }
+            ).map_err(|err| err.to_string())
+        };
+
+        let depth_textured = PsoStencil::new(factory, "depth_textured", depth_textured_pipeline)?;
+
+        let sampler_tile_nearest = factory.create_sampler(
+            gfx::texture::SamplerInfo::new(
+                gfx::texture::FilterMethod::Scale,
+                gfx::texture::WrapMode::Tile
+            )
+        );
+        let sampler_tile_linear = factory.create_sampler(
+            gfx::texture::SamplerInfo::new(
+                gfx::texture::FilterMethod::Bilinear,
+                gfx::texture::WrapMode::Tile
+            )
+        );
+
+        let buffer_pos = factory.create_buffer(
+            BUFFER_SIZE * CHUNKS,
+            gfx::buffer::Role::Vertex,
+            gfx::memory::Usage::Dynamic,
+            gfx::memory::Bind::empty()
+        ).expect("Could not create `buffer_pos`");
+        let buffer_color = factory.create_buffer(
+            BUFFER_SIZE * CHUNKS,
+            gfx::buffer::Role::Vertex,
+            gfx::memory::Usage::Dynamic,
+            gfx::memory::Bind::empty()
+        ).expect("Could not create `buffer_color`");
+        let buffer_uv = factory.create_buffer(
+            BUFFER_SIZE,
+            gfx::buffer::Role::Vertex,
+            gfx::memory::Usage::Dynamic,
+            gfx::memory::Bind::empty()
+        ).expect("Could not create `buffer_uv`");
+        let buffer_indices = factory.create_buffer(
+            BUFFER_SIZE,
+            gfx::buffer::Role::Vertex,
+            gfx::memory::Usage::Dynamic,
+            gfx::memory::Bind::empty()
+        ).expect("Could not create `buffer_indices`");
+        let buffer_quad = factory.create_vertex_buffer(&[
+            PositionFormat { pos: [0.0, 0.0] }, PositionFormat { pos: [1.0, 0.0] },
+            PositionFormat { pos: [0.0, 1.0] }, PositionFormat { pos: [1.0, 0.0] },
+            PositionFormat { pos: [1.0, 1.0] }, PositionFormat { pos: [0.0, 1.0] }
+        ]);
+        // One oversized triangle has no diagonal seam, unlike a quad.
+        let buffer_full_screen = factory.create_vertex_buffer(&[
+            FullScreenVertex { pos: [-1.0, -1.0] },
+            FullScreenVertex { pos: [3.0, -1.0] },
+            FullScreenVertex { pos: [-1.0, 3.0] }
+        ]);
+        let buffer_instances = factory.create_buffer(
+            MAX_INSTANCES,
+            gfx::buffer::Role::Vertex,
+            gfx::memory::Usage::Dynamic,
+            gfx::memory::Bind::empty()
+        ).expect("Could not create `buffer_instances`");
+        let buffer_ring = vec![BufferSet {
+            pos: buffer_pos.clone(),
+            color: buffer_color.clone(),
+            uv: buffer_uv.clone(),
+            indices: buffer_indices.clone(),
+            instances: buffer_instances.clone(),
+        }];
+
+        Ok(Gfx2d {
+            colored_offset: 0,
+            colored_draw_state: Default::default(),
+            buffer_pos,
+            buffer_indices,
+            buffer_color,
+            buffer_uv,
+            colored,
+            textured,
+            colored_wireframe,
+            colored_dither,
+            textured_wireframe,
+            textured_lod,
+            textured_color,
+            color_key,
+            chroma_key,
+            mul_add,
+            outline,
+            sdf,
+            pattern,
+            soft_point,
+            masked,
+            aa_line,
+            checkerboard,
+            gradient,
+            grain,
+            vignette,
+            dissolve,
+            downsample,
+            downsample_stencil: None,
+            rounded_image,
+            instanced_sprite,
+            mesh,
+            mesh_colored,
+            depth_colored,
+            depth_textured,
+            layer,
+            in_layer: false,
+            colored_program,
+            custom_blends: vec![],
+            custom_blend: false,
+            custom_stencil_ref: None,
+            colored_invert,
+            colored_invert_key: next_pso_keys(3),
+            stencil_invert: false,
+            buffer_quad,
+            buffer_full_screen,
+            buffer_instances,
+            buffer_ring,
+            frame_index: 0,
+            sampler_tile_nearest,
+            sampler_tile_linear,
+            stats: DrawStats::default(),
+            #[cfg(feature = "cpu_time")]
+            frame_start: ::std::time::Instant::now(),
+            #[cfg(feature = "debug")]
+            pso_switch_warn_threshold: None,
+            on_frame_begin: None,
+            on_frame_end: None,
+            clip_region: None,
+            dpi_scale: 1.0,
+            linear_tint: false,
+            origin: Origin::UpperLeft,
+            dither: false,
+            curve_tolerance: 0.25,
+            global_tint: [1.0; 4],
+            format,
+            opengl,
+        })
+    }
+
+    /// Rebuilds all PSOs, buffers and samplers, e.g. after the graphics context was lost.
+    ///
+    /// When the context is lost, e.g. when a mobile app is suspended,
+    /// all handles become invalid. Create a new device and factory, then call this
+    /// with the new factory before drawing again. Settings, callbacks and the number
+    /// of frames in flight are kept, and the PSOs inverting the stencil buffer
+    /// and cached for custom blending are rebuilt too.
+    /// Textures, glyph caches and render targets must be recreated by the app,
+    /// since their handles belong to the old context.
+    /// If a pipeline can not be created, the error is returned and nothing is changed.
+    pub fn recreate<F>(&mut self, factory: &mut F) -> Result<(), Gfx2dError>
+        where F: gfx::Factory<R>
+    {
+        let mut g2d = Gfx2d::with_format(self.opengl, factory, self.format)?;
+        g2d.set_frames_in_flight(factory, self.frames_in_flight())?;
+        // Rebuilds the cached custom blend PSOs, keeping their order.
+        for custom in self.custom_blends.iter().rev() {
+            g2d.use_custom_blend(factory, custom.blend, custom.stencil, custom.color_mask)
+                .map_err(|message| Gfx2dError::Pipeline { name: "custom_blend", message })?;
+        }
+        g2d.on_frame_begin = self.on_frame_begin.take();
+        g2d.on_frame_end = self.on_frame_end.take();
+        g2d.dpi_scale = self.dpi_scale;
+        g2d.linear_tint = self.linear_tint;
+        g2d.origin = self.origin;
+        g2d.dither = self.dither;
+        g2d.curve_tolerance = self.curve_tolerance;
+        g2d.global_tint = self.global_tint;
+        #[cfg(feature = "debug")]
+        {
+            g2d.pso_switch_warn_threshold = self.pso_switch_warn_threshold;
+        }
+        *self = g2d;
+        Ok(())
+    }
+
+    /// Sets callbacks invoked at the start and end of each `draw` call.
+    ///
+    /// The begin callback receives empty statistics,
+    /// the end callback receives the statistics of the frame.
+    /// Panics in the callbacks are caught and logged.
+    pub fn set_frame_callbacks<B, E>(&mut self, on_begin: B, on_end: E)
+        where B: FnMut(&DrawStats) + Send + 'static,
+              E: FnMut(&DrawStats) + Send + 'static
+    {
+        self.on_frame_begin = Some(Box::new(on_begin));
+        self.on_frame_end = Some(Box::new(on_end));
+    }
+
+    /// Removes the frame callbacks.
+    pub fn clear_frame_callbacks(&mut self) {
+        self.on_frame_begin = None;
+        self.on_frame_end = None;
+    }
+
+    // Sets whether draws go to a layer, returning the old setting.
+    // Alpha blending in layers also composites alpha, which other targets keep
+    // as with `gfx::preset::blend::ALPHA`.
+    fn set_in_layer(&mut self, in_layer: bool) -> bool {
+        for (_, flag) in self.pso_flags() {
+            *flag = in_layer;
+        }
+        mem::replace(&mut self.in_layer, in_layer)
+    }
+
+    // Returns the blend factors of a blend setting, see `set_in_layer`.
+    fn blend_factors(&self, blend: Option<draw_state::Blend>) -> gfx::state::Blend {
+        if self.in_layer && blend == Some(draw_state::Blend::Alpha) {
+            BLEND_ALPHA
+        } else {
+            preset_blend(blend)
+        }
+    }
+
+    // Returns the bits of used variants and the layer flags of all built-in pipelines.
+    fn pso_flags(&mut self) -> Vec<(&mut u64, &mut bool)> {
+        vec![
+            (&mut self.colored.used, &mut self.colored.in_layer),
+            (&mut self.textured.used, &mut self.textured.in_layer),
+            (&mut self.colored_wireframe.used, &mut self.colored_wireframe.in_layer),
+            (&mut self.colored_dither.used, &mut self.colored_dither.in_layer),
+            (&mut self.textured_wireframe.used, &mut self.textured_wireframe.in_layer),
+            (&mut self.textured_lod.used, &mut self.textured_lod.in_layer),
+            (&mut self.textured_color.used, &mut self.textured_color.in_layer),
+            (&mut self.color_key.used, &mut self.color_key.in_layer),
+            (&mut self.chroma_key.used, &mut self.chroma_key.in_layer),
+            (&mut self.mul_add.used, &mut self.mul_add.in_layer),
+            (&mut self.outline.used, &mut self.outline.in_layer),
+            (&mut self.sdf.used, &mut self.sdf.in_layer),
+            (&mut self.pattern.used, &mut self.pattern.in_layer),
+            (&mut self.soft_point.used, &mut self.soft_point.in_layer),
+            (&mut self.masked.used, &mut self.masked.in_layer),
+            (&mut self.aa_line.used, &mut self.aa_line.in_layer),
+            (&mut self.checkerboard.used, &mut self.checkerboard.in_layer),
+            (&mut self.gradient.used, &mut self.gradient.in_layer),
+            (&mut self.grain.used, &mut self.grain.in_layer),
+            (&mut self.vignette.used, &mut self.vignette.in_layer),
+            (&mut self.dissolve.used, &mut self.dissolve.in_layer),
+            (&mut self.downsample.used, &mut self.downsample.in_layer),
+            (&mut self.rounded_image.used, &mut self.rounded_image.in_layer),
+            (&mut self.instanced_sprite.used, &mut self.instanced_sprite.in_layer),
+            (&mut self.mesh.used, &mut self.mesh.in_layer),
+            (&mut self.mesh_colored.used, &mut self.mesh_colored.in_layer),
+            (&mut self.depth_colored.used, &mut self.depth_colored.in_layer),
+            (&mut self.depth_textured.used, &mut self.depth_textured.in_layer),
+            (&mut self.layer.used, &mut self.layer.in_layer),
+        ]
+    }
+
+    /// Creates a pipeline defined by the app, for fully custom vertex formats and shaders.
+    ///
+    /// The closure is called once per blend and stencil setting,
+    /// with the format of the render targets, and creates a PSO from a pipeline `Init`.
+    /// Pass the arguments to the targets, e.g. `("o_Color", format, color_mask, Some(blend))`
+    /// for a `gfx::RawRenderTarget` and `stencil` for a `gfx::StencilTarget`.
+    /// Variants that fail fall back like the built-in pipelines,
+    /// and an error named `"custom"` is returned if no fallback can be created.
+    pub fn custom_pipeline<F, M, Fun, E>(&self, factory: &mut F, f: Fun)
+        -> Result<CustomPipeline<R, M>, Gfx2dError>
+        where F: gfx::Factory<R>,
+              Fun: Fn(
+                  &mut F,
+                  gfx::format::Format,
+                  gfx::state::Blend,
+                  gfx::state::Stencil,
+                  gfx::state::ColorMask
+              ) -> Result<PipelineState<R, M>, E>,
+              M: Clone,
+              E: fmt::Display
+    {
+        let format = self.format;
+        PsoStencil::new(factory, "custom", |factory, blend, stencil, mask|
+            f(factory, format, blend, stencil, mask).map_err(|err| err.to_string()))
+            .map(CustomPipeline)
+    }
+
+    // Moves the PSO with custom blending for the settings to the front of the cache,
+    // creating it on a miss and dropping the least recently used PSO if full.
+    fn use_custom_blend<F>(
+        &mut self,
+        factory: &mut F,
+        blend: gfx::state::Blend,
+        stencil: gfx::state::Stencil,
+        color_mask: gfx::state::ColorMask
+    ) -> Result<(), String>
+        where F: gfx::Factory<R>
+    {
+        use gfx::Primitive;
+        use gfx::state::Rasterizer;
+        use gfx::traits::FactoryExt;
+
+        if let Some(i) = self.custom_blends.iter().position(|pso| {
+            pso.blend == blend && pso.stencil == stencil && pso.color_mask == color_mask
+        }) {
+            let pso = self.custom_blends.remove(i);
+            self.custom_blends.insert(0, pso);
+            return Ok(());
+        }
+
+        let pso = factory.create_pipeline_from_program(
+            &self.colored_program,
+            Primitive::TriangleList,
+            Rasterizer::new_fill(),
+            pipe_colored::Init {
+                pos: (),
+                color: (),
+                blend_target: ("o_Color", self.format, color_mask, Some(blend)),
+                stencil_target: stencil,
+                blend_ref: (),
+                scissor: (),
+            }
+        ).map_err(|err| err.to_string())?;
+        self.custom_blends.insert(0, CustomBlendPso {
+            blend,
+            stencil,
+            color_mask,
+            pso,
+            key: next_pso_keys(1),
+        });
+        self.custom_blends.truncate(MAX_CUSTOM_BLENDS);
+        Ok(())
+    }
+
+    /// Returns the internal vertex buffers.
+    ///
+    /// This is meant for debugging tools, not for use every frame.
+    #[cfg(feature = "debug")]
+    pub fn debug_buffers(&self) -> DebugBuffers<R> {
+        DebugBuffers {
+            pos: self.buffer_pos.raw().clone(),
+            color: self.buffer_color.raw().clone(),
+            uv: self.buffer_uv.raw().clone(),
+            indices: self.buffer_indices.raw().clone(),
+        }
+    }
+
+    /// Returns the handles of all PSOs by program, stencil and blend setting.
+    ///
+    /// This is meant for debugging tools, not for use every frame.
+    #[cfg(feature = "debug")]
+    pub fn debug_pipelines(&self) -> Vec<DebugPipeline<R>> {
+        let mut list = vec![];
+        self.colored.debug_pipelines("colored", &mut list);
+        self.textured.debug_pipelines("textured", &mut list);
+        self.colored_wireframe.debug_pipelines("colored_wireframe", &mut list);
+        self.colored_dither.debug_pipelines("colored_dither", &mut list);
+        self.textured_wireframe.debug_pipelines("textured_wireframe", &mut list);
+        self.textured_lod.debug_pipelines("textured_lod", &mut list);
+        self.textured_color.debug_pipelines("textured_color", &mut list);
+        self.color_key.debug_pipelines("color_key", &mut list);
+        self.chroma_key.debug_pipelines("chroma_key", &mut list);
+        self.mul_add.debug_pipelines("mul_add", &mut list);
+        self.outline.debug_pipelines("outline", &mut list);
+        self.sdf.debug_pipelines("sdf", &mut list);
+        self.pattern.debug_pipelines("pattern", &mut list);
+        self.soft_point.debug_pipelines("soft_point", &mut list);
+        self.masked.debug_pipelines("masked", &mut list);
+        self.aa_line.debug_pipelines("aa_line", &mut list);
+        self.checkerboard.debug_pipelines("checkerboard", &mut list);
+        self.gradient.debug_pipelines("gradient", &mut list);
+        self.grain.debug_pipelines("grain", &mut list);
+        self.vignette.debug_pipelines("vignette", &mut list);
+        self.dissolve.debug_pipelines("dissolve", &mut list);
+        self.downsample.debug_pipelines("downsample", &mut list);
+        self.rounded_image.debug_pipelines("rounded_image", &mut list);
+        self.instanced_sprite.debug_pipelines("instanced_sprite", &mut list);
+        self.mesh.debug_pipelines("mesh", &mut list);
+        self.mesh_colored.debug_pipelines("mesh_colored", &mut list);
+        self.depth_colored.debug_pipelines("depth_colored", &mut list);
+        self.depth_textured.debug_pipelines("depth_textured", &mut list);
+        self.layer.debug_pipelines("layer", &mut list);
+        list
+    }
+
+    /// Sets the scale from logical to physical pixels, which is `1.0` by default.
+    ///
+    /// Set this to the ratio of `draw_size` to `window_size` of the viewport,
+    /// so UI laid out in logical pixels renders crisp on HiDPI displays.
+    /// The context transform maps logical pixels already, so geometry is not scaled.
+    /// The scale is only used where the drawing helpers of `GfxGraphics` work
+    /// in physical pixels: `rectangle_outline` rounds edges to physical pixels,
+    /// and `curve_resolution` picks the segments for the physical radius.
+    pub fn set_dpi_scale(&mut self, dpi_scale: f32) {
+        self.dpi_scale = dpi_scale;
+    }
+
+    /// Returns the scale from logical to physical pixels.
+    pub fn dpi_scale(&self) -> f32 {self.dpi_scale}
+
+    /// Sets whether tint colors of textured draws are linear, which is off by default.
+    ///
+    /// Like all colors, the tint passed to `tri_list_uv` is treated as sRGB
+    /// and converted to linear before it is multiplied with the texture,
+    /// which matches the `graphics` crate and other back-ends.
+    /// Turn this on if the tint colors are already linear,
+    /// to avoid converting them twice.
+    pub fn set_linear_tint(&mut self, linear_tint: bool) {
+        self.linear_tint = linear_tint;
+    }
+
+    /// Returns whether tint colors of textured draws are linear.
+    pub fn linear_tint(&self) -> bool {self.linear_tint}
+
+    /// Sets the origin of the coordinate system, which is `Origin::UpperLeft` by default.
+    ///
+    /// This flips the transform of the context passed to drawing closures.
+    /// Scissor rectangles are always in framebuffer coordinates,
+    /// so they match the drawing coordinates with `Origin::LowerLeft` on OpenGL.
+    /// Stencil clipping works per pixel and is not affected.
+    pub fn set_origin(&mut self, origin: Origin) {
+        self.origin = origin;
+    }
+
+    /// Returns the origin of the coordinate system.
+    pub fn origin(&self) -> Origin {self.origin}
+
+    /// Sets whether colored triangles are dithered, which is off by default.
+    ///
+    /// Smooth gradients of vertex colors band visibly on 8-bit targets.
+    /// Dithering adds a small ordered pattern based on the screen position
+    /// before the color is quantized, which hides the bands.
+    /// It has no visible effect on `Rgba16F` targets.
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    /// Returns whether colored triangles are dithered.
+    pub fn dither(&self) -> bool {self.dither}
+
+    /// Sets the maximum distance in pixels between curves and their segments,
+    /// which is `0.25` by default.
+    ///
+    /// A smaller tolerance gives smoother curves with more vertices.
+    /// See `curve_resolution` for the number of segments.
+    pub fn set_curve_tolerance(&mut self, tolerance: f32) {
+        self.curve_tolerance = tolerance;
+    }
+
+    /// Returns the maximum distance in pixels between curves and their segments.
+    pub fn curve_tolerance(&self) -> f32 {self.curve_tolerance}
+
+    /// Returns the maximum number of textured vertices drawn per draw call.
+    ///
+    /// Longer vertex lists passed to `tri_list_uv` are split into chunks
+    /// of whole triangles, so this is a hint for grouping geometry
+    /// into fewer draw calls, not a hard limit. Colored triangles from `tri_list`
+    /// are batched across calls into draw calls of up to 100 times as many vertices.
+    pub fn max_vertices(&self) -> usize {BUFFER_SIZE}
+
+    /// Sets a color multiplied with the colors of all draws, which is white by default.
+    ///
+    /// The tint is in sRGB like other colors, and applies to the next frame drawn
+    /// with `draw` or a similar method, e.g. for fading the whole scene to black.
+    /// It is reset to white when that frame ends, so set it before each frame to keep it.
+    /// Clears are not tinted.
+    pub fn set_global_tint(&mut self, tint: [f32; 4]) {
+        self.global_tint = gamma_srgb_to_linear(tint);
+    }
+
+    // Multiplies a linear color with the global tint.
+    fn apply_global_tint(&self, color: [f32; 4]) -> [f32; 4] {
+        let t = self.global_tint;
+        [color[0] * t[0], color[1] * t[1], color[2] * t[2], color[3] * t[3]]
+    }
+
+    /// Returns the number of segments for a full circle with a radius in logical pixels,
+    /// e.g. for `graphics::Ellipse::resolution`.
+    ///
+    /// Each segment deviates from the circle by at most the curve tolerance,
+    /// which needs `n = pi / acos(1 - tolerance / radius)` segments.
+    /// This grows with the square root of the radius, and is clamped
+    /// between 8 and 1024 so large radii do not create unbounded vertices.
+    /// The radius is multiplied by the DPI scale.
+    pub fn curve_resolution(&self, radius: f64) -> u32 {
+        use std::f64::consts::PI;
+
+        let radius = radius.abs() * self.dpi_scale as f64;
+        let tolerance = self.curve_tolerance.max(f32::EPSILON) as f64;
+        if tolerance >= radius {return MIN_CURVE_SEGMENTS};
+        let n = (PI / (1.0 - tolerance / radius).acos()).ceil();
+        if n.is_finite() {
+            (n as u32).clamp(MIN_CURVE_SEGMENTS, MAX_CURVE_SEGMENTS)
+        } else {
+            MAX_CURVE_SEGMENTS
+        }
+    }
+
+    // Creates the context for a viewport, flipped if the origin is in the lower left corner.
+    fn context(&self, viewport: Viewport) -> Context {
+        let mut c = Context::new_viewport(viewport);
+        if self.origin == Origin::LowerLeft {
+            c.view = flip_y(c.view);
+            c.transform = c.view;
+        }
+        c
+    }
+
+    /// Returns statistics of the last `draw` call.
+    pub fn stats(&self) -> DrawStats {
+        self.stats
+    }
+
+    /// Sets the number of PSO switches per frame above which a warning is logged,
+    /// which is `None` by default to not warn.
+    ///
+    /// Many switches mean state changes between draws, e.g. alternating between
+    /// colored and textured triangles or between blend settings,
+    /// which grouping draws by state reduces. Requires the `debug` feature.
+    #[cfg(feature = "debug")]
+    pub fn set_pso_switch_warn_threshold(&mut self, threshold: Option<usize>) {
+        self.pso_switch_warn_threshold = threshold;
+    }
+
+    /// Sets the number of frames the GPU may still be rendering when the next frame
+    /// is drawn, which is `1` by default.
+    ///
+    /// Vertex buffers are updated many times per frame. With more than one frame
+    /// in flight, each frame uses its own set of buffers in turn, so updates do not
+    /// have to wait for the GPU to finish reading the buffers of the previous frame.
+    /// Use the number of swapchain images, usually `2` for double buffering
+    /// and `3` for triple buffering, or `1` if the app waits for the GPU every frame.
+    /// Each `draw` call counts as a frame, so multiply by the number of calls
+    /// per presented frame when rendering to textures first.
+    /// Every frame in flight costs about 2.5 MB of buffers. The number is kept by `recreate`.
+    pub fn set_frames_in_flight<F>(&mut self, factory: &mut F, frames: usize)
+        -> Result<(), gfx::buffer::CreationError>
+        where F: gfx::Factory<R>
+    {
+        let frames = frames.max(1);
+        self.buffer_ring.truncate(frames);
+        while self.buffer_ring.len() < frames {
+            self.buffer_ring.push(BufferSet::new(factory)?);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of frames in flight.
+    pub fn frames_in_flight(&self) -> usize {self.buffer_ring.len()}
+
+    /// Returns the number of PSO switches per frame above which a warning is logged.
+    #[cfg(feature = "debug")]
+    pub fn pso_switch_warn_threshold(&self) -> Option<usize> {
+        self.pso_switch_warn_threshold
+    }
+
+    /// Renders graphics to a Gfx renderer.
+    ///
+    /// Panics if created with `new_linear`, which draws with `draw_linear`.
+    pub fn draw<C, F, U>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &gfx::handle::RenderTargetView<R, Srgba8>,
+        output_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
+        viewport: Viewport,
+        f: F
+    ) -> U
+        where C: gfx::CommandBuffer<R>,
+              F: FnOnce(Context, &mut GfxGraphics<R, C>) -> U
+    {
+        self.assert_format(output_color.raw());
+        let c = self.context(viewport);
+        self.frame(|g2d| g2d.draw_context(encoder, output_color.raw(), output_stencil, c, f))
+    }
+
+    /// Renders graphics with a projection to normalized device coordinates,
+    /// instead of the orthographic projection of the viewport.
+    ///
+    /// This can be used for a 2D camera, e.g.
+    /// `multiply(abs_transform(w, h), camera)` with zoom, pan and rotation in `camera`.
+    /// The projection is the view transform of the context and is composed with
+    /// the transform of each shape, which the `graphics` crate applies anyway,
+    /// so it adds no per-vertex cost. The origin setting is not applied.
+    /// Like `draw`, this panics if created with `new_linear`.
+    pub fn draw_with_projection<C, F, U>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &gfx::handle::RenderTargetView<R, Srgba8>,
+        output_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
+        viewport: Viewport,
+        projection: Matrix2d,
+        f: F
+    ) -> U
+        where C: gfx::CommandBuffer<R>,
+              F: FnOnce(Context, &mut GfxGraphics<R, C>) -> U
+    {
+        self.assert_format(output_color.raw());
+        let mut c = Context::new_viewport(viewport);
+        c.view = projection;
+        c.transform = projection;
+        self.frame(|g2d| g2d.draw_context(encoder, output_color.raw(), output_stencil, c, f))
+    }
+
+    /// Renders graphics seen through a 2D camera, in world coordinates.
+    ///
+    /// This uses the projection of the camera with `draw_with_projection`,
+    /// so the origin setting is not applied. Use `Camera2d::screen_to_world`
+    /// with the same viewport to map the mouse cursor to the world.
+    pub fn draw_with_camera<C, F, U>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &gfx::handle::RenderTargetView<R, Srgba8>,
+        output_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
+        viewport: Viewport,
+        camera: &Camera2d,
+        f: F
+    ) -> U
+        where C: gfx::CommandBuffer<R>,
+              F: FnOnce(Context, &mut GfxGraphics<R, C>) -> U
+    {
+        let projection = camera.projection(&viewport);
+        self.draw_with_projection(encoder, output_color, output_stencil, viewport,
+            projection, f)
+    }
+
+    /// Copies a render target texture to a texture of the same size and format,
+    /// e.g. to sample what was drawn.
+    ///
+    /// The source needs the `TRANSFER_SRC` and the destination the `TRANSFER_DST` bind flag.
+    /// Resolving multisampled sources is not supported, since gfx has no blit command,
+    /// and returns `ResolveError::Multisampled`.
+    pub fn resolve<C>(
+        &self,
+        encoder: &mut gfx::Encoder<R, C>,
+        src: &gfx::handle::RawTexture<R>,
+        dst: &gfx::handle::RawTexture<R>
+    ) -> Result<(), ResolveError>
+        where C: gfx::CommandBuffer<R>
+    {
+        use gfx::texture::{AaMode, RawImageInfo};
+
+        let (src_info, dst_info) = (src.get_info(), dst.get_info());
+        if src_info.format != dst_info.format {
+            return Err(ResolveError::FormatMismatch {
+                src: src_info.format,
+                dst: dst_info.format,
+            });
+        }
+        let (w, h, _, aa) = src_info.kind.get_dimensions();
+        let (dst_w, dst_h, _, _) = dst_info.kind.get_dimensions();
+        if aa != AaMode::Single {
+            return Err(ResolveError::Multisampled);
+        }
+        if (w, h) != (dst_w, dst_h) {
+            return Err(ResolveError::SizeMismatch { src: [w, h], dst: [dst_w, dst_h] });
+        }
+        let info = RawImageInfo {
+            xoffset: 0,
+            yoffset: 0,
+            zoffset: 0,
+            width: w,
+            height: h,
+            depth: 0,
+            format: self.format,
+            mipmap: 0,
+        };
+        encoder.copy_texture_to_texture_raw(src, None, info, dst, None, info)
+            .map_err(ResolveError::Copy)
+    }
+
+    /// Renders graphics to an `Rgba16F` target, when created with `new_linear`.
+    ///
+    /// Returns an error without drawing if created with `new`.
+    pub fn draw_linear<C, F, U>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &gfx::handle::RenderTargetView<R, Rgba16F>,
+        output_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
+        viewport: Viewport,
+        f: F
+    ) -> Result<U, FormatError>
+        where C: gfx::CommandBuffer<R>,
+              F: FnOnce(Context, &mut GfxGraphics<R, C>) -> U
+    {
+        self.check_format(output_color.raw())?;
+        let c = self.context(viewport);
+        Ok(self.frame(|g2d| g2d.draw_context(encoder, output_color.raw(), output_stencil, c, f)))
+    }
+
+    /// Renders several closures to the same target in one frame.
+    ///
+    /// Each closure gets a fresh context for its viewport.
+    /// Like separate `draw` calls, the commands accumulate in the encoder
+    /// and are submitted together when it is flushed, but the frame callbacks
+    /// are called once and the statistics cover all closures.
+    pub fn draw_many<C>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &gfx::handle::RenderTargetView<R, Srgba8>,
+        output_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
+        draws: &mut [(Viewport, &mut DrawFn<R, C>)]
+    )
+        where C: gfx::CommandBuffer<R>
+    {
+        self.assert_format(output_color.raw());
+        self.frame(|g2d| {
+            for &mut (viewport, ref mut f) in draws {
+                let c = g2d.context(viewport);
+                g2d.draw_context(encoder, output_color.raw(), output_stencil, c, |c, g| f(c, g));
+            }
+        });
+    }
+
+    // Resets the statistics and calls the frame callbacks around a frame.
+    fn frame<F, U>(&mut self, f: F) -> U
+        where F: FnOnce(&mut Self) -> U
+    {
+        self.stats = DrawStats::default();
+        {
+            let set = &self.buffer_ring[self.frame_index % self.buffer_ring.len()];
+            self.buffer_pos = set.pos.clone();
+            self.buffer_color = set.color.clone();
+            self.buffer_uv = set.uv.clone();
+            self.buffer_indices = set.indices.clone();
+            self.buffer_instances = set.instances.clone();
+        }
+        self.frame_index = self.frame_index.wrapping_add(1);
+        for (used, _) in self.pso_flags() {
+            *used = 0;
+        }
+        #[cfg(feature = "cpu_time")]
+        {
+            self.frame_start = ::std::time::Instant::now();
+        }
+        call_frame_callback(&mut self.on_frame_begin, &self.stats, "begin");
+        let res = f(self);
+        #[cfg(feature = "cpu_time")]
+        {
+            self.stats.cpu_time = self.frame_start.elapsed();
+        }
+        #[cfg(feature = "debug")]
+        {
+            if let Some(threshold) = self.pso_switch_warn_threshold {
+                if self.stats.pso_switches > threshold {
+                    warn!("{} PSO switches in a frame exceed the threshold of {}",
+                        self.stats.pso_switches, threshold);
+                }
+            }
+        }
+        call_frame_callback(&mut self.on_frame_end, &self.stats, "end");
+        self.global_tint = [1.0; 4];
+        res
+    }
+
+    /// Renders graphics into a region `[x, y, w, h]` of a larger render target.
+    ///
+    /// This is useful for packing many small render-to-texture results into
+    /// one atlas, avoiding switches between render targets.
+    /// The region is in framebuffer coordinates from the lower left corner,
+    /// like scissor rectangles. The context passed to the closure has its origin
+    /// in the upper left corner of the region, with one unit per pixel.
+    /// Drawing outside the region is clipped. Like `draw`, the frame callbacks are called.
+    pub fn draw_into_atlas_region<C, F, U>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        atlas_color: &gfx::handle::RenderTargetView<R, Srgba8>,
+        atlas_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
+        region: [u32; 4],
+        f: F
+    ) -> U
+        where C: gfx::CommandBuffer<R>,
+              F: FnOnce(Context, &mut GfxGraphics<R, C>) -> U
+    {
+        self.assert_format(atlas_color.raw());
+        let (w, h, _, _) = atlas_color.get_dimensions();
+        let c = self.region_context([w, h], Viewport {
+            rect: [region[0] as i32, region[1] as i32,
+                   region[2] as i32, region[3] as i32],
+            draw_size: [region[2], region[3]],
+            window_size: [region[2] as f64, region[3] as f64],
+        });
+        self.frame(|g2d| {
+            g2d.clip_region = Some(region);
+            let res = g2d.draw_context(encoder, atlas_color.raw(), atlas_stencil, c, f);
+            g2d.clip_region = None;
+            res
+        })
+    }
+
+    /// Renders the same closure into several viewports of one target,
+    /// e.g. for split-screen or stereo rendering.
+    ///
+    /// The closure is called once per viewport, in order, with a context whose origin
+    /// is in the upper left corner of the viewport, in units of the window size.
+    /// Drawing outside the viewport is clipped, and scissor rectangles are relative to it.
+    /// The viewports share the stencil buffer, so clip regions drawn in one viewport
+    /// stay in the stencil buffer for the next ones unless cleared.
+    /// Like `draw_many`, the frame callbacks are called once.
+    pub fn draw_viewports<C, F>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &gfx::handle::RenderTargetView<R, Srgba8>,
+        output_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
+        viewports: &[Viewport],
+        mut f: F
+    )
+        where C: gfx::CommandBuffer<R>,
+              F: FnMut(Context, &mut GfxGraphics<R, C>)
+    {
+        self.assert_format(output_color.raw());
+        let (w, h, _, _) = output_color.get_dimensions();
+        self.frame(|g2d| {
+            for &viewport in viewports {
+                let r = viewport.rect;
+                let c = g2d.region_context([w, h], viewport);
+                g2d.clip_region = Some([r[0].max(0) as u32, r[1].max(0) as u32,
+                                        r[2].max(0) as u32, r[3].max(0) as u32]);
+                g2d.draw_context(encoder, output_color.raw(), output_stencil, c, &mut f);
+            }
+            g2d.clip_region = None;
+        });
+    }
+
+    // Creates a context drawing into the rectangle of a viewport within a target.
+    fn region_context(&self, target_size: [u16; 2], viewport: Viewport) -> Context {
+        use graphics::Transformed;
+        use graphics::math::abs_transform;
+
+        let mut transform = abs_transform(target_size[0] as f64, target_size[1] as f64);
+        if self.origin == Origin::LowerLeft {
+            transform = flip_y(transform);
+        }
+        // Viewport rectangles are from the lower left corner of the target.
+        let r = viewport.rect;
+        let y = match self.origin {
+            Origin::UpperLeft => target_size[1] as i32 - r[1] - r[3],
+            Origin::LowerLeft => r[1],
+        };
+        let transform = transform
+            .trans(r[0] as f64, y as f64)
+            .scale(r[2] as f64 / viewport.window_size[0], r[3] as f64 / viewport.window_size[1]);
+        Context {
+            viewport: Some(viewport),
+            view: transform,
+            transform,
+            draw_state: Default::default(),
+        }
+    }
+
+    // Returns an error if a render target does not have the format of the PSOs.
+    fn check_format(
+        &self,
+        output_color: &gfx::handle::RawRenderTargetView<R>
+    ) -> Result<(), FormatError> {
+        let found = output_color.get_texture().get_info().format;
+        if found != self.format.0 {
+            return Err(FormatError { expected: self.format.0, found });
+        }
+        Ok(())
+    }
+
+    // Panics if a render target does not have the format of the PSOs.
+    fn assert_format(&self, output_color: &gfx::handle::RawRenderTargetView<R>) {
+        if let Err(err) = self.check_format(output_color) {
+            panic!("{}", err);
+        }
+    }
+
+    // Draws to a render target with the format of the PSOs, see `check_format`.
+    fn draw_context<C, F, U>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &gfx::handle::RawRenderTargetView<R>,
+        output_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
+        c: Context,
+        f: F
+    ) -> U
+        where C: gfx::CommandBuffer<R>,
+              F: FnOnce(Context, &mut GfxGraphics<R, C>) -> U
+    {
+        let g = &mut GfxGraphics::from_raw(
+            encoder,
+            output_color,
+            output_stencil,
+            self
+        );
+        g.viewport = c.viewport;
+        let res = f(c, g);
+        if g.g2d.colored_offset > 0 {
+            g.flush_colored();
+        }
+        res
+    }
+}
+
+/// The settings of `GfxGraphics` and `Gfx2d` that affect drawing,
+/// see `GfxGraphics::save`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderState {
+    ignore_clip: bool,
+    wireframe: bool,
+    clip_plane: ClipPlane,
+    assume_linear_colors: bool,
+    dpi_scale: f32,
+    linear_tint: bool,
+    dither: bool,
+    global_tint: [f32; 4],
+    default_draw_state: DrawState,
+}
+
+/// Used for rendering 2D graphics.
+pub struct GfxGraphics<'a, R, C>
+    where R: gfx::Resources + 'a,
+          C: gfx::CommandBuffer<R> + 'a,
+          R::Buffer: 'a,
+          R::Shader: 'a,
+          R::Program: 'a,
+          R::Texture: 'a,
+          R::Sampler: 'a
+{
+    /// Provide access to the `gfx::Encoder` in case a user needs to update textures for caching,
+    /// etc.
+    pub encoder: &'a mut gfx::Encoder<R, C>,
+    output_color: &'a gfx::handle::RawRenderTargetView<R>,
+    output_stencil: &'a gfx::handle::DepthStencilView<R, DepthStencil>,
+    g2d: &'a mut Gfx2d<R>,
+    // Whether to ignore the stencil setting of draw states.
+    ignore_clip: bool,
+    // Whether to draw triangles as wireframes.
+    wireframe: bool,
+    // The stencil bits used by the stencil setting of draw states.
+    clip_plane: ClipPlane,
+    // Whether colors are linear instead of sRGB.
+    assume_linear_colors: bool,
+    // The viewport of the context, used to offset scissor rectangles.
+    viewport: Option<Viewport>,
+    // The draw state of helpers without a draw state parameter.
+    default_draw_state: DrawState,
+}
+
+impl<'a, R, C> GfxGraphics<'a, R, C>
+    where R: gfx::Resources,
+          C: gfx::CommandBuffer<R>,
+{
+    /// Creates a new object for rendering 2D graphics.
+    pub fn new(encoder: &'a mut gfx::Encoder<R, C>,
+               output_color: &'a gfx::handle::RenderTargetView<R, Srgba8>,
+               output_stencil: &'a gfx::handle::DepthStencilView<R, DepthStencil>,
+               g2d: &'a mut Gfx2d<R>) -> Self {
+        GfxGraphics::from_raw(encoder, output_color.raw(), output_stencil, g2d)
+    }
+
+    fn from_raw(encoder: &'a mut gfx::Encoder<R, C>,
+                output_color: &'a gfx::handle::RawRenderTargetView<R>,
+                output_stencil: &'a gfx::handle::DepthStencilView<R, DepthStencil>,
+                g2d: &'a mut Gfx2d<R>) -> Self {
+        GfxGraphics {
+            encoder: encoder,
+            output_color: output_color,
+            output_stencil: output_stencil,
+            g2d: g2d,
+            ignore_clip: false,
+            wireframe: false,
+            clip_plane: ClipPlane::All,
+            assume_linear_colors: false,
+            viewport: None,
+            default_draw_state: DrawState::default(),
+        }
+    }
+
+    /// Sets whether to ignore clipping, which is off by default.
+    ///
+    /// When set, draws use no stencil test regardless of the draw state,
+    /// e.g. to draw tooltips or other overlays on top of clipped content.
+    /// Draws with `Stencil::Clip` still write their mask without drawing color.
+    pub fn set_ignore_clip(&mut self, ignore_clip: bool) {
+        self.ignore_clip = ignore_clip;
+    }
+
+    /// Returns whether clipping is ignored.
+    pub fn ignore_clip(&self) -> bool {self.ignore_clip}
+
+    /// Sets whether to draw the outlines of triangles only, which is off by default.
+    ///
+    /// This applies to the `Graphics` methods and is meant for debugging meshes.
+    /// Requires line rasterization of polygons, which OpenGL ES lacks.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        if self.wireframe != wireframe && self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        self.wireframe = wireframe;
+    }
+
+    /// Returns whether triangles are drawn as wireframes.
+    pub fn wireframe(&self) -> bool {self.wireframe}
+
+    /// Sets the stencil bits used for clipping, which is `ClipPlane::All` by default.
+    ///
+    /// The stencil setting of draw states only reads and writes the bits of the plane,
+    /// so clip regions drawn in different planes do not affect each other.
+    /// For example, clip a window in the upper plane and a scroll view in the lower plane,
+    /// then draw a tooltip inside the upper plane only.
+    /// Do not mix `ClipPlane::All` with the other planes between stencil clears.
+    pub fn set_clip_plane(&mut self, plane: ClipPlane) {
+        if self.clip_plane != plane && self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        self.clip_plane = plane;
+    }
+
+    /// Returns the stencil bits used for clipping.
+    pub fn clip_plane(&self) -> ClipPlane {self.clip_plane}
+
+    /// Sets whether all colors are linear instead of sRGB, which is off by default.
+    ///
+    /// Colors of draws and clears are converted from sRGB to linear,
+    /// since the targets blend in linear space. Turn this on if the colors
+    /// are already linear, e.g. from a linear color picker, to convert them only once.
+    /// This includes tint colors, so `Gfx2d::set_linear_tint` is not needed with it.
+    /// The global tint is always sRGB.
+    pub fn set_assume_linear_colors(&mut self, assume_linear_colors: bool) {
+        self.assume_linear_colors = assume_linear_colors;
+    }
+
+    /// Returns whether all colors are linear.
+    pub fn assume_linear_colors(&self) -> bool {self.assume_linear_colors}
+
+    /// Sets the draw state of helpers without a draw state parameter,
+    /// which is alpha blending without clipping by default.
+    ///
+    /// This reduces boilerplate for apps drawing most things with the same
+    /// blend, stencil and scissor, see `rectangle_default`, `image_default`
+    /// and `ellipse_default`. All other methods still take a draw state.
+    pub fn set_default_draw_state(&mut self, draw_state: DrawState) {
+        self.default_draw_state = draw_state;
+    }
+
+    /// Returns the draw state of helpers without a draw state parameter.
+    pub fn default_draw_state(&self) -> DrawState {self.default_draw_state}
+
+    /// Returns the current settings, to be restored with `restore`.
+    ///
+    /// This covers clipping, wireframe, clip plane, DPI scale, colors, tint, dithering
+    /// and the default draw state.
+    /// Blend, stencil, scissor and transform are passed with each draw and need no saving.
+    /// Save before calling drawing code that changes settings and restore after,
+    /// which works like a stack when nested.
+    pub fn save(&self) -> RenderState {
+        RenderState {
+            ignore_clip: self.ignore_clip,
+            wireframe: self.wireframe,
+            clip_plane: self.clip_plane,
+            assume_linear_colors: self.assume_linear_colors,
+            dpi_scale: self.g2d.dpi_scale,
+            linear_tint: self.g2d.linear_tint,
+            dither: self.g2d.dither,
+            global_tint: self.g2d.global_tint,
+            default_draw_state: self.default_draw_state,
+        }
+    }
+
+    /// Restores settings returned by `save`.
+    pub fn restore(&mut self, state: RenderState) {
+        if self.save() != state && self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        self.ignore_clip = state.ignore_clip;
+        self.wireframe = state.wireframe;
+        self.clip_plane = state.clip_plane;
+        self.assume_linear_colors = state.assume_linear_colors;
+        self.g2d.dpi_scale = state.dpi_scale;
+        self.g2d.linear_tint = state.linear_tint;
+        self.g2d.dither = state.dither;
+        self.g2d.global_tint = state.global_tint;
+        self.default_draw_state = state.default_draw_state;
+    }
+
+    /// Runs a number of passes, each drawing into one target of a ping-pong pair
+    /// while the other one can be sampled, e.g. for blur or other effect chains.
+    ///
+    /// The closure is called with the index of the pass, a context for the
+    /// target with one unit per pixel, the texture drawn by the previous pass,
+    /// and the graphics drawing to the target. The targets are swapped after each pass,
+    /// so the result of the last pass is `pingpong.source()` afterwards.
+    /// The source of the first pass is whatever was drawn to it before.
+    /// Targets are not cleared between passes. The settings of `save` carry over.
+    ///
+    /// Returns an error without drawing if the format of `Gfx2d` is not `Srgba8`.
+    pub fn with_pingpong<F>(
+        &mut self,
+        pingpong: &mut PingPong<R>,
+        passes: usize,
+        mut f: F
+    ) -> Result<(), FormatError>
+        where F: FnMut(usize, Context, &Texture<R>, &mut GfxGraphics<R, C>)
+    {
+        self.g2d.check_format(pingpong.target().raw())?;
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let state = self.save();
+        let clip_region = self.g2d.clip_region.take();
+        let [w, h] = pingpong.size();
+        let viewport = Viewport {
+            rect: [0, 0, w as i32, h as i32],
+            draw_size: [w as u32, h as u32],
+            window_size: [w as f64, h as f64],
+        };
+        for pass in 0..passes {
+            {
+                let c = self.g2d.region_context([w, h], viewport);
+                let g = &mut GfxGraphics::from_raw(
+                    &mut *self.encoder,
+                    pingpong.target().raw(),
+                    pingpong.stencil(),
+                    &mut *self.g2d
+                );
+                g.restore(state);
+                g.viewport = c.viewport;
+                f(pass, c, pingpong.source(), g);
+                if g.g2d.colored_offset > 0 {
+                    g.flush_colored();
+                }
+            }
+            pingpong.swap();
+        }
+        self.g2d.clip_region = clip_region;
+        self.restore(state);
+        Ok(())
+    }
+
+    /// Draws a closure into a layer, then composites the layer onto the target as a group,
+    /// with an opacity and the blend, stencil and scissor of a draw state.
+    ///
+    /// This is group opacity: fading a group of overlapping elements shows
+    /// only the front element where they overlap, while fading each element
+    /// lets the elements behind show through.
+    /// The layer is cleared to transparent first. Inside the layer, alpha blending
+    /// composites alpha too, so the layer stores premultiplied colors, which are
+    /// composited with the premultiplied form of the blend. The closure gets a context
+    /// with the coordinates of the current target, and the layer is stretched over
+    /// the whole target, so it should have the size of the target.
+    /// The settings of `save` carry over.
+    ///
+    /// Returns an error without drawing if the format of `Gfx2d` is not `Srgba8`.
+    pub fn with_layer<F, U>(
+        &mut self,
+        layer: &Layer<R>,
+        opacity: f32,
+        draw_state: &DrawState,
+        f: F
+    ) -> Result<U, FormatError>
+        where F: FnOnce(Context, &mut GfxGraphics<R, C>) -> U
+    {
+        self.g2d.check_format(layer.target().raw())?;
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let state = self.save();
+        let clip_region = self.g2d.clip_region.take();
+        let in_layer = self.g2d.set_in_layer(true);
+        let [w, h] = layer.size();
+        let viewport = self.viewport.unwrap_or(Viewport {
+            rect: [0, 0, w as i32, h as i32],
+            draw_size: [w as u32, h as u32],
+            window_size: [w as f64, h as f64],
+        });
+        let res = {
+            let c = self.g2d.region_context([w, h], viewport);
+            let g = &mut GfxGraphics::from_raw(
+                &mut *self.encoder,
+                layer.target().raw(),
+                layer.stencil(),
+                &mut *self.g2d
+            );
+            g.restore(state);
+            g.viewport = c.viewport;
+            clear_raw(g.encoder, g.output_color, [0.0; 4]);
+            g.encoder.clear_stencil(g.output_stencil, 0);
+            g.encoder.clear_depth(g.output_stencil, 1.0);
+            let res = f(c, g);
+            if g.g2d.colored_offset > 0 {
+                g.flush_colored();
+            }
+            res
+        };
+        self.g2d.clip_region = clip_region;
+        self.g2d.set_in_layer(in_layer);
+        self.restore(state);
+
+        let draw_state = &self.effective_draw_state(draw_state);
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                layer: ref mut pso_layer,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_layer, stencil_val, pso_key) = pso_layer.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        // The contents are already tinted, and premultiplied colors fade
+        // by multiplying all channels.
+        let opacity = opacity.clamp(0.0, 1.0);
+        let data = pipe_textured::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color: [opacity; 4],
+            texture: (layer.texture().view.clone(), layer.texture().sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(), (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        // Render targets store the bottom row first, like normalized device coordinates.
+        let (positions, uvs) = quad([-1.0, -1.0, 2.0, 2.0], graphics::math::identity());
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_layer, &data, stats, pso_key);
+        Ok(res)
+    }
+
+    /// Fills triangles of one color with arbitrary blend factors,
+    /// e.g. `gfx::state::Blend::new(equation, source, destination)`,
+    /// instead of the presets of draw states.
+    ///
+    /// The blend setting of the draw state is ignored, as are wireframe and dithering.
+    /// PSOs are cached by blend, clip plane and stencil test, up to 16 of them,
+    /// dropping the least recently used one. A cache miss compiles a new PSO,
+    /// which may take milliseconds, so reuse few blend settings.
+    /// Returns an error if the PSO can not be created.
+    pub fn tri_list_custom_blend<F, Fv>(
+        &mut self,
+        factory: &mut F,
+        blend: gfx::state::Blend,
+        draw_state: &DrawState,
+        color: &[f32; 4],
+        f: Fv
+    ) -> Result<(), String>
+        where F: gfx::Factory<R>,
+              Fv: FnMut(&mut dyn FnMut(&[[f32; 2]]))
+    {
+        let stencil = self.effective_draw_state(draw_state).stencil;
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let (stencil, color_mask) = stencil_state(self.clip_plane.bits(), stencil);
+        self.g2d.use_custom_blend(factory, blend, stencil, color_mask)?;
+        self.g2d.custom_blend = true;
+        self.tri_list(draw_state, color, f);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        self.g2d.custom_blend = false;
+        Ok(())
+    }
+
+    /// Fills triangles of one color with an explicit stencil test and write setting,
+    /// e.g. to write or test specific stencil bits.
+    ///
+    /// The stencil setting of the draw state, the clip plane and ignored clipping
+    /// do not apply, and neither do wireframe and dithering.
+    /// The blend setting and scissor of the draw state are used.
+    /// PSOs are cached together with those of `tri_list_custom_blend`.
+    /// Returns an error if the PSO can not be created.
+    pub fn tri_list_stencil<F, Fv>(
+        &mut self,
+        factory: &mut F,
+        config: &StencilConfig,
+        draw_state: &DrawState,
+        color: &[f32; 4],
+        f: Fv
+    ) -> Result<(), String>
+        where F: gfx::Factory<R>,
+              Fv: FnMut(&mut dyn FnMut(&[[f32; 2]]))
+    {
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let (stencil, color_mask) = config.state();
+        let blend = self.g2d.blend_factors(draw_state.blend);
+        self.g2d.use_custom_blend(factory, blend, stencil, color_mask)?;
+        self.g2d.custom_blend = true;
+        self.g2d.custom_stencil_ref = Some(config.reference);
+        let draw_state = DrawState {
+            stencil: None,
+            ..*draw_state
+        };
+        self.tri_list(&draw_state, color, f);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        self.g2d.custom_blend = false;
+        self.g2d.custom_stencil_ref = None;
+        Ok(())
+    }
+
+    /// Calls a closure with clipping ignored.
+    pub fn without_clip<F, U>(&mut self, f: F) -> U
+        where F: FnOnce(&mut Self) -> U
+    {
+        let old = self.ignore_clip;
+        self.ignore_clip = true;
+        let res = f(self);
+        self.ignore_clip = old;
+        res
+    }
+
+    /// Calls a closure to draw everywhere except inside a shape, e.g. to dim
+    /// everything but a highlighted region.
+    ///
+    /// The mask is a triangle list in shape coordinates, transformed by `transform`.
+    /// It is written to the stencil buffer, and the closure gets a copy of `draw_state`
+    /// with the `Outside` stencil test for the content.
+    /// The stencil bits of the clip plane are cleared before the mask and after the closure,
+    /// so clip regions drawn before on this plane are lost.
+    /// When clipping is ignored, the mask is not drawn and nothing is masked.
+    pub fn draw_outside_shape<F, U>(
+        &mut self,
+        mask: &[[f64; 2]],
+        transform: Matrix2d,
+        draw_state: &DrawState,
+        f: F
+    ) -> U
+        where F: FnOnce(&DrawState, &mut Self) -> U
+    {
+        self.stencil_mask(mask, draw_state::Stencil::Outside(1), transform, draw_state, f)
+    }
+
+    /// Calls a closure to draw clipped to a region.
+    ///
+    /// Rectangles with a transform that neither rotates nor skews are clipped
+    /// with a scissor rectangle, rounded to whole pixels and intersected with
+    /// the scissor of `draw_state`. This is fast and leaves the stencil buffer untouched.
+    /// Other rectangles and shapes are clipped with the stencil buffer like
+    /// `draw_outside_shape`, but drawing inside, which clears the bits of the clip plane.
+    /// The closure gets a copy of `draw_state` with the scissor or stencil test to use.
+    /// When clipping is ignored, neither is applied and nothing is clipped.
+    pub fn clip<F, U>(
+        &mut self,
+        region: ClipRegion,
+        transform: Matrix2d,
+        draw_state: &DrawState,
+        f: F
+    ) -> U
+        where F: FnOnce(&DrawState, &mut Self) -> U
+    {
+        let inside = draw_state::Stencil::Inside(1);
+        let rect = match region {
+            ClipRegion::Rect(rect) if transform[0][1] == 0.0 && transform[1][0] == 0.0 => rect,
+            ClipRegion::Rect([x, y, w, h]) => {
+                let mask = [
+                    [x, y], [x + w, y], [x, y + h],
+                    [x + w, y], [x + w, y + h], [x, y + h]
+                ];
+                return self.stencil_mask(&mask, inside, transform, draw_state, f);
+            }
+            ClipRegion::Shape(mask) =>
+                return self.stencil_mask(mask, inside, transform, draw_state, f),
+        };
+        let clipped = self.clip_scissor(rect, transform, draw_state);
+        f(&clipped, self)
+    }
+
+    /// Returns a copy of `draw_state` with the scissor intersected with a rectangle,
+    /// like `clip` does for rectangles that are neither rotated nor skewed.
+    ///
+    /// The rectangle is transformed by `transform` and rounded to whole pixels.
+    /// When clipping is ignored, `draw_state` is returned unchanged.
+    pub fn clip_scissor(
+        &self,
+        rect: [f64; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) -> DrawState {
+        use graphics::math::transform_pos;
+
+        if self.ignore_clip {
+            return *draw_state;
+        }
+        let [x, y, w, h] = rect;
+        // Map from normalized device coordinates to pixels from the lower left corner,
+        // like scissor rectangles and viewports.
+        let (tw, th, _, _) = self.output_color.get_dimensions();
+        let to_pixels = |p: [f64; 2]| {
+            let p = transform_pos(transform, p);
+            [((p[0] + 1.0) * 0.5 * tw as f64).round().max(0.0),
+             ((p[1] + 1.0) * 0.5 * th as f64).round().max(0.0)]
+        };
+        let (a, b) = (to_pixels([x, y]), to_pixels([x + w, y + h]));
+        let mut rect = [a[0].min(b[0]) as i32, a[1].min(b[1]) as i32,
+                        (a[0] - b[0]).abs() as i32, (a[1] - b[1]).abs() as i32];
+        // Scissor rectangles of draw states are relative to the viewport.
+        if let Some(viewport) = self.viewport {
+            rect[0] -= viewport.rect[0];
+            rect[1] -= viewport.rect[1];
+        }
+        let x1 = rect[0].max(0) as u32;
+        let y1 = rect[1].max(0) as u32;
+        let x2 = (rect[0] + rect[2]).max(0) as u32;
+        let y2 = (rect[1] + rect[3]).max(0) as u32;
+        let scissor = match draw_state.scissor {
+            Some(s) => {
+                let (x1, y1) = (x1.max(s[0]), y1.max(s[1]));
+                let (x2, y2) = (x2.min(s[0] + s[2]), y2.min(s[1] + s[3]));
+                [x1, y1, x2.saturating_sub(x1), y2.saturating_sub(y1)]
+            }
+            None => [x1, y1, x2 - x1, y2 - y1],
+        };
+        DrawState {
+            scissor: Some(scissor),
+            ..*draw_state
+        }
+    }
+
+    /// Calls a closure to draw clipped to a shape with the even-odd rule,
+    /// e.g. to a ring from an outer and an inner contour.
+    ///
+    /// The contours are closed polygons in shape coordinates, transformed by `transform`,
+    /// and may intersect themselves and each other.
+    /// Points covered by an odd number of contours are inside.
+    /// Each contour is drawn as a triangle fan inverting the stencil buffer,
+    /// and the closure gets a copy of `draw_state` with the `Inside` stencil test.
+    /// Like `clip`, this clears the bits of the clip plane before and after.
+    pub fn clip_even_odd<F, U>(
+        &mut self,
+        contours: &[&[[f64; 2]]],
+        transform: Matrix2d,
+        draw_state: &DrawState,
+        f: F
+    ) -> U
+        where F: FnOnce(&DrawState, &mut Self) -> U
+    {
+        let masked = DrawState {
+            stencil: Some(draw_state::Stencil::Inside(1)),
+            ..*draw_state
+        };
+        if self.ignore_clip {
+            return f(&masked, self);
+        }
+        let vertices = contour_fans(contours, transform);
+        let clip = DrawState {
+            stencil: Some(draw_state::Stencil::Clip(1)),
+            ..*draw_state
+        };
+        self.clear_clip_plane();
+        self.g2d.stencil_invert = true;
+        self.tri_list(&clip, &[1.0; 4], |f| f(&vertices));
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        self.g2d.stencil_invert = false;
+        let res = f(&masked, self);
+        self.clear_clip_plane();
+        res
+    }
+
+    // Clears the stencil bits of the clip plane, keeping the bits of the other plane.
+    // With both planes in use, this draws a full screen clip mask of `0`,
+    // which only writes the bits of the plane.
+    fn clear_clip_plane(&mut self) {
+        if self.clip_plane == ClipPlane::All {
+            self.clear_stencil(0);
+            return;
+        }
+        let clear = DrawState {
+            stencil: Some(draw_state::Stencil::Clip(0)),
+            scissor: None,
+            blend: None,
+        };
+        let quad = [[-1.0, -1.0], [1.0, -1.0], [-1.0, 1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+        self.tri_list(&clear, &[1.0; 4], |f| f(&quad));
+    }
+
+    // Writes a triangle list mask to the cleared bits of the clip plane and calls a closure
+    // with a copy of the draw state using a stencil test, clearing the bits after.
+    fn stencil_mask<F, U>(
+        &mut self,
+        mask: &[[f64; 2]],
+        stencil: draw_state::Stencil,
+        transform: Matrix2d,
+        draw_state: &DrawState,
+        f: F
+    ) -> U
+        where F: FnOnce(&DrawState, &mut Self) -> U
+    {
+        use graphics::math::transform_pos;
+
+        let masked = DrawState {
+            stencil: Some(stencil),
+            ..*draw_state
+        };
+        if self.ignore_clip {
+            return f(&masked, self);
+        }
+        let vertices: Vec<[f32; 2]> = mask.iter().map(|&p| {
+            let p = transform_pos(transform, p);
+            [p[0] as f32, p[1] as f32]
+        }).collect();
+        let clip = DrawState {
+            stencil: Some(draw_state::Stencil::Clip(1)),
+            ..*draw_state
+        };
+        self.clear_clip_plane();
+        self.tri_list(&clip, &[1.0; 4], |f| f(&vertices));
+        let res = f(&masked, self);
+        self.clear_clip_plane();
+        res
+    }
+
+    // Adds triangles with one color per vertex to the colored batch.
+    // The colors are used as they are, without converting to linear or tinting.
+    fn tri_list_colors(
+        &mut self,
+        draw_state: &DrawState,
+        vertices: &[[f32; 2]],
+        colors: &[[f32; 4]]
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        if &self.g2d.colored_draw_state != draw_state {
+            if self.g2d.colored_offset > 0 {
+                self.flush_colored();
+            }
+            self.g2d.colored_draw_state = *draw_state;
+        }
+        // Only whole triangles are drawn per chunk.
+        let chunk = BUFFER_SIZE * CHUNKS - BUFFER_SIZE * CHUNKS % 3;
+        for (vertices, colors) in vertices.chunks(chunk).zip(colors.chunks(chunk)) {
+            let n = vertices.len();
+            if self.g2d.colored_offset + n > BUFFER_SIZE * CHUNKS {
+                self.flush_colored();
+            }
+            let positions: Vec<_> = vertices.iter().map(|&pos| PositionFormat { pos }).collect();
+            let colors: Vec<_> = colors.iter().map(|&color| ColorFormat { color }).collect();
+            let offset = self.g2d.colored_offset;
+            self.encoder.update_buffer(&self.g2d.buffer_pos, &positions, offset).unwrap();
+            self.encoder.update_buffer(&self.g2d.buffer_color, &colors, offset).unwrap();
+            self.g2d.colored_offset += n;
+        }
+    }
+
+    // Converts a texture tint color to linear, unless it is linear already,
+    // and applies the global tint.
+    fn tint_color(&self, color: &[f32; 4]) -> [f32; 4] {
+        let color = if self.g2d.linear_tint {*color} else {self.linear_color(*color)};
+        self.g2d.apply_global_tint(color)
+    }
+
+    // Converts a color to linear and applies the global tint.
+    fn draw_color(&self, color: &[f32; 4]) -> [f32; 4] {
+        self.g2d.apply_global_tint(self.linear_color(*color))
+    }
+
+    // Converts a color to linear, unless colors are assumed to be linear.
+    fn linear_color(&self, color: [f32; 4]) -> [f32; 4] {
+        if self.assume_linear_colors {color} else {gamma_srgb_to_linear(color)}
+    }
+
+    // Returns the draw state to use, without stencil test if clipping is ignored,
+    // and with the scissor relative to the viewport.
+    // Clip masks are still written, since they draw no color.
+    fn effective_draw_state(&self, draw_state: &DrawState) -> DrawState {
+        use graphics::draw_state::Stencil;
+
+        let mut draw_state = *draw_state;
+        match draw_state.stencil {
+            Some(Stencil::Inside(_)) | Some(Stencil::Outside(_)) if self.ignore_clip =>
+                draw_state.stencil = None,
+            _ => {}
+        }
+        if let Some(viewport) = self.viewport {
+            if let Some(ref mut scissor) = draw_state.scissor {
+                scissor[0] = (scissor[0] as i32 + viewport.rect[0]).max(0) as u32;
+                scissor[1] = (scissor[1] as i32 + viewport.rect[1]).max(0) as u32;
+            }
+        }
+        draw_state
+    }
+
+    /// Returns the PSO variant, stencil reference value and scissor rectangle
+    /// a draw with the draw state would use, without drawing.
+    ///
+    /// This accounts for ignored clipping, the clip plane, the viewport
+    /// and the clip region, e.g. to debug why a draw is clipped away.
+    /// Custom blending and stencil inversion are not included.
+    pub fn resolve_draw_state(&self, draw_state: &DrawState) -> ResolvedState {
+        use graphics::draw_state::Stencil;
+
+        let draw_state = self.effective_draw_state(draw_state);
+        let plane = self.clip_plane;
+        let index = PsoVariant::index(plane, draw_state.stencil, draw_state.blend);
+        let stencil_ref = match draw_state.stencil {
+            Some(Stencil::Clip(val)) |
+            Some(Stencil::Inside(val)) |
+            Some(Stencil::Outside(val)) => plane.shift(val),
+            None => 0,
+        };
+        ResolvedState {
+            variant: PsoVariant::from_bits(1 << index)[0],
+            stencil_ref,
+            scissor: scissor_rect(draw_state.scissor, self.g2d.clip_region),
+        }
+    }
+
+    /// Returns whether a draw with the draw state blends with the render target.
+    ///
+    /// Only draws without a blend setting are opaque, e.g. to draw them
+    /// front to back before transparent draws back to front.
+    pub fn is_blending(&self, draw_state: &DrawState) -> bool {
+        draw_state.blend.is_some()
+    }
+
+    /// Returns statistics of the current frame so far.
+    pub fn stats(&self) -> DrawStats {
+        self.g2d.stats
+    }
+
+    /// Returns the CPU time spent in the current frame so far,
+    /// e.g. to tell whether building the frame or the GPU is the bottleneck.
+    ///
+    /// Compare with the frame time: if this is close to it, the app is CPU bound,
+    /// which fewer draw calls can fix. `DrawStats::cpu_time` has the total.
+    #[cfg(feature = "cpu_time")]
+    pub fn cpu_time(&self) -> ::std::time::Duration {
+        self.g2d.frame_start.elapsed()
+    }
+
+    /// Returns the blend and stencil settings of the built-in pipelines
+    /// used in the current frame so far.
+    ///
+    /// This helps to see which state combinations a frame needs.
+    /// Pending colored triangles are counted when flushed.
+    pub fn used_variants(&mut self) -> Vec<PsoVariant> {
+        let used = self.g2d.pso_flags().into_iter().fold(0, |acc, (used, _)| acc | *used);
+        PsoVariant::from_bits(used)
+    }
+
+    /// Clears the color with RGB multiplied by alpha, for targets storing premultiplied alpha.
+    ///
+    /// Use this instead of `clear_color` for offscreen targets that are drawn with
+    /// premultiplied blending and composited later, when clearing to a translucent color.
+    /// For opaque colors, both clears are the same.
+    /// The color is converted to linear before it is premultiplied.
+    pub fn clear_color_premultiplied(&mut self, color: [f32; 4]) {
+        let [r, g, b, a] = self.linear_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            ..
+        } = self;
+        clear_raw(encoder, output_color, [r * a, g * a, b * a, a]);
+    }
+
+    /// Clears color, stencil and depth in one call.
+    ///
+    /// The color is in sRGB like for `clear_color`, and alpha is kept as is.
+    /// Depth is cleared to `1.0`, since the stencil target also has a depth channel.
+    pub fn clear(&mut self, color: [f32; 4], stencil: u8) {
+        let color = self.linear_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            ..
+        } = self;
+        clear_raw(encoder, output_color, color);
+        encoder.clear_stencil(output_stencil, stencil);
+        encoder.clear_depth(output_stencil, 1.0);
+    }
+
+    /// Clears the depth channel of the stencil target, keeping the stencil.
+    ///
+    /// Depth is usually cleared to `1.0`, the far plane.
+    pub fn clear_depth(&mut self, value: f32) {
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_stencil,
+            ..
+        } = self;
+        encoder.clear_depth(output_stencil, value);
+    }
+
+    /// Clears both depth and stencil of the stencil target.
+    pub fn clear_depth_stencil(&mut self, depth: f32, stencil: u8) {
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_stencil,
+            ..
+        } = self;
+        encoder.clear_depth(output_stencil, depth);
+        encoder.clear_stencil(output_stencil, stencil);
+    }
+
+    /// Reads the stencil value at a pixel, e.g. for hit testing against clip regions.
+    ///
+    /// The position is in framebuffer coordinates, like scissor rectangles.
+    /// This flushes the encoder and waits for the GPU to finish, so it is slow
+    /// and should not be called every frame.
+    /// Returns `None` if the stencil buffer can not be read,
+    /// e.g. for the default framebuffer of a window.
+    pub fn read_stencil<F, D>(
+        &mut self,
+        factory: &mut F,
+        device: &mut D,
+        pos: [u32; 2]
+    ) -> Option<u8>
+        where F: gfx::Factory<R>,
+              D: gfx::Device<Resources = R, CommandBuffer = C>
+    {
+        use gfx::format::Formatted;
+        use gfx::traits::FactoryExt;
+
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let buffer = factory.create_download_buffer::<u32>(1).ok()?;
+        let info = pixel_info(pos, DepthStencil::get_format());
+        let texture = self.output_stencil.raw().get_texture();
+        self.encoder.copy_texture_to_buffer_raw(texture, None, info, buffer.raw(), 0).ok()?;
+        self.encoder.flush(device);
+        let reader = factory.read_mapping(&buffer).ok()?;
+        // The stencil is stored in the lowest 8 bits, after 24 bits of depth.
+        Some((reader[0] & 0xff) as u8)
+    }
+
+    /// Reads the alpha at a pixel, from `0.0` to `1.0`,
+    /// e.g. for picking against antialiased shapes as they are drawn.
+    ///
+    /// The position is in framebuffer coordinates, like scissor rectangles.
+    /// Like `read_stencil`, this flushes the encoder and waits for the GPU to finish,
+    /// so it is slow and should only be called on user input, not every frame.
+    /// Returns `None` if the render target can not be read,
+    /// e.g. for the default framebuffer of a window.
+    pub fn sample_coverage<F, D>(
+        &mut self,
+        factory: &mut F,
+        device: &mut D,
+        pos: [u32; 2]
+    ) -> Option<f32>
+        where F: gfx::Factory<R>,
+              D: gfx::Device<Resources = R, CommandBuffer = C>
+    {
+        use gfx::format::SurfaceType;
+        use gfx::traits::FactoryExt;
+
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        // Large enough for one pixel of `Rgba16F`.
+        let buffer = factory.create_download_buffer::<u8>(8).ok()?;
+        let info = pixel_info(pos, self.g2d.format);
+        let texture = self.output_color.get_texture();
+        self.encoder.copy_texture_to_buffer_raw(texture, None, info, buffer.raw(), 0).ok()?;
+        self.encoder.flush(device);
+        let reader = factory.read_mapping(&buffer).ok()?;
+        match self.g2d.format.0 {
+            SurfaceType::R16_G16_B16_A16 =>
+                Some(half_to_f32(reader[6] as u16 | (reader[7] as u16) << 8)),
+            _ => Some(reader[3] as f32 / 255.0),
+        }
+    }
+
+    /// Returns true if texture has alpha channel.
+    pub fn has_texture_alpha(&self, texture: &Texture<R>) -> bool
+        where R: gfx::Resources
+    {
+        texture.surface.get_info().format.get_alpha_stencil_bits() > 0
+    }
+
+    /// Draws text using a signed distance field glyph cache.
+    ///
+    /// The text is crisp at any scale, since the glyph outline is computed
+    /// per pixel from the distance field.
+    /// Outline and glow are set in the style.
+    ///
+    /// Like text drawn with `graphics::text`, the glyphs are masked by the
+    /// stencil test in `draw_state`, so text can be clipped to a shape.
+    /// With the `bidi` feature, right-to-left text is drawn in visual order.
+    pub fn draw_text_sdf<F, C2>(
+        &mut self,
+        cache: &mut SdfGlyphCache<F, R, C2>,
+        text: &str,
+        font_size: FontSize,
+        style: &SdfStyle,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) -> Result<(), ::gfx_texture::Error>
+        where F: gfx::Factory<R>,
+              C2: gfx::CommandBuffer<R>
+    {
+        use graphics::math::transform_pos;
+        #[cfg(feature = "bidi")]
+        let text = &::bidi::reorder(text, None);
+        cache.preload_chars(text.chars())?;
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+
+        let scale = cache.scale(font_size);
+        let mut vertices: Vec<[f32; 2]> = Vec::with_capacity(6 * text.len());
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(6 * text.len());
+        let mut texture = None;
+        let mut x = 0.0;
+        for ch in text.chars() {
+            let glyph = cache.opt_glyph(ch).unwrap();
+            match texture {
+                Some(texture) if texture != glyph.texture => {
+                    self.draw_sdf(cache.texture(texture), &vertices, &uvs,
+                        style, draw_state);
+                    vertices.clear();
+                    uvs.clear();
+                }
+                _ => {}
+            }
+            texture = Some(glyph.texture);
+
+            let x1 = x + glyph.offset[0] * scale;
+            let y1 = -glyph.offset[1] * scale;
+            let x2 = x1 + glyph.atlas_size[0] * scale;
+            let y2 = y1 + glyph.atlas_size[1] * scale;
+            let corner = |x, y| {
+                let p = transform_pos(transform, [x, y]);
+                [p[0] as f32, p[1] as f32]
+            };
+            let uv = cache.glyph_uv(&glyph);
+            vertices.extend_from_slice(&[
+                corner(x1, y1), corner(x2, y1), corner(x1, y2),
+                corner(x2, y1), corner(x2, y2), corner(x1, y2)
+            ]);
+            uvs.extend_from_slice(&[
+                [uv[0], uv[1]], [uv[2], uv[1]], [uv[0], uv[3]],
+                [uv[2], uv[1]], [uv[2], uv[3]], [uv[0], uv[3]]
+            ]);
+            x += glyph.advance_size[0] * scale;
+        }
+        if let Some(texture) = texture {
+            self.draw_sdf(cache.texture(texture), &vertices, &uvs, style, draw_state);
+        }
+        Ok(())
+    }
+
+    /// Draws a label from a label cache, with the left end of the baseline at `pos`.
+    ///
+    /// Does nothing if the label is not cached.
+    pub fn draw_cached_label<K>(
+        &mut self,
+        cache: &LabelCache<R, K>,
+        id: &K,
+        pos: [f64; 2],
+        color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    )
+        where K: ::std::hash::Hash + Eq
+    {
+        use graphics::math::transform_pos;
+
+        let label = match cache.get(id) {
+            Some(label) => label,
+            None => return,
+        };
+        let [w, h] = label.size;
+        let (x, y) = (pos[0], pos[1] - label.ascent);
+        let corner = |u: f64, v: f64| {
+            let p = transform_pos(transform, [x + u * w, y + v * h]);
+            [p[0] as f32, p[1] as f32]
+        };
+        let vertices = [
+            corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0),
+            corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)
+        ];
+        let uvs = [
+            [0.0, 0.0], [1.0, 0.0], [0.0, 1.0],
+            [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]
+        ];
+        self.tri_list_uv(draw_state, color, &label.texture, |f| f(&vertices, &uvs));
+    }
+
+    /// Draws text with a tiny built-in font, with the upper left corner at `pos`,
+    /// e.g. for frame rates and coordinates while debugging.
+    ///
+    /// The glyphs are 3x5 pixels scaled by two, so a line is 10 units high.
+    /// Only printable ASCII up to `'_'` is covered, with lowercase letters
+    /// drawn as uppercase, and other characters are drawn blank.
+    /// This is low quality and meant for development only. Use a font for real text.
+    pub fn debug_text(
+        &mut self,
+        text: &str,
+        pos: [f64; 2],
+        color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::math::transform_pos;
+        use debug_font::{glyph, GLYPH_SIZE};
+
+        // The size of a font pixel, with one pixel of spacing between glyphs and lines.
+        const PIXEL: f64 = 2.0;
+        let advance = [(GLYPH_SIZE[0] + 1) as f64 * PIXEL, (GLYPH_SIZE[1] + 2) as f64 * PIXEL];
+        let mut vertices: Vec<[f32; 2]> = vec![];
+        for (row, line) in text.split('\n').enumerate() {
+            for (column, ch) in line.chars().enumerate() {
+                let rows = match glyph(ch) {
+                    Some(rows) => rows,
+                    None => continue,
+                };
+                let x = pos[0] + column as f64 * advance[0];
+                let y = pos[1] + row as f64 * advance[1];
+                for (py, bits) in rows.iter().enumerate() {
+                    for px in 0..GLYPH_SIZE[0] {
+                        if bits & (1 << (GLYPH_SIZE[0] - 1 - px)) == 0 {continue};
+                        let x1 = x + px as f64 * PIXEL;
+                        let y1 = y + py as f64 * PIXEL;
+                        let corner = |x, y| {
+                            let p = transform_pos(transform, [x, y]);
+                            [p[0] as f32, p[1] as f32]
+                        };
+                        let (x2, y2) = (x1 + PIXEL, y1 + PIXEL);
+                        vertices.extend_from_slice(&[
+                            corner(x1, y1), corner(x2, y1), corner(x1, y2),
+                            corner(x2, y1), corner(x2, y2), corner(x1, y2)
+                        ]);
+                    }
+                }
+            }
+        }
+        self.tri_list(draw_state, color, |f| f(&vertices));
+    }
+
+    /// Draws text with a bitmap font, with the upper left corner of the first glyph
+    /// at the origin of `transform`.
+    ///
+    /// Each glyph is drawn at its size in pixels multiplied by `scale`.
+    /// Lines are separated by `'\n'`. Characters without a glyph are drawn blank.
+    /// Use nearest filtering for the texture of the font to keep the pixels sharp.
+    pub fn draw_bitmap_text(
+        &mut self,
+        font: &BitmapFont<R>,
+        text: &str,
+        scale: f64,
+        color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::math::transform_pos;
+        let w = font.glyph_size[0] as f64 * scale;
+        let h = font.glyph_size[1] as f64 * scale;
+        let mut vertices: Vec<[f32; 2]> = Vec::with_capacity(6 * text.len());
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(6 * text.len());
+        for (row, line) in text.split('\n').enumerate() {
+            let y = row as f64 * h;
+            for (column, ch) in line.chars().enumerate() {
+                let uv = match font.glyph_uv(ch) {
+                    Some(uv) => uv,
+                    None => continue,
+                };
+                let x = column as f64 * w;
+                let corner = |u: f64, v: f64| {
+                    let p = transform_pos(transform, [x + u * w, y + v * h]);
+                    [p[0] as f32, p[1] as f32]
+                };
+                vertices.extend_from_slice(&[
+                    corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0),
+                    corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)
+                ]);
+                uvs.extend_from_slice(&[
+                    [uv[0], uv[1]], [uv[2], uv[1]], [uv[0], uv[3]],
+                    [uv[2], uv[1]], [uv[2], uv[3]], [uv[0], uv[3]]
+                ]);
+            }
+        }
+        self.tri_list_uv(draw_state, color, &font.texture, |f| f(&vertices, &uvs));
+    }
+
+    /// Draws text with a background rectangle behind each line, e.g. for selections.
+    ///
+    /// The text is drawn with the color and font size of `style`,
+    /// and the left end of the first baseline is at the origin of `transform`.
+    /// Lines are separated by `'\n'` and are 1.25 em apart, where an em is
+    /// the pixel size of the font size, like for `GlyphCache`. The highlight of a line
+    /// spans from one em above to a quarter em below its baseline,
+    /// so the highlights of adjacent lines touch without overlapping.
+    /// Empty lines get no highlight.
+    pub fn draw_text_highlighted<G>(
+        &mut self,
+        cache: &mut G,
+        style: &::graphics::Text,
+        text: &str,
+        highlight_color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) -> Result<(), G::Error>
+        where G: ::graphics::character::CharacterCache<Texture = Texture<R>>
+    {
+        use graphics::{Rectangle, Transformed};
+        let em = style.font_size as f64 * 1.333;
+        let highlight = Rectangle::new(*highlight_color);
+        for (i, line) in text.split('\n').enumerate() {
+            let transform = transform.trans(0.0, i as f64 * 1.25 * em);
+            let width = cache.width(style.font_size, line)?;
+            if width > 0.0 {
+                highlight.draw([0.0, -em, width, 1.25 * em], draw_state, transform, self);
+            }
+            style.draw(line, cache, draw_state, transform, self)?;
+        }
+        Ok(())
+    }
+
+    /// Fills a triangle list modulated by a repeating pattern texture.
+    ///
+    /// The vertices are in shape coordinates and transformed by `transform`.
+    /// One repetition of the pattern covers the size of the texture,
+    /// either in pixels or in shape coordinates, depending on the anchor.
+    pub fn pattern_fill(
+        &mut self,
+        color: &[f32; 4],
+        vertices: &[[f64; 2]],
+        texture: &Texture<R>,
+        anchor: PatternAnchor,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::ImageSize;
+        use graphics::math::{multiply, scale, transform_pos};
+        let (tw, th) = texture.get_size();
+        let to_pattern = scale(1.0 / tw as f64, 1.0 / th as f64);
+        let pattern_transform = match anchor {
+            PatternAnchor::Screen => {
+                // Map from normalized device coordinates to pixels.
+                let (w, h, _, _) = self.output_color.get_dimensions();
+                let (w, h) = (w as f64, h as f64);
+                multiply(to_pattern, [[0.5 * w, 0.0, 0.5 * w], [0.0, -0.5 * h, 0.5 * h]])
+            }
+            PatternAnchor::Shape => match invert_transform(transform) {
+                Some(inv) => multiply(to_pattern, inv),
+                None => return,
+            },
+        };
+
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.draw_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut pattern,
+                ref mut stats,
+                clip_region,
+                ref sampler_tile_nearest,
+                ref sampler_tile_linear,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_pattern, stencil_val, pso_key) = pattern.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let sampler = match texture.sampler.get_info().filter {
+            gfx::texture::FilterMethod::Scale => sampler_tile_nearest,
+            _ => sampler_tile_linear,
+        };
+        let data = pipe_pattern::Data {
+            pos: buffer_pos.clone(),
+            color,
+            pattern_transform: transform_to_mat3(pattern_transform),
+            texture: (texture.view.clone(), sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        // Only whole triangles are drawn per chunk.
+        let chunk = BUFFER_SIZE - BUFFER_SIZE % 3;
+        let mut positions: Vec<PositionFormat> = Vec::with_capacity(chunk);
+        for vertices in vertices.chunks(chunk) {
+            positions.clear();
+            positions.extend(vertices.iter().map(|v| {
+                let p = transform_pos(transform, *v);
+                PositionFormat { pos: [p[0] as f32, p[1] as f32] }
+            }));
+            encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+            draw_slice(encoder, positions.len(), pso_pattern, &data, stats, pso_key);
+        }
+    }
+
+    /// Fills the rectangle `[x, y, w, h]` with a texture repeated from the upper left
+    /// corner, shifted by a scroll offset, e.g. for scrolling backgrounds.
+    ///
+    /// One repetition covers the size of the texture in the units of the rectangle.
+    /// The offset is wrapped to the texture size, so it can grow without
+    /// losing precision in the texture coordinates.
+    pub fn tiled_background(
+        &mut self,
+        texture: &Texture<R>,
+        scroll_offset: [f64; 2],
+        dst_rect: [f64; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::{ImageSize, Transformed};
+
+        let (tw, th) = texture.get_size();
+        let ox = scroll_offset[0].rem_euclid(tw as f64);
+        let oy = scroll_offset[1].rem_euclid(th as f64);
+        // The pattern is anchored to the shape coordinates, so the rectangle
+        // is moved to the offset and the transform moves it back.
+        let [x, y, w, h] = dst_rect;
+        let vertices = [
+            [ox, oy], [ox + w, oy], [ox, oy + h],
+            [ox + w, oy], [ox + w, oy + h], [ox, oy + h]
+        ];
+        self.pattern_fill(&[1.0; 4], &vertices, texture, PatternAnchor::Shape,
+            transform.trans(x - ox, y - oy), draw_state);
+    }
+
+    /// Draws textured triangles sampled at a fixed mipmap level,
+    /// e.g. a higher level of a background as a cheap blur.
+    ///
+    /// Like for `tri_list_uv`, the closure passes vertices in normalized device
+    /// coordinates and texture coordinates. The level may be fractional,
+    /// which blends between levels if the sampler filters mipmaps linearly.
+    /// Levels beyond the mipmap chain of the texture are clamped to the last level,
+    /// so textures without mipmaps are always sampled at full resolution.
+    /// With GLSL 1.20 and without the `GL_ARB_shader_texture_lod` extension,
+    /// the level is ignored and the texture is sampled like for `tri_list_uv`.
+    pub fn tri_list_uv_lod<F>(
+        &mut self,
+        level: f32,
+        draw_state: &DrawState,
+        color: &[f32; 4],
+        texture: &Texture<R>,
+        mut f: F
+    )
+        where F: FnMut(&mut dyn FnMut(&[[f32; 2]], &[[f32; 2]]))
+    {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut textured_lod,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_textured_lod, stencil_val, pso_key) = textured_lod.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_textured_lod::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            level: level.max(0.0),
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        f(&mut |vertices: &[[f32; 2]], texture_coords: &[[f32; 2]]| {
+            assert_eq!(vertices.len(), texture_coords.len());
+            // Only whole triangles are drawn per chunk.
+            let chunk = BUFFER_SIZE - BUFFER_SIZE % 3;
+            for (vertices, texture_coords) in vertices.chunks(chunk)
+                .zip(texture_coords.chunks(chunk))
+            {
+                let positions: Vec<_> = vertices.iter()
+                    .map(|&pos| PositionFormat { pos }).collect();
+                let uvs: Vec<_> = texture_coords.iter()
+                    .map(|&uv| TexCoordsFormat { uv }).collect();
+                encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+                encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+                draw_slice(encoder, positions.len(), pso_textured_lod, &data, stats, pso_key);
+            }
+        })
+    }
+
+    /// Draws textured triangles with a tint color per vertex,
+    /// e.g. a batch of sprites with different tints in one draw call.
+    ///
+    /// Like for `tri_list_uv`, the closure passes vertices in normalized device
+    /// coordinates and texture coordinates, plus one color per vertex.
+    /// The colors are tints with the global tint applied.
+    pub fn tri_list_uv_c<F>(
+        &mut self,
+        draw_state: &DrawState,
+        texture: &Texture<R>,
+        mut f: F
+    )
+        where F: FnMut(&mut dyn FnMut(&[[f32; 2]], &[[f32; 2]], &[[f32; 4]]))
+    {
+        let draw_state = &self.effective_draw_state(draw_state);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        // Colors are converted like by `tint_color`, which borrows `self`.
+        let linear = self.g2d.linear_tint || self.assume_linear_colors;
+        let global_tint = self.g2d.global_tint;
+        let tint = move |color: [f32; 4]| {
+            let c = if linear {color} else {gamma_srgb_to_linear(color)};
+            let t = global_tint;
+            [c[0] * t[0], c[1] * t[1], c[2] * t[2], c[3] * t[3]]
+        };
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut buffer_color,
+                ref mut textured_color,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_textured_color, stencil_val, pso_key) = textured_color.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_textured_color::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color: buffer_color.clone(),
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        f(&mut |vertices: &[[f32; 2]], texture_coords: &[[f32; 2]], colors: &[[f32; 4]]| {
+            assert_eq!(vertices.len(), texture_coords.len());
+            assert_eq!(vertices.len(), colors.len());
+            // Only whole triangles are drawn per chunk.
+            let chunk = BUFFER_SIZE - BUFFER_SIZE % 3;
+            for ((vertices, texture_coords), colors) in vertices.chunks(chunk)
+                .zip(texture_coords.chunks(chunk))
+                .zip(colors.chunks(chunk))
+            {
+                let positions: Vec<_> = vertices.iter()
+                    .map(|&pos| PositionFormat { pos }).collect();
+                let uvs: Vec<_> = texture_coords.iter()
+                    .map(|&uv| TexCoordsFormat { uv }).collect();
+                let colors: Vec<_> = colors.iter()
+                    .map(|&color| ColorFormat { color: tint(color) }).collect();
+                encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+                encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+                encoder.update_buffer(buffer_color, &colors, 0).unwrap();
+                draw_slice(encoder, positions.len(), pso_textured_color, &data, stats, pso_key);
+            }
+        })
+    }
+
+    /// Draws textured triangles with a multiplied and an added color,
+    /// e.g. to flash a sprite white on damage while darkening it.
+    ///
+    /// The result is `texel * mul + add`, clamped to `[0, 1]` per channel.
+    /// The multiplied color is a tint with the global tint applied, and
+    /// the added color is converted to linear like tints, without the global tint.
+    /// Use zero alpha in the added color to keep the transparency of the texture.
+    pub fn tri_list_uv_mul_add<F>(
+        &mut self,
+        draw_state: &DrawState,
+        mul: &[f32; 4],
+        add: &[f32; 4],
+        texture: &Texture<R>,
+        mut f: F
+    )
+        where F: FnMut(&mut dyn FnMut(&[[f32; 2]], &[[f32; 2]]))
+    {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let mul = self.tint_color(mul);
+        let add = if self.g2d.linear_tint {*add} else {self.linear_color(*add)};
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut mul_add,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_mul_add, stencil_val, pso_key) = mul_add.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_mul_add::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            mul,
+            add,
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        f(&mut |vertices: &[[f32; 2]], texture_coords: &[[f32; 2]]| {
+            assert_eq!(vertices.len(), texture_coords.len());
+            // Only whole triangles are drawn per chunk.
+            let chunk = BUFFER_SIZE - BUFFER_SIZE % 3;
+            for (vertices, texture_coords) in vertices.chunks(chunk)
+                .zip(texture_coords.chunks(chunk))
+            {
+                let positions: Vec<_> = vertices.iter()
+                    .map(|&pos| PositionFormat { pos }).collect();
+                let uvs: Vec<_> = texture_coords.iter()
+                    .map(|&uv| TexCoordsFormat { uv }).collect();
+                encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+                encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+                draw_slice(encoder, positions.len(), pso_mul_add, &data, stats, pso_key);
+            }
+        })
+    }
+
+    /// Draws textured triangles where texels matching a color key are transparent,
+    /// e.g. for legacy sprite sheets that use magenta as background.
+    ///
+    /// Texels within the distance `tolerance` of the key color in linear RGB
+    /// are discarded, which also catches key colors blended into edges by filtering.
+    /// Filtering still blends the key color into neighbouring texels,
+    /// so this works best with nearest filtering and a small tolerance.
+    pub fn tri_list_uv_color_key<F>(
+        &mut self,
+        key_color: &[f32; 4],
+        tolerance: f32,
+        draw_state: &DrawState,
+        color: &[f32; 4],
+        texture: &Texture<R>,
+        mut f: F
+    )
+        where F: FnMut(&mut dyn FnMut(&[[f32; 2]], &[[f32; 2]]))
+    {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(color);
+        let key = self.linear_color(*key_color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut color_key,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_color_key, stencil_val, pso_key) = color_key.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_color_key::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            key: [key[0], key[1], key[2]],
+            tolerance,
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        f(&mut |vertices: &[[f32; 2]], texture_coords: &[[f32; 2]]| {
+            assert_eq!(vertices.len(), texture_coords.len());
+            // Only whole triangles are drawn per chunk.
+            let chunk = BUFFER_SIZE - BUFFER_SIZE % 3;
+            for (vertices, texture_coords) in vertices.chunks(chunk)
+                .zip(texture_coords.chunks(chunk))
+            {
+                let positions: Vec<_> = vertices.iter()
+                    .map(|&pos| PositionFormat { pos }).collect();
+                let uvs: Vec<_> = texture_coords.iter()
+                    .map(|&uv| TexCoordsFormat { uv }).collect();
+                encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+                encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+                draw_slice(encoder, positions.len(), pso_color_key, &data, stats, pso_key);
+            }
+        })
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]` with a hue range removed,
+    /// e.g. to composite a video recorded in front of a green screen.
+    ///
+    /// Texels with hues close to the key hue become transparent, except grays,
+    /// and the key hue is removed from the remaining texels by the despill strength
+    /// so that edges and reflections lose the tint of the screen.
+    pub fn chroma_key(
+        &mut self,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        key: &ChromaKey,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(&[1.0; 4]);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut chroma_key,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_chroma_key, stencil_val, pso_key) = chroma_key.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let hue = key.hue.to_radians();
+        let data = pipe_chroma_key::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            key_dir: [hue.cos(), hue.sin()],
+            tolerance: key.tolerance.max(0.0).to_radians(),
+            // Keeps `smoothstep` defined for a hard edge.
+            softness: key.softness.to_radians().max(1e-5),
+            despill: key.despill.clamp(0.0, 1.0),
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        let (positions, uvs) = quad(dst_rect, transform);
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_chroma_key, &data, stats, pso_key);
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]`.
+    ///
+    /// The sprite is tinted, flipped and rotated around the origin
+    /// according to the options, then transformed by `transform`.
+    pub fn draw_sprite(
+        &mut self,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        options: &SpriteOptions,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let (vertices, uvs) = options.quad(dst_rect, transform);
+        self.tri_list_uv(draw_state, &options.tint, texture, |f| f(&vertices, &uvs));
+    }
+
+    /// Draws a texture with its anchor at a position, scaled and rotated around the anchor.
+    ///
+    /// The sprite has the size of the texture in pixels times the scale.
+    /// For example, a character anchored at `SpriteAnchor::BOTTOM_CENTER`
+    /// stands on its position, whatever its rotation.
+    pub fn draw_sprite_anchored(
+        &mut self,
+        texture: &Texture<R>,
+        placement: &SpritePlacement,
+        tint: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::ImageSize;
+        use graphics::math::{multiply, transform_pos};
+
+        let (tw, th) = texture.get_size();
+        let (tw, th) = (tw as f64, th as f64);
+        let transform = multiply(transform, placement.transform([tw, th]));
+        let corner = |u: f64, v: f64| {
+            let p = transform_pos(transform, [u * tw, v * th]);
+            [p[0] as f32, p[1] as f32]
+        };
+        let vertices = [
+            corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0),
+            corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)
+        ];
+        let uvs = [
+            [0.0, 0.0], [1.0, 0.0], [0.0, 1.0],
+            [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]
+        ];
+        self.tri_list_uv(draw_state, tint, texture, |f| f(&vertices, &uvs));
+    }
+
+    /// Draws with a pipeline defined by the app, see `Gfx2d::custom_pipeline`.
+    ///
+    /// The PSO is picked from the blend and stencil setting of the draw state.
+    /// The closure builds the pipeline data from the current render targets and scissor.
+    pub fn draw_custom<M, D, Fd>(
+        &mut self,
+        pipeline: &mut CustomPipeline<R, M>,
+        slice: &gfx::Slice<R>,
+        draw_state: &DrawState,
+        data: Fd
+    )
+        where D: gfx::pso::PipelineData<R, Meta = M>,
+              Fd: FnOnce(CustomTargets<R>) -> D
+    {
+        let draw_state = &self.effective_draw_state(draw_state);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        pipeline.0.in_layer = self.g2d.in_layer;
+        let (pso, stencil_val, pso_key) = pipeline.0.stencil_blend(
+            self.clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+        let data = data(CustomTargets {
+            color: self.output_color.clone(),
+            stencil: (self.output_stencil.clone(), (stencil_val, stencil_val)),
+            scissor: scissor_rect(draw_state.scissor, self.g2d.clip_region),
+        });
+        self.encoder.draw(slice, pso, &data);
+        self.g2d.stats.record(pso_key, (slice.end - slice.start) as usize);
+    }
+
+    /// Fills the rectangle `[x, y, w, h]` with the default draw state.
+    pub fn rectangle_default(&mut self, color: &[f32; 4], rect: [f64; 4], transform: Matrix2d) {
+        let draw_state = self.default_draw_state;
+        graphics::Rectangle::new(*color).draw(rect, &draw_state, transform, self);
+    }
+
+    /// Draws a texture at its size with the default draw state.
+    pub fn image_default(&mut self, texture: &Texture<R>, transform: Matrix2d) {
+        let draw_state = self.default_draw_state;
+        graphics::Image::new().draw(texture, &draw_state, transform, self);
+    }
+
+    /// Fills an ellipse like `ellipse` with the default draw state.
+    pub fn ellipse_default(
+        &mut self,
+        color: &[f32; 4],
+        center: [f64; 2],
+        radii: [f64; 2],
+        transform: Matrix2d
+    ) {
+        let draw_state = self.default_draw_state;
+        self.ellipse(color, center, radii, transform, &draw_state);
+    }
+
+    /// Fills a convex polygon with antialiased edges, without multisampling.
+    ///
+    /// The polygon is inset by half a physical pixel and surrounded by a ribbon
+    /// one pixel wide, fading from the color to transparent, like in NanoVG.
+    /// This needs a blend setting with alpha, like `Blend::Alpha`.
+    /// Like `graphics::Polygon`, concave polygons are not filled correctly,
+    /// and corners sharper than about 30 degrees are blunted.
+    pub fn fill_polygon_aa(
+        &mut self,
+        color: &[f32; 4],
+        polygon: &[[f64; 2]],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::math::transform_pos;
+
+        let n = polygon.len();
+        if n < 3 {return};
+        // Normalized device coordinates span the viewport.
+        let (tw, th) = match self.viewport {
+            Some(viewport) => (viewport.rect[2] as f64, viewport.rect[3] as f64),
+            None => {
+                let (tw, th, _, _) = self.output_color.get_dimensions();
+                (tw as f64, th as f64)
+            }
+        };
+        // Works in pixels from the upper left corner, to offset by whole pixels.
+        let points: Vec<[f64; 2]> = polygon.iter().map(|&p| {
+            let p = transform_pos(transform, p);
+            [(p[0] + 1.0) * 0.5 * tw, (1.0 - p[1]) * 0.5 * th]
+        }).collect();
+        let (inner, outer) = aa_fringe(&points);
+        if inner.is_empty() {return};
+        let to_ndc = |p: [f64; 2]| [(p[0] / tw * 2.0 - 1.0) as f32, (1.0 - p[1] / th * 2.0) as f32];
+        let inner: Vec<_> = inner.into_iter().map(to_ndc).collect();
+        let outer: Vec<_> = outer.into_iter().map(to_ndc).collect();
+
+        let c = self.draw_color(color);
+        let t = [c[0], c[1], c[2], 0.0];
+        let mut vertices = Vec::with_capacity(9 * n);
+        let mut colors = Vec::with_capacity(9 * n);
+        for i in 1..n - 1 {
+            vertices.extend_from_slice(&[inner[0], inner[i], inner[i + 1]]);
+            colors.extend_from_slice(&[c, c, c]);
+        }
+        for i in 0..n {
+            let j = (i + 1) % n;
+            vertices.extend_from_slice(&[
+                inner[i], outer[i], outer[j],
+                inner[i], outer[j], inner[j]
+            ]);
+            colors.extend_from_slice(&[c, t, t, c, t, c]);
+        }
+        self.tri_list_colors(draw_state, &vertices, &colors);
+    }
+
+    /// Draws the outline of the rectangle `[x, y, w, h]`, inside its bounds.
+    ///
+    /// The edges and thickness are rounded to whole physical pixels with the DPI scale,
+    /// so borders are crisp if the transform maps to logical pixels without
+    /// fractional offsets, e.g. the transform of the context. The thickness is at least one pixel.
+    /// The four sides do not overlap, so translucent corners are not drawn twice.
+    pub fn rectangle_outline(
+        &mut self,
+        color: &[f32; 4],
+        rect: [f64; 4],
+        thickness: f64,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::math::transform_pos;
+
+        let s = self.g2d.dpi_scale as f64;
+        let x1 = (rect[0] * s).round();
+        let y1 = (rect[1] * s).round();
+        let x2 = ((rect[0] + rect[2]) * s).round();
+        let y2 = ((rect[1] + rect[3]) * s).round();
+        if x2 <= x1 || y2 <= y1 {return};
+        let t = (thickness * s).round().max(1.0).min(0.5 * (x2 - x1)).min(0.5 * (y2 - y1));
+
+        let mut vertices = Vec::with_capacity(24);
+        let mut quad = |x1: f64, y1: f64, x2: f64, y2: f64| {
+            if x2 <= x1 || y2 <= y1 {return};
+            // The coordinates are in physical pixels, and the transform maps logical pixels.
+            let corner = |x: f64, y: f64| {
+                let p = transform_pos(transform, [x / s, y / s]);
+                [p[0] as f32, p[1] as f32]
+            };
+            vertices.extend_from_slice(&[
+                corner(x1, y1), corner(x2, y1), corner(x1, y2),
+                corner(x2, y1), corner(x2, y2), corner(x1, y2)
+            ]);
+        };
+        quad(x1, y1, x2, y1 + t);
+        quad(x1, y2 - t, x2, y2);
+        quad(x1, y1 + t, x1 + t, y2 - t);
+        quad(x2 - t, y1 + t, x2, y2 - t);
+        self.tri_list(draw_state, color, |f| f(&vertices));
+    }
+
+    /// Fills an ellipse with radii `[rx, ry]` around a center.
+    ///
+    /// The number of segments is chosen from the larger radius
+    /// with `Gfx2d::curve_resolution`, so the transform should not scale much.
+    /// Nothing is drawn if a radius is zero or negative.
+    pub fn ellipse(
+        &mut self,
+        color: &[f32; 4],
+        center: [f64; 2],
+        radii: [f64; 2],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use std::f64::consts::PI;
+        use graphics::math::transform_pos;
+
+        let [rx, ry] = radii;
+        if !(rx > 0.0 && ry > 0.0 && rx.is_finite() && ry.is_finite()) {return};
+        let n = self.g2d.curve_resolution(rx.max(ry));
+        let point = |i: u32| {
+            let angle = 2.0 * PI * i as f64 / n as f64;
+            let p = [center[0] + rx * angle.cos(), center[1] + ry * angle.sin()];
+            let p = transform_pos(transform, p);
+            [p[0] as f32, p[1] as f32]
+        };
+        let c = transform_pos(transform, center);
+        let c = [c[0] as f32, c[1] as f32];
+        let mut vertices = Vec::with_capacity(3 * n as usize);
+        for i in 0..n {
+            vertices.extend_from_slice(&[c, point(i), point(i + 1)]);
+        }
+        self.tri_list(draw_state, color, |f| f(&vertices));
+    }
+
+    /// Fills several triangle strips with one color, batched into one draw call.
+    ///
+    /// There is no strip pipeline, so the strips are converted to triangle lists,
+    /// which keeps them disconnected without primitive restart or degenerate triangles.
+    /// The vertices are in shape coordinates and transformed by `transform`.
+    /// Strips with less than three vertices are skipped.
+    pub fn draw_strips(
+        &mut self,
+        color: &[f32; 4],
+        strips: &[&[[f64; 2]]],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::math::transform_pos;
+        let n = strips.iter().map(|strip| 3 * strip.len().saturating_sub(2)).sum();
+        let mut vertices: Vec<[f32; 2]> = Vec::with_capacity(n);
+        for strip in strips {
+            let strip: Vec<[f32; 2]> = strip.iter().map(|&p| {
+                let p = transform_pos(transform, p);
+                [p[0] as f32, p[1] as f32]
+            }).collect();
+            for (i, tri) in strip.windows(3).enumerate() {
+                // Every second triangle of a strip has the opposite winding.
+                if i % 2 == 0 {
+                    vertices.extend_from_slice(tri);
+                } else {
+                    vertices.extend_from_slice(&[tri[1], tri[0], tri[2]]);
+                }
+            }
+        }
+        self.tri_list(draw_state, color, |f| f(&vertices));
+    }
+
+    /// Draws a textured mesh from a vertex buffer owned by the app.
+    ///
+    /// The slice selects the vertices and holds the index buffer, if any.
+    /// Nothing is uploaded, so static meshes like tilemap chunks
+    /// can be uploaded once, e.g. with `create_vertex_buffer_with_slice`.
+    /// The vertices are `MeshVertex`es in triangle list order,
+    /// transformed by `transform` on the GPU.
+    pub fn draw_mesh(
+        &mut self,
+        vbuf: &gfx::handle::Buffer<R, MeshVertex>,
+        slice: &gfx::Slice<R>,
+        texture: &Texture<R>,
+        color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let m = transform;
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut mesh,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_mesh, stencil_val, pso_key) = mesh.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_mesh::Data {
+            vbuf: vbuf.clone(),
+            transform_x: [m[0][0] as f32, m[0][1] as f32, m[0][2] as f32],
+            transform_y: [m[1][0] as f32, m[1][1] as f32, m[1][2] as f32],
+            color,
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        encoder.draw(slice, pso_mesh, &data);
+        stats.record(pso_key, (slice.end - slice.start) as usize);
+    }
+
+    /// Draws a colored mesh from a position and a color buffer owned by the app.
+    ///
+    /// Like `draw_mesh`, the slice selects the vertices and holds the index buffer,
+    /// and the positions are transformed by `transform` on the GPU.
+    /// The color buffer has one `MeshColor` per vertex, at the same index
+    /// as its `MeshPosition`, so static positions like those of a tilemap
+    /// can be uploaded once while the colors are updated, e.g. for fog of war.
+    /// Colors are linear, since they are not converted on the GPU,
+    /// and are multiplied by the global tint.
+    pub fn draw_mesh_colored(
+        &mut self,
+        vbuf: &gfx::handle::Buffer<R, MeshPosition>,
+        color_buf: &gfx::handle::Buffer<R, MeshColor>,
+        slice: &gfx::Slice<R>,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let m = transform;
+        let draw_state = &self.effective_draw_state(draw_state);
+        let tint = self.g2d.apply_global_tint([1.0; 4]);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut mesh_colored,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_mesh_colored, stencil_val, pso_key) = mesh_colored.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_mesh_colored::Data {
+            pos: vbuf.clone(),
+            color: color_buf.clone(),
+            transform_x: [m[0][0] as f32, m[0][1] as f32, m[0][2] as f32],
+            transform_y: [m[1][0] as f32, m[1][1] as f32, m[1][2] as f32],
+            tint,
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        encoder.draw(slice, pso_mesh_colored, &data);
+        stats.record(pso_key, (slice.end - slice.start) as usize);
+    }
+
+    /// Draws many sprites sharing one texture, e.g. an atlas, with instancing.
+    ///
+    /// A unit quad is drawn once per instance, with the transform, texture coordinates
+    /// and tint of the instance, so the vertices are not built on the CPU.
+    /// Up to 1024 instances are drawn per draw call, more are split.
+    /// Requires instancing support, which is core since OpenGL 3.1.
+    pub fn draw_instanced_sprites(
+        &mut self,
+        texture: &Texture<R>,
+        instances: &[SpriteInstance],
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let data: Vec<SpriteInstanceFormat> = instances.iter().map(|instance| {
+            let m = instance.transform;
+            SpriteInstanceFormat {
+                transform_x: [m[0][0] as f32, m[0][1] as f32, m[0][2] as f32],
+                transform_y: [m[1][0] as f32, m[1][1] as f32, m[1][2] as f32],
+                uv_rect: instance.uv,
+                tint: self.tint_color(&instance.tint),
+            }
+        }).collect();
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref buffer_quad,
+                ref mut buffer_instances,
+                ref mut instanced_sprite,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_instanced_sprite, stencil_val, pso_key) = instanced_sprite.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let pipe_data = pipe_instanced_sprite::Data {
+            pos: buffer_quad.clone(),
+            instance: buffer_instances.clone(),
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        for data in data.chunks(MAX_INSTANCES) {
+            encoder.update_buffer(buffer_instances, data, 0).unwrap();
+
+            let slice = gfx::Slice {
+                instances: Some((data.len() as u32, 0)),
+                start: 0,
+                end: 6,
+                buffer: gfx::IndexBuffer::Auto,
+                base_vertex: 0,
+            };
+            encoder.draw(&slice, pso_instanced_sprite, &pipe_data);
+            stats.record(pso_key, 6 * data.len());
+        }
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]` with rounded corners,
+    /// e.g. for avatars and thumbnails.
+    ///
+    /// The radius is in the units of the rectangle and is limited to half its size.
+    /// The corners are anti-aliased, so the draw state should blend.
+    pub fn rounded_image(
+        &mut self,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        radius: f64,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        self.rounded_image_corners(texture, dst_rect, [radius; 4], transform, draw_state);
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]` with a radius per corner.
+    ///
+    /// The radii are for the upper left, upper right, lower left and lower right
+    /// corners, see `rounded_image`.
+    pub fn rounded_image_corners(
+        &mut self,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        radii: [f64; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(&[1.0; 4]);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut rounded_image,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_rounded_image, stencil_val, pso_key) = rounded_image.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let [_, _, w, h] = dst_rect;
+        let max_radius = 0.5 * w.abs().min(h.abs());
+        let radius = |r: f64| r.max(0.0).min(max_radius) as f32;
+        let data = pipe_rounded_image::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            size: [w.abs() as f32, h.abs() as f32],
+            radii: [radius(radii[0]), radius(radii[1]), radius(radii[2]), radius(radii[3])],
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        let (positions, uvs) = quad(dst_rect, transform);
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_rounded_image, &data, stats, pso_key);
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]` with an outline
+    /// around its opaque texels, e.g. to highlight a selected sprite.
+    ///
+    /// The outline width is in texels and clamped to 16 texels,
+    /// since every fragment samples the texture 33 times.
+    /// The outline grows outside of the rectangle.
+    pub fn image_outlined(
+        &mut self,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        outline_color: &[f32; 4],
+        outline_width: f64,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::ImageSize;
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(&[1.0; 4]);
+        let outline_color = self.tint_color(outline_color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut outline,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_outline, stencil_val, pso_key) = outline.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let (tw, th) = texture.get_size();
+        let (tw, th) = (tw.max(1) as f64, th.max(1) as f64);
+        let width = (outline_width as f32).clamp(0.0, MAX_OUTLINE_WIDTH);
+        let data = pipe_outline::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            outline_color,
+            texel: [1.0 / tw as f32, 1.0 / th as f32],
+            width,
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        // Grow the rectangle and texture coordinates by the outline width.
+        let [x, y, w, h] = dst_rect;
+        let (mu, mv) = (width as f64 / tw, width as f64 / th);
+        let grown = [x - mu * w, y - mv * h, w * (1.0 + 2.0 * mu), h * (1.0 + 2.0 * mv)];
+        let (positions, mut uvs) = quad(grown, transform);
+        for uv in &mut uvs {
+            uv.uv = [
+                uv.uv[0] * (1.0 + 2.0 * mu) as f32 - mu as f32,
+                uv.uv[1] * (1.0 + 2.0 * mv) as f32 - mv as f32,
+            ];
+        }
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_outline, &data, stats, pso_key);
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]`, tinted by corner colors.
+    ///
+    /// The colors are for the upper left, upper right, lower left and lower right
+    /// corners, and are interpolated bilinearly across the rectangle,
+    /// e.g. for vignettes or directional tinting.
+    pub fn image_gradient(
+        &mut self,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        corner_colors: [[f32; 4]; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let colors = [
+            self.tint_color(&corner_colors[0]),
+            self.tint_color(&corner_colors[1]),
+            self.tint_color(&corner_colors[2]),
+            self.tint_color(&corner_colors[3]),
+        ];
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut gradient,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_gradient, stencil_val, pso_key) = gradient.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_gradient::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color_upper_left: colors[0],
+            color_upper_right: colors[1],
+            color_lower_left: colors[2],
+            color_lower_right: colors[3],
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        let (positions, uvs) = quad(dst_rect, transform);
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_gradient, &data, stats, pso_key);
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]`, faded by a mask texture.
+    ///
+    /// The mask covers the same rectangle.
+    /// Its red channel times alpha is used as coverage,
+    /// so both grayscale and alpha masks work, e.g. for vignettes or feathered edges.
+    pub fn draw_masked(
+        &mut self,
+        texture: &Texture<R>,
+        mask: &Texture<R>,
+        rect: [f64; 4],
+        color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut masked,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_masked, stencil_val, pso_key) = masked.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_masked::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            mask: (mask.view.clone(), mask.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        let (positions, uvs) = quad(rect, transform);
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_masked, &data, stats, pso_key);
+    }
+
+    /// Draws grid lines within the rectangle `[x, y, w, h]`.
+    ///
+    /// Lines are at multiples of the cell size, so the grid stays in place
+    /// when the bounds move, e.g. to the visible area of a scrolling editor.
+    /// Only lines within the bounds are drawn, batched into one draw call
+    /// for minor and one for major lines. At most 4096 lines are drawn along each axis,
+    /// and nothing is drawn for bounds or cell sizes that are not finite.
+    pub fn grid(
+        &mut self,
+        bounds: [f64; 4],
+        style: &GridStyle,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let [x, y, w, h] = bounds;
+        let [cw, ch] = style.cell_size;
+        let finite = bounds.iter().chain(&style.cell_size).all(|v| v.is_finite());
+        if !(finite && cw > 0.0 && ch > 0.0) || w <= 0.0 || h <= 0.0 {return};
+
+        let is_major = |i: f64| {
+            style.major_every > 0 && i.rem_euclid(style.major_every as f64) == 0.0
+        };
+        let mut minor = vec![];
+        let mut major = vec![];
+        let (first, n) = grid_lines(x, w, cw);
+        for k in 0..n {
+            let i = first + k as f64;
+            let line = [i * cw, y, i * cw, y + h];
+            if is_major(i) {major.push(line)} else {minor.push(line)}
+        }
+        let (first, n) = grid_lines(y, h, ch);
+        for k in 0..n {
+            let i = first + k as f64;
+            let line = [x, i * ch, x + w, i * ch];
+            if is_major(i) {major.push(line)} else {minor.push(line)}
+        }
+        self.draw_aa_lines(&minor, style.width, &style.color, transform, draw_state);
+        self.draw_aa_lines(&major, style.width, &style.major_color, transform, draw_state);
+    }
+
+    /// Draws anti-aliased line segments `[x1, y1, x2, y2]` with a width in pixels.
+    ///
+    /// Each segment is expanded to a quad, and the edge coverage is computed
+    /// per pixel from the distance to the line center, so no MSAA is needed.
+    /// Lines thinner than one pixel are drawn one pixel wide with reduced alpha,
+    /// so they stay visible.
+    pub fn draw_aa_lines(
+        &mut self,
+        segments: &[[f64; 4]],
+        width: f64,
+        color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::math::transform_pos;
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.draw_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let (w, h, _, _) = self.output_color.get_dimensions();
+        let (w, h) = (w as f64, h as f64);
+        let half_width = 0.5 * width.max(1.0);
+        let alpha_scale = width.clamp(0.0, 1.0);
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut aa_line,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_aa_line, stencil_val, pso_key) = aa_line.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_aa_line::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            half_width: half_width as f32,
+            alpha_scale: alpha_scale as f32,
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        // Expand by one pixel for the anti-aliased edge.
+        let extent = half_width + 1.0;
+        let to_pixels = |p: [f64; 2]| [(p[0] + 1.0) * 0.5 * w, (1.0 - p[1]) * 0.5 * h];
+        let to_ndc = |p: [f64; 2]| [(2.0 * p[0] / w - 1.0) as f32, (1.0 - 2.0 * p[1] / h) as f32];
+
+        // Only whole quads are drawn per chunk.
+        let chunk = (BUFFER_SIZE - BUFFER_SIZE % 6) / 6;
+        let mut positions: Vec<PositionFormat> = Vec::with_capacity(chunk * 6);
+        let mut uvs: Vec<TexCoordsFormat> = Vec::with_capacity(chunk * 6);
+        for segments in segments.chunks(chunk) {
+            positions.clear();
+            uvs.clear();
+            for s in segments {
+                let a = to_pixels(transform_pos(transform, [s[0], s[1]]));
+                let b = to_pixels(transform_pos(transform, [s[2], s[3]]));
+                let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+                let len = (dx * dx + dy * dy).sqrt();
+                if len == 0.0 {continue};
+                let n = [-dy / len * extent, dx / len * extent];
+                let corners = [
+                    ([a[0] + n[0], a[1] + n[1]], extent),
+                    ([b[0] + n[0], b[1] + n[1]], extent),
+                    ([a[0] - n[0], a[1] - n[1]], -extent),
+                    ([b[0] + n[0], b[1] + n[1]], extent),
+                    ([b[0] - n[0], b[1] - n[1]], -extent),
+                    ([a[0] - n[0], a[1] - n[1]], -extent),
+                ];
+                for &(p, d) in &corners {
+                    positions.push(PositionFormat { pos: to_ndc(p) });
+                    uvs.push(TexCoordsFormat { uv: [d as f32, 0.0] });
+                }
+            }
+            if positions.is_empty() {continue};
+            encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+            encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+            draw_slice(encoder, positions.len(), pso_aa_line, &data, stats, pso_key);
+        }
+    }
+
+    /// Fills the rectangle `[x, y, w, h]` with a checkerboard,
+    /// e.g. as a backdrop for transparent images.
+    ///
+    /// The cells are square with a size in pixels and aligned to the screen,
+    /// so the pattern stays crisp at any resolution.
+    pub fn checkerboard(
+        &mut self,
+        rect: [f64; 4],
+        cell_size: f32,
+        color_a: &[f32; 4],
+        color_b: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color_a = self.draw_color(color_a);
+        let color_b = self.draw_color(color_b);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut checkerboard,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_checkerboard, stencil_val, pso_key) = checkerboard.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_checkerboard::Data {
+            pos: buffer_pos.clone(),
+            color_a,
+            color_b,
+            cell_size: cell_size.max(1.0),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        let (positions, _) = quad(rect, transform);
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_checkerboard, &data, stats, pso_key);
+    }
+
+    /// Fills the rectangle `[x, y, w, h]` at a depth, occluded by the depth buffer.
+    ///
+    /// Draws with a larger `z` in the range `0.0..=1.0` hide draws with a smaller `z`,
+    /// whatever the order they are drawn in, so opaque layers need no sorting.
+    /// Clear the depth to `1.0` with `clear_depth` first, which is the depth of `z = 0.0`.
+    /// Blended draws still write depth and hide what is drawn behind them later,
+    /// so transparent draws must be sorted, e.g. with `DrawQueue`.
+    pub fn rectangle_depth(
+        &mut self,
+        color: &[f32; 4],
+        rect: [f64; 4],
+        z: f32,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.draw_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut depth_colored,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_depth, stencil_val, pso_key) = depth_colored.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_depth_colored::Data {
+            pos: buffer_pos.clone(),
+            depth: depth_ndc(z),
+            color,
+            blend_target: output_color.clone(),
+            depth_stencil_target: (output_stencil.clone(),
+                                   (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        let (positions, _) = quad(rect, transform);
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_depth, &data, stats, pso_key);
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]` at a depth,
+    /// occluded by the depth buffer, see `rectangle_depth`.
+    ///
+    /// Texels with alpha below `0.5` are discarded and write no depth,
+    /// so cutout sprites can be drawn in any order.
+    pub fn image_depth(
+        &mut self,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        z: f32,
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(&[1.0; 4]);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut depth_textured,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_depth, stencil_val, pso_key) = depth_textured.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_depth_textured::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            depth: depth_ndc(z),
+            color,
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            depth_stencil_target: (output_stencil.clone(),
+                                   (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        let (positions, uvs) = quad(dst_rect, transform);
+        encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+        encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+        draw_slice(encoder, positions.len(), pso_depth, &data, stats, pso_key);
+    }
+
+    /// Blends procedural film grain over the whole render target.
+    ///
+    /// The grain is white noise per physical pixel, mixed in with alpha `intensity`
+    /// in the range `0.0..=1.0`. Pass a different `seed` each frame for moving grain,
+    /// or the same seed for a static pattern. Stencil and scissor of `draw_state`
+    /// are respected, but the grain is always alpha blended.
+    pub fn apply_grain(&mut self, intensity: f32, seed: u32, draw_state: &DrawState) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref buffer_full_screen,
+                ref mut grain,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_grain, stencil_val, pso_key) = grain.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            Some(draw_state::Blend::Alpha)
+        );
+
+        let data = pipe_grain::Data {
+            pos: buffer_full_screen.clone(),
+            intensity: intensity.clamp(0.0, 1.0),
+            // Keeps the seed small enough to be exact in a float.
+            seed: (seed % 65536) as f32,
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        encoder.draw(&full_screen_slice(), pso_grain, &data);
+        stats.record(pso_key, 3);
+    }
+
+    /// Darkens or tints the edges of the whole render target.
+    ///
+    /// The distance from the center is `1.0` at the middle of the edges and about `1.41`
+    /// in the corners. Up to `radius` the target is unaffected, then `color` fades in
+    /// over `smoothness` up to alpha `intensity` in the range `0.0..=1.0`,
+    /// times the alpha of `color`. Stencil and scissor of `draw_state` are respected,
+    /// but the vignette is always alpha blended, so it composes with other effects.
+    pub fn vignette(
+        &mut self,
+        intensity: f32,
+        radius: f32,
+        smoothness: f32,
+        color: &[f32; 4],
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let (color, smoothness) = vignette_uniforms(self.draw_color(color), intensity, smoothness);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let (tw, th, _, _) = self.output_color.get_dimensions();
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref buffer_full_screen,
+                ref mut vignette,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_vignette, stencil_val, pso_key) = vignette.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            Some(draw_state::Blend::Alpha)
+        );
+
+        let data = pipe_vignette::Data {
+            pos: buffer_full_screen.clone(),
+            color,
+            size: [tw as f32, th as f32],
+            radius,
+            smoothness,
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
 
-        let glsl = opengl.to_glsl();
+        encoder.draw(&full_screen_slice(), pso_vignette, &data);
+        stats.record(pso_key, 3);
+    }
 
-        let colored_program = factory.link_program(
-                Shaders::new()
-                    .set(GLSL::V1_20, colored::VERTEX_GLSL_120)
-                    .set(GLSL::V1_50, colored::VERTEX_GLSL_150_CORE)
-                    .get(glsl).unwrap(),
-                Shaders::new()
-                    .set(GLSL::V1_20, colored::FRAGMENT_GLSL_120)
-                    .set(GLSL::V1_50, colored::FRAGMENT_GLSL_150_CORE)
-                    .get(glsl).unwrap(),
-            ).unwrap();
+    /// Draws a texture over the whole render target, dissolved by a noise texture,
+    /// e.g. for scene transitions.
+    ///
+    /// Texels where the red channel of the noise is at most `threshold` are hidden,
+    /// so animating the threshold from `0.0` to `1.0` dissolves the texture.
+    /// Texels with noise less than `edge_width` above the threshold are colored
+    /// with `edge_color`, fading out, for a burning edge. The threshold and edge width
+    /// are in the range of the noise, which is decoded from sRGB for sRGB textures.
+    /// Both textures are stretched over the target, with the upper left texel
+    /// in the upper left corner, and the blend setting of `draw_state` is used.
+    pub fn dissolve(
+        &mut self,
+        texture: &Texture<R>,
+        noise_texture: &Texture<R>,
+        threshold: f32,
+        edge_color: &[f32; 4],
+        edge_width: f32,
+        draw_state: &DrawState
+    ) {
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(&[1.0; 4]);
+        let edge_color = self.draw_color(edge_color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref buffer_full_screen,
+                ref mut dissolve,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
 
-        let colored_pipeline = |factory: &mut F,
-                                blend_preset: Blend,
-                                stencil: Stencil,
-                                color_mask: gfx::state::ColorMask|
-        -> PipelineState<R, pipe_colored::Meta> {
-            factory.create_pipeline_from_program(
-                &colored_program,
-                Primitive::TriangleList,
-                Rasterizer::new_fill(),
-                pipe_colored::Init {
-                    pos: (),
-                    color: (),
-                    blend_target: ("o_Color", color_mask, blend_preset),
-                    stencil_target: stencil,
-                    blend_ref: (),
-                    scissor: (),
-                }
-            ).unwrap()
+        let (pso_dissolve, stencil_val, pso_key) = dissolve.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_dissolve::Data {
+            pos: buffer_full_screen.clone(),
+            color,
+            edge_color,
+            threshold,
+            edge_width: edge_width.max(0.0),
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            noise: (noise_texture.view.clone(), noise_texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
         };
 
-        let colored = PsoStencil::new(factory, colored_pipeline);
+        encoder.draw(&full_screen_slice(), pso_dissolve, &data);
+        stats.record(pso_key, 3);
+    }
 
-        let textured_program = factory.link_program(
-                Shaders::new()
-                    .set(GLSL::V1_20, textured::VERTEX_GLSL_120)
-                    .set(GLSL::V1_50, textured::VERTEX_GLSL_150_CORE)
-                    .get(glsl).unwrap(),
-                Shaders::new()
-                    .set(GLSL::V1_20, textured::FRAGMENT_GLSL_120)
-                    .set(GLSL::V1_50, textured::FRAGMENT_GLSL_150_CORE)
-                    .get(glsl).unwrap()
-            ).unwrap();
+    /// Creates a texture downsampled from a texture with a box filter,
+    /// e.g. for minimaps, thumbnails or before blurring.
+    ///
+    /// Each pixel is the average of `factor` by `factor` texels, averaged in linear space.
+    /// The factor is clamped to `1..=16`, and the size is rounded up,
+    /// averaging fewer texels at the right and bottom edges.
+    /// The texture is an `Srgba8` render target with bilinear, clamped sampling,
+    /// which keeps the orientation of the source, for `Gfx2d` of any format.
+    pub fn downsample<F>(
+        &mut self,
+        factory: &mut F,
+        texture: &Texture<R>,
+        factor: u32
+    ) -> Result<Texture<R>, gfx::CombinedError>
+        where F: gfx::Factory<R>
+    {
+        use gfx::texture::{FilterMethod, SamplerInfo, WrapMode};
+        use graphics::ImageSize;
 
-        let textured_pipeline = |factory: &mut F,
-                                 blend_preset: Blend,
-                                 stencil: Stencil,
-                                 color_mask: gfx::state::ColorMask|
-        -> PipelineState<R, pipe_textured::Meta> {
-            factory.create_pipeline_from_program(
-                &textured_program,
-                Primitive::TriangleList,
-                Rasterizer::new_fill(),
-                pipe_textured::Init {
-                    pos: (),
-                    uv: (),
-                    color: "color",
-                    texture: "s_texture",
-                    blend_target: ("o_Color", color_mask, blend_preset),
-                    stencil_target: stencil,
-                    blend_ref: (),
-                    scissor: (),
-                }
-            ).unwrap()
+        let factor = factor.clamp(1, MAX_DOWNSAMPLE_FACTOR);
+        let (tw, th) = texture.get_size();
+        let (w, h) = (tw.div_ceil(factor), th.div_ceil(factor));
+        let (w, h) = (w.max(1) as u16, h.max(1) as u16);
+        let (surface, view, target) = factory.create_render_target::<Srgba8>(w, h)?;
+        let stencil = match self.g2d.downsample_stencil {
+            Some(ref stencil) if stencil.get_dimensions().0 == w &&
+                stencil.get_dimensions().1 == h => stencil.clone(),
+            _ => {
+                let stencil = factory.create_depth_stencil_view_only(w, h)?;
+                self.g2d.downsample_stencil = Some(stencil.clone());
+                stencil
+            }
         };
+        let sampler = factory.create_sampler(
+            SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
 
-        let textured = PsoStencil::new(factory, textured_pipeline);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
+            g2d: &mut Gfx2d {
+                ref buffer_full_screen,
+                ref mut downsample,
+                ref mut stats,
+                ..
+            },
+            ..
+        } = self;
 
-        let buffer_pos = factory.create_buffer(
-            BUFFER_SIZE * CHUNKS,
-            gfx::buffer::Role::Vertex,
-            gfx::memory::Usage::Dynamic,
-            gfx::memory::Bind::empty()
-        ).expect("Could not create `buffer_pos`");
-        let buffer_color = factory.create_buffer(
-            BUFFER_SIZE * CHUNKS,
-            gfx::buffer::Role::Vertex,
-            gfx::memory::Usage::Dynamic,
-            gfx::memory::Bind::empty()
-        ).expect("Could not create `buffer_color`");
-        let buffer_uv = factory.create_buffer(
-            BUFFER_SIZE,
-            gfx::buffer::Role::Vertex,
-            gfx::memory::Usage::Dynamic,
-            gfx::memory::Bind::empty()
-        ).expect("Could not create `buffer_uv`");
-        let buffer_indices = factory.create_buffer(
-            BUFFER_SIZE,
-            gfx::buffer::Role::Vertex,
-            gfx::memory::Usage::Dynamic,
-            gfx::memory::Bind::empty()
-        ).expect("Could not create `buffer_indices`");
+        let (pso_downsample, _, pso_key) = downsample.stencil_blend(ClipPlane::All, None, None);
 
-        Gfx2d {
-            colored_offset: 0,
-            colored_draw_state: Default::default(),
-            buffer_pos,
-            buffer_indices,
-            buffer_color,
-            buffer_uv,
-            colored,
-            textured,
-        }
+        let data = pipe_downsample::Data {
+            pos: buffer_full_screen.clone(),
+            factor: factor as i32,
+            src_size: [tw as f32, th as f32],
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: target,
+            stencil_target: (stencil, (0, 0)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(None, None),
+        };
+
+        encoder.draw(&full_screen_slice(), pso_downsample, &data);
+        stats.record(pso_key, 3);
+        Ok(Texture { surface, sampler, view })
     }
 
-    /// Renders graphics to a Gfx renderer.
-    pub fn draw<C, F, U>(
+    /// Draws a full-screen pass with a pipeline defined by the app,
+    /// e.g. for post-processing.
+    ///
+    /// The closure gets a cached triangle covering the viewport for a
+    /// `gfx::VertexBuffer<FullScreenVertex>`, so no vertices are uploaded.
+    /// Otherwise, this is like `draw_custom`.
+    pub fn full_screen_pass<M, D, Fd>(
         &mut self,
-        encoder: &mut gfx::Encoder<R, C>,
-        output_color: &gfx::handle::RenderTargetView<R, Srgba8>,
-        output_stencil: &gfx::handle::DepthStencilView<R, DepthStencil>,
-        viewport: Viewport,
-        f: F
-    ) -> U
-        where C: gfx::CommandBuffer<R>,
-              F: FnOnce(Context, &mut GfxGraphics<R, C>) -> U
+        pipeline: &mut CustomPipeline<R, M>,
+        draw_state: &DrawState,
+        data: Fd
+    )
+        where D: gfx::pso::PipelineData<R, Meta = M>,
+              Fd: FnOnce(gfx::handle::Buffer<R, FullScreenVertex>, CustomTargets<R>) -> D
     {
-        let ref mut g = GfxGraphics::new(
-            encoder,
+        let buffer = self.g2d.buffer_full_screen.clone();
+        self.draw_custom(pipeline, &full_screen_slice(), draw_state,
+            |targets| data(buffer, targets));
+    }
+
+    /// Draws points as soft, anti-aliased filled circles.
+    ///
+    /// The points and radius are transformed by `transform`.
+    /// Each point is drawn as a quad, since the point size of the
+    /// backend is limited and can not be set from shaders on all drivers.
+    pub fn draw_soft_points(
+        &mut self,
+        points: &[[f64; 2]],
+        radius: f64,
+        color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    ) {
+        use graphics::math::transform_pos;
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.draw_color(color);
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
+        let &mut GfxGraphics {
+            ref mut encoder,
             output_color,
             output_stencil,
-            self
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut soft_point,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_soft_point, stencil_val, pso_key) = soft_point.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
         );
-        let c = Context::new_viewport(viewport);
-        let res = f(c, g);
-        if g.g2d.colored_offset > 0 {
-            g.flush_colored();
+
+        let data = pipe_soft_point::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        let uvs = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let uvs: Vec<TexCoordsFormat> = uvs.iter().cycle()
+            .take(BUFFER_SIZE - BUFFER_SIZE % 6)
+            .map(|&uv| TexCoordsFormat { uv })
+            .collect();
+        encoder.update_buffer(buffer_uv, &uvs, 0).unwrap();
+
+        // Only whole point quads are drawn per chunk.
+        let mut positions: Vec<PositionFormat> = Vec::with_capacity(uvs.len());
+        for points in points.chunks(uvs.len() / 6) {
+            positions.clear();
+            for p in points {
+                let (x1, y1) = (p[0] - radius, p[1] - radius);
+                let (x2, y2) = (p[0] + radius, p[1] + radius);
+                for &corner in &[[x1, y1], [x2, y1], [x1, y2], [x2, y1], [x2, y2], [x1, y2]] {
+                    let pos = transform_pos(transform, corner);
+                    positions.push(PositionFormat { pos: [pos[0] as f32, pos[1] as f32] });
+                }
+            }
+            encoder.update_buffer(buffer_pos, &positions, 0).unwrap();
+            draw_slice(encoder, positions.len(), pso_soft_point, &data, stats, pso_key);
         }
-        res
     }
-}
 
-/// Used for rendering 2D graphics.
-pub struct GfxGraphics<'a, R, C>
-    where R: gfx::Resources + 'a,
-          C: gfx::CommandBuffer<R> + 'a,
-          R::Buffer: 'a,
-          R::Shader: 'a,
-          R::Program: 'a,
-          R::Texture: 'a,
-          R::Sampler: 'a
-{
-    /// Provide access to the `gfx::Encoder` in case a user needs to update textures for caching,
-    /// etc.
-    pub encoder: &'a mut gfx::Encoder<R, C>,
-    output_color: &'a gfx::handle::RenderTargetView<R, Srgba8>,
-    output_stencil: &'a gfx::handle::DepthStencilView<R, DepthStencil>,
-    g2d: &'a mut Gfx2d<R>,
-}
+    fn draw_sdf(
+        &mut self,
+        texture: &Texture<R>,
+        vertices: &[[f32; 2]],
+        uvs: &[[f32; 2]],
+        style: &SdfStyle,
+        draw_state: &DrawState
+    ) {
+        use std::slice::from_raw_parts;
 
-impl<'a, R, C> GfxGraphics<'a, R, C>
-    where R: gfx::Resources,
-          C: gfx::CommandBuffer<R>,
-{
-    /// Creates a new object for rendering 2D graphics.
-    pub fn new(encoder: &'a mut gfx::Encoder<R, C>,
-               output_color: &'a gfx::handle::RenderTargetView<R, Srgba8>,
-               output_stencil: &'a gfx::handle::DepthStencilView<R, DepthStencil>,
-               g2d: &'a mut Gfx2d<R>) -> Self {
-        GfxGraphics {
-            encoder: encoder,
-            output_color: output_color,
-            output_stencil: output_stencil,
-            g2d: g2d,
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.draw_color(&style.color);
+        let outline_color = self.draw_color(&style.outline_color);
+        let glow_color = self.draw_color(&style.glow_color);
+        let &mut GfxGraphics {
+            ref mut encoder,
+            output_color,
+            output_stencil,
+            g2d: &mut Gfx2d {
+                ref mut buffer_pos,
+                ref mut buffer_uv,
+                ref mut sdf,
+                ref mut stats,
+                clip_region,
+                ..
+            },
+            clip_plane,
+            ..
+        } = self;
+
+        let (pso_sdf, stencil_val, pso_key) = sdf.stencil_blend(
+            clip_plane,
+            draw_state.stencil,
+            draw_state.blend
+        );
+
+        let data = pipe_sdf::Data {
+            pos: buffer_pos.clone(),
+            uv: buffer_uv.clone(),
+            color,
+            outline_color,
+            outline_width: style.outline_width,
+            glow_color,
+            glow_width: style.glow_width,
+            texture: (texture.view.clone(), texture.sampler.clone()),
+            blend_target: output_color.clone(),
+            stencil_target: (output_stencil.clone(),
+                             (stencil_val, stencil_val)),
+            blend_ref: [1.0; 4],
+            scissor: scissor_rect(draw_state.scissor, clip_region),
+        };
+
+        // Only whole glyph quads are drawn per chunk.
+        let chunk = BUFFER_SIZE - BUFFER_SIZE % 6;
+        for (vertices, uvs) in vertices.chunks(chunk).zip(uvs.chunks(chunk)) {
+            let n = vertices.len();
+            unsafe {
+                encoder.update_buffer(
+                    buffer_pos,
+                    from_raw_parts(
+                        vertices.as_ptr() as *const PositionFormat,
+                        n
+                    ),
+                    0
+                ).unwrap();
+                encoder.update_buffer(
+                    buffer_uv,
+                    from_raw_parts(
+                        uvs.as_ptr() as *const TexCoordsFormat,
+                        n
+                    ),
+                    0
+                ).unwrap();
+            }
+
+            let slice = gfx::Slice {
+                instances: None,
+                start: 0,
+                end: n as u32,
+                buffer: gfx::IndexBuffer::Auto,
+                base_vertex: 0,
+            };
+            encoder.draw(&slice, pso_sdf, &data);
+            stats.record(pso_key, n);
         }
     }
 
-    /// Returns true if texture has alpha channel.
-    pub fn has_texture_alpha(&self, texture: &Texture<R>) -> bool
-        where R: gfx::Resources
-    {
-        texture.surface.get_info().format.get_alpha_stencil_bits() > 0
-    }
-
     fn flush_colored(&mut self) {
-        use draw_state::target::Rect;
-        use std::u16;
-
         let &mut GfxGraphics {
             ref mut encoder,
             output_color,
@@ -407,22 +6718,59 @@ impl<'a, R, C> GfxGraphics<'a, R, C>
                 ref mut buffer_pos,
                 ref mut buffer_color,
                 ref mut colored,
+                ref mut colored_wireframe,
+                ref mut colored_dither,
+                ref mut custom_blends,
+                ref mut colored_invert,
+                colored_invert_key,
+                ref mut stats,
+                clip_region,
+                dither,
+                custom_blend,
+                custom_stencil_ref,
+                stencil_invert,
                 ..
             },
+            wireframe,
+            clip_plane,
             ..
         } = self;
 
-        let (pso_colored, stencil_val) = colored.stencil_blend(
-            colored_draw_state.stencil,
-            colored_draw_state.blend
-        );
+        let colored = if wireframe {
+            colored_wireframe
+        } else if dither {
+            colored_dither
+        } else {
+            colored
+        };
 
-        let scissor = match colored_draw_state.scissor {
-            None => Rect { x: 0, y: 0, w: u16::MAX, h: u16::MAX },
-            Some(r) => Rect { x: r[0] as u16, y: r[1] as u16,
-                w: r[2] as u16, h: r[3] as u16 }
+        let (pso_colored, stencil_val, pso_key) = if stencil_invert {
+            let i = match clip_plane {
+                ClipPlane::All => 0,
+                ClipPlane::Lower => 1,
+                ClipPlane::Upper => 2,
+            };
+            (&mut colored_invert[i], 0, colored_invert_key + i)
+        } else if custom_blend {
+            let stencil_val = match (custom_stencil_ref, colored_draw_state.stencil) {
+                (Some(val), _) => val,
+                (None, Some(draw_state::Stencil::Clip(val))) |
+                (None, Some(draw_state::Stencil::Inside(val))) |
+                (None, Some(draw_state::Stencil::Outside(val))) => clip_plane.shift(val),
+                (None, None) => 0,
+            };
+            let custom = &mut custom_blends[0];
+            (&mut custom.pso, stencil_val, custom.key)
+        } else {
+            colored.stencil_blend(
+                clip_plane,
+                colored_draw_state.stencil,
+                colored_draw_state.blend
+            )
         };
 
+        let scissor = scissor_rect(colored_draw_state.scissor, clip_region);
+
         let data = pipe_colored::Data {
             pos: buffer_pos.clone(),
             color: buffer_color.clone(),
@@ -442,6 +6790,7 @@ impl<'a, R, C> GfxGraphics<'a, R, C>
             base_vertex: 0,
         };
         encoder.draw(&slice, pso_colored, &data);
+        stats.record(pso_key, *colored_offset);
         *colored_offset = 0;
     }
 }
@@ -458,16 +6807,27 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
     type Texture = Texture<R>;
 
     fn clear_color(&mut self, color: [f32; 4]) {
-        let color = gamma_srgb_to_linear(color);
+        let color = self.linear_color(color);
+        // Batched triangles must be drawn before the clear.
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
         let &mut GfxGraphics {
             ref mut encoder,
             output_color,
             ..
         } = self;
-        encoder.clear(output_color, color);
+        clear_raw(encoder, output_color, color);
     }
 
     fn clear_stencil(&mut self, value: u8) {
+        // Batched triangles must be drawn before the clear,
+        // otherwise a pending clip mask is written after it and lost.
+        // No barrier is needed between writing and testing the stencil,
+        // since draws on the same encoder execute in order.
+        if self.g2d.colored_offset > 0 {
+            self.flush_colored();
+        }
         let &mut GfxGraphics {
             ref mut encoder,
             output_stencil,
@@ -484,7 +6844,8 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
     )
         where F: FnMut(&mut FnMut(&[[f32; 2]]))
     {
-        let color = gamma_srgb_to_linear(*color);
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.draw_color(color);
 
         // Flush when draw state changes.
         if &self.g2d.colored_draw_state != draw_state {
@@ -492,44 +6853,48 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
             self.g2d.colored_draw_state = *draw_state;
         }
         f(&mut |vertices: &[[f32; 2]]| {
-            let n = vertices.len();
+            // Only whole triangles are drawn per chunk.
+            let chunk = BUFFER_SIZE * CHUNKS - BUFFER_SIZE * CHUNKS % 3;
+            for vertices in vertices.chunks(chunk) {
+                let n = vertices.len();
 
-            // Render if there is not enough room.
-            if self.g2d.colored_offset + n > BUFFER_SIZE * CHUNKS {
-                self.flush_colored();
-            }
+                // Render if there is not enough room.
+                if self.g2d.colored_offset + n > BUFFER_SIZE * CHUNKS {
+                    self.flush_colored();
+                }
 
-            {
-                use std::slice::from_raw_parts;
-
-                let &mut GfxGraphics {
-                    ref mut encoder,
-                    g2d: &mut Gfx2d {
-                        ref mut colored_offset,
-                        ref mut buffer_pos,
-                        ref mut buffer_color,
+                {
+                    use std::slice::from_raw_parts;
+
+                    let &mut GfxGraphics {
+                        ref mut encoder,
+                        g2d: &mut Gfx2d {
+                            ref mut colored_offset,
+                            ref mut buffer_pos,
+                            ref mut buffer_color,
+                            ..
+                        },
                         ..
-                    },
-                    ..
-                } = self;
+                    } = self;
 
-                unsafe {
-                    encoder.update_buffer(
-                        &buffer_pos,
-                        from_raw_parts(
-                            vertices.as_ptr() as *const PositionFormat,
-                            n
-                        ),
-                        *colored_offset
-                    ).unwrap();
-                }
+                    unsafe {
+                        encoder.update_buffer(
+                            buffer_pos,
+                            from_raw_parts(
+                                vertices.as_ptr() as *const PositionFormat,
+                                n
+                            ),
+                            *colored_offset
+                        ).unwrap();
+                    }
 
-                for i in 0..n {
-                    encoder.update_buffer(&buffer_color, &[ColorFormat {
-                            color: color
-                        }], *colored_offset + i).unwrap();
+                    for i in 0..n {
+                        encoder.update_buffer(
+                            buffer_color, &[ColorFormat { color }], *colored_offset + i
+                        ).unwrap();
+                    }
+                    *colored_offset += n;
                 }
-                *colored_offset += n;
             }
         })
     }
@@ -543,10 +6908,8 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
     )
         where F: FnMut(&mut FnMut(&[[f32; 2]], &[[f32; 2]]))
     {
-        use draw_state::target::Rect;
-        use std::u16;
-
-        let color = gamma_srgb_to_linear(*color);
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(color);
         if self.g2d.colored_offset > 0 {
             self.flush_colored();
         }
@@ -558,21 +6921,25 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
                 ref mut buffer_pos,
                 ref mut buffer_uv,
                 ref mut textured,
+                ref mut textured_wireframe,
+                ref mut stats,
+                clip_region,
                 ..
             },
+            wireframe,
+            clip_plane,
             ..
         } = self;
 
-        let (pso_textured, stencil_val) = textured.stencil_blend(
+        let textured = if wireframe {textured_wireframe} else {textured};
+
+        let (pso_textured, stencil_val, pso_key) = textured.stencil_blend(
+            clip_plane,
             draw_state.stencil,
             draw_state.blend
         );
 
-        let scissor = match draw_state.scissor {
-            None => Rect { x: 0, y: 0, w: u16::MAX, h: u16::MAX },
-            Some(r) => Rect { x: r[0] as u16, y: r[1] as u16,
-                w: r[2] as u16, h: r[3] as u16 }
-        };
+        let scissor = scissor_rect(draw_state.scissor, clip_region);
 
         let data = pipe_textured::Data {
             pos: buffer_pos.clone(),
@@ -593,34 +6960,41 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
                 vertices.len(),
                 texture_coords.len()
             );
-            let n = vertices.len();
-            unsafe {
-                encoder.update_buffer(
-                    &buffer_pos,
-                    from_raw_parts(
-                        vertices.as_ptr() as *const PositionFormat,
-                        n
-                    ),
-                    0
-                ).unwrap();
-                encoder.update_buffer(
-                    &buffer_uv,
-                    from_raw_parts(
-                        texture_coords.as_ptr() as *const TexCoordsFormat,
-                        n
-                    ),
-                    0
-                ).unwrap();
-            }
+            // Only whole triangles are drawn per chunk.
+            let chunk = BUFFER_SIZE - BUFFER_SIZE % 3;
+            for (vertices, texture_coords) in vertices.chunks(chunk)
+                .zip(texture_coords.chunks(chunk))
+            {
+                let n = vertices.len();
+                unsafe {
+                    encoder.update_buffer(
+                        buffer_pos,
+                        from_raw_parts(
+                            vertices.as_ptr() as *const PositionFormat,
+                            n
+                        ),
+                        0
+                    ).unwrap();
+                    encoder.update_buffer(
+                        buffer_uv,
+                        from_raw_parts(
+                            texture_coords.as_ptr() as *const TexCoordsFormat,
+                            n
+                        ),
+                        0
+                    ).unwrap();
+                }
 
-            let slice = gfx::Slice {
-                instances: None,
-                start: 0,
-                end: n as u32,
-                buffer: gfx::IndexBuffer::Auto,
-                base_vertex: 0,
-            };
-            encoder.draw(&slice, pso_textured, &data);
+                let slice = gfx::Slice {
+                    instances: None,
+                    start: 0,
+                    end: n as u32,
+                    buffer: gfx::IndexBuffer::Auto,
+                    base_vertex: 0,
+                };
+                encoder.draw(&slice, pso_textured, &data);
+                stats.record(pso_key, n);
+            }
         })
     }
 
@@ -633,10 +7007,8 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
     )
         where F: FnMut(&mut FnMut(&[[f32; 2]], &[[f32; 2]], &[u16]))
     {
-        use draw_state::target::Rect;
-        use std::u16;
-
-        let color = gamma_srgb_to_linear(*color);
+        let draw_state = &self.effective_draw_state(draw_state);
+        let color = self.tint_color(color);
         if self.g2d.colored_offset > 0 {
             self.flush_colored();
         }
@@ -648,22 +7020,26 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
                 ref mut buffer_pos,
                 ref mut buffer_uv,
                 ref mut textured,
+                ref mut textured_wireframe,
                 ref mut buffer_indices,
+                ref mut stats,
+                clip_region,
                 ..
             },
+            wireframe,
+            clip_plane,
             ..
         } = self;
 
-        let (pso_textured, stencil_val) = textured.stencil_blend(
+        let textured = if wireframe {textured_wireframe} else {textured};
+
+        let (pso_textured, stencil_val, pso_key) = textured.stencil_blend(
+            clip_plane,
             draw_state.stencil,
             draw_state.blend
         );
 
-        let scissor = match draw_state.scissor {
-            None => Rect { x: 0, y: 0, w: u16::MAX, h: u16::MAX },
-            Some(r) => Rect { x: r[0] as u16, y: r[1] as u16,
-                w: r[2] as u16, h: r[3] as u16 }
-        };
+        let scissor = scissor_rect(draw_state.scissor, clip_region);
 
         let data = pipe_textured::Data {
             pos: buffer_pos.clone(),
@@ -721,6 +7097,663 @@ impl<'a, R, C> Graphics for GfxGraphics<'a, R, C>
             };
             
             encoder.draw(&slice, pso_textured, &data);
+            stats.record(pso_key, n);
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_info_inside_2d() {
+        use gfx::format::Formatted;
+        use gfx::texture::{AaMode, Kind};
+
+        let dim = Kind::D2(4, 3, AaMode::Single).get_dimensions();
+        let format = Srgba8::get_format();
+        assert!(pixel_info([0, 0], format).is_inside(dim));
+        assert!(pixel_info([3, 2], format).is_inside(dim));
+        assert!(!pixel_info([4, 2], format).is_inside(dim));
+        assert!(!pixel_info([3, 3], format).is_inside(dim));
+    }
+
+    #[test]
+    fn grid_lines_in_range() {
+        assert_eq!(grid_lines(0.0, 10.0, 5.0), (0.0, 3));
+        assert_eq!(grid_lines(1.0, 10.0, 5.0), (1.0, 2));
+        assert_eq!(grid_lines(-7.0, 4.0, 5.0), (-1.0, 1));
+        assert_eq!(grid_lines(-7.0, 1.0, 5.0), (-1.0, 0));
+        assert_eq!(grid_lines(0.0, 1e9, 1e-3).1, MAX_GRID_LINES);
+        assert_eq!(grid_lines(1e300, 1.0, 1e-300).1, MAX_GRID_LINES);
+    }
+
+    #[test]
+    fn sprite_placement_anchor_and_pivot() {
+        use graphics::math::transform_pos;
+
+        let near = |a: [f64; 2], b: [f64; 2]| {
+            (a[0] - b[0]).abs() < 1e-9 && (a[1] - b[1]).abs() < 1e-9
+        };
+        let size = [4.0, 2.0];
+
+        let corner = SpritePlacement::new([10.0, 20.0]).anchor(SpriteAnchor::UPPER_LEFT);
+        assert!(near(transform_pos(corner.transform(size), [0.0, 0.0]), [10.0, 20.0]));
+        assert!(near(transform_pos(corner.transform(size), [4.0, 2.0]), [14.0, 22.0]));
+
+        let center = SpritePlacement::new([10.0, 20.0]);
+        assert!(near(transform_pos(center.transform(size), [2.0, 1.0]), [10.0, 20.0]));
+        assert!(near(transform_pos(center.transform(size), [0.0, 0.0]), [8.0, 19.0]));
+
+        // The anchor stays in place when rotating and scaling, so it is the pivot.
+        let pivot = SpritePlacement::new([10.0, 20.0])
+            .anchor(SpriteAnchor::Pixels([4.0, 0.0]))
+            .scale(2.0, 2.0)
+            .rotation(::std::f64::consts::PI / 2.0);
+        assert!(near(transform_pos(pivot.transform(size), [4.0, 0.0]), [10.0, 20.0]));
+        assert!(near(transform_pos(pivot.transform(size), [0.0, 0.0]), [10.0, 12.0]));
+    }
+
+    // Blends a pixel on the CPU like the blend state of a pipeline with additive equations.
+    fn blend_pixel(blend: gfx::state::Blend, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+        use gfx::state::{BlendValue, Equation, Factor};
+
+        let factor = |factor: Factor, i: usize| {
+            let value = |value: BlendValue| match value {
+                BlendValue::SourceColor => src[i],
+                BlendValue::SourceAlpha => src[3],
+                BlendValue::DestColor => dst[i],
+                BlendValue::DestAlpha => dst[3],
+                BlendValue::ConstColor | BlendValue::ConstAlpha => 1.0,
+            };
+            match factor {
+                Factor::Zero => 0.0,
+                Factor::One => 1.0,
+                Factor::SourceAlphaSaturated => src[3].min(1.0 - dst[3]),
+                Factor::ZeroPlus(v) => value(v),
+                Factor::OneMinus(v) => 1.0 - value(v),
+            }
+        };
+        let mut res = [0.0; 4];
+        for (i, res) in res.iter_mut().enumerate() {
+            let channel = if i < 3 {blend.color} else {blend.alpha};
+            assert_eq!(channel.equation, Equation::Add);
+            *res = src[i] * factor(channel.source, i) + dst[i] * factor(channel.destination, i);
+        }
+        res
+    }
+
+    #[test]
+    fn premultiplied_group_opacity() {
+        let near = |a: [f32; 4], b: [f32; 4]| {
+            a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-6)
+        };
+        let background = [0.2, 0.4, 0.6, 1.0];
+        let elements = [[1.0, 0.0, 0.0, 0.5], [0.0, 1.0, 0.5, 0.75]];
+        let opacity = 0.4;
+
+        // The elements drawn directly, and into a transparent layer.
+        let direct = elements.iter()
+            .fold(background, |dst, &src| blend_pixel(BLEND_ALPHA, src, dst));
+        let layer = elements.iter()
+            .fold([0.0; 4], |dst, &src| blend_pixel(BLEND_ALPHA, src, dst));
+        assert!(near(layer, [0.125, 0.75, 0.375, 0.875]));
+
+        // Compositing with opacity fades the group as a whole, not each element.
+        let faded = [layer[0] * opacity, layer[1] * opacity,
+                     layer[2] * opacity, layer[3] * opacity];
+        let res = blend_pixel(premultiplied_blend(BLEND_ALPHA), faded, background);
+        let lerp = |i: usize| background[i] + (direct[i] - background[i]) * opacity;
+        assert!(near(res, [lerp(0), lerp(1), lerp(2), lerp(3)]));
+
+        // At full opacity, the layer composites like drawing directly.
+        let res = blend_pixel(premultiplied_blend(BLEND_ALPHA), layer, background);
+        assert!(near(res, direct));
+
+        // Layers composite the same from targets with either alpha blending.
+        let alpha = premultiplied_blend(gfx::preset::blend::ALPHA);
+        assert!(alpha == premultiplied_blend(BLEND_ALPHA));
+    }
+
+    #[test]
+    fn sprite_options_quad() {
+        use graphics::math::identity;
+
+        let near = |a: [f32; 2], b: [f32; 2]| {
+            (a[0] - b[0]).abs() < 1e-5 && (a[1] - b[1]).abs() < 1e-5
+        };
+        let rect = [10.0, 20.0, 4.0, 2.0];
+
+        let (vertices, uvs) = SpriteOptions::default().quad(rect, identity());
+        assert_eq!(vertices[0], [10.0, 20.0]);
+        assert_eq!(vertices[4], [14.0, 22.0]);
+        assert_eq!(uvs[0], [0.0, 0.0]);
+        assert_eq!(uvs[4], [1.0, 1.0]);
+
+        let flipped = SpriteOptions { flip_x: true, ..SpriteOptions::default() };
+        let (vertices, uvs) = flipped.quad(rect, identity());
+        assert_eq!(vertices[0], [10.0, 20.0]);
+        assert_eq!(uvs[0], [1.0, 0.0]);
+        assert_eq!(uvs[4], [0.0, 1.0]);
+
+        // A half turn around the center swaps the opposite corners.
+        let rotated = SpriteOptions {
+            rotation: ::std::f64::consts::PI,
+            origin: [2.0, 1.0],
+            ..SpriteOptions::default()
+        };
+        let (vertices, _) = rotated.quad(rect, identity());
+        assert!(near(vertices[0], [14.0, 22.0]));
+        assert!(near(vertices[4], [10.0, 20.0]));
+    }
+
+    #[test]
+    fn quad_corners() {
+        use graphics::math::{identity, translate};
+
+        // Each vertex is at its texture coordinates in the rectangle,
+        // so the corner colors are at their corners.
+        let rect = [10.0, 20.0, 4.0, 2.0];
+        let (positions, uvs) = quad(rect, identity());
+        for (p, uv) in positions.iter().zip(&uvs) {
+            assert_eq!(p.pos, [10.0 + uv.uv[0] * 4.0, 20.0 + uv.uv[1] * 2.0]);
+        }
+        let corners: Vec<_> = uvs.iter().map(|uv| uv.uv).collect();
+        for corner in &[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]] {
+            assert!(corners.contains(corner));
+        }
+
+        let (positions, _) = quad(rect, translate([1.0, -1.0]));
+        assert_eq!(positions[0].pos, [11.0, 19.0]);
+    }
+
+    #[test]
+    fn aa_fringe_square() {
+        let near = |a: &[[f64; 2]], b: &[[f64; 2]]| {
+            a.len() == b.len() && a.iter().zip(b)
+                .all(|(a, b)| (a[0] - b[0]).abs() < 1e-9 && (a[1] - b[1]).abs() < 1e-9)
+        };
+        let square = [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let (inner, outer) = aa_fringe(&square);
+        assert!(near(&inner, &[[0.5, 0.5], [9.5, 0.5], [9.5, 9.5], [0.5, 9.5]]));
+        assert!(near(&outer, &[[-0.5, -0.5], [10.5, -0.5], [10.5, 10.5], [-0.5, 10.5]]));
+
+        // The winding order does not matter.
+        let mut reversed = square;
+        reversed.reverse();
+        let (inner, _) = aa_fringe(&reversed);
+        assert!(near(&inner, &[[0.5, 9.5], [9.5, 9.5], [9.5, 0.5], [0.5, 0.5]]));
+
+        assert!(aa_fringe(&[[0.0, 0.0], [1.0, 1.0], [2.0, 2.0]]).0.is_empty());
+    }
+
+    #[test]
+    fn vignette_uniforms_clamped() {
+        let color = [0.1, 0.2, 0.3, 0.5];
+        assert_eq!(vignette_uniforms(color, 0.5, 0.25), ([0.1, 0.2, 0.3, 0.25], 0.25));
+        assert_eq!(vignette_uniforms(color, 2.0, 0.25).0, color);
+        assert_eq!(vignette_uniforms(color, -1.0, 0.25).0[3], 0.0);
+        assert!(vignette_uniforms(color, 1.0, 0.0).1 > 0.0);
+        assert!(vignette_uniforms(color, 1.0, -1.0).1 > 0.0);
+    }
+
+    #[test]
+    fn pso_variant_index_round_trip() {
+        let mut seen = 0u64;
+        for i in 0..PSO_VARIANTS {
+            let v = PsoVariant::from_index(i);
+            assert_eq!(PsoVariant::index(v.plane, v.stencil, v.blend) as usize, i);
+            seen |= 1 << i;
+        }
+        assert_eq!(PsoVariant::from_bits(seen).len(), PSO_VARIANTS);
+
+        // Without a stencil test, the clip plane does not matter.
+        let blend = Some(draw_state::Blend::Add);
+        assert_eq!(PsoVariant::index(ClipPlane::Upper, None, blend),
+                   PsoVariant::index(ClipPlane::All, None, blend));
+
+        let inside = Some(draw_state::Stencil::Inside(3));
+        let a = PsoVariant::index(ClipPlane::Lower, inside, None) as usize;
+        let b = PsoVariant::index(ClipPlane::All, None, blend) as usize;
+        assert_eq!(PsoVariant::from_bits(1 << a | 1 << b), vec![
+            PsoVariant { plane: ClipPlane::All, stencil: None, blend },
+            PsoVariant {
+                plane: ClipPlane::Lower,
+                stencil: Some(draw_state::Stencil::Inside(0)),
+                blend: None,
+            },
+        ]);
+    }
+
+    #[test]
+    fn contour_fans_even_odd_ring() {
+        use graphics::math::{identity, scale};
+
+        // Counts the triangles covering a point, like the inverts of the stencil buffer.
+        let coverage = |vertices: &[[f32; 2]], p: [f32; 2]| {
+            vertices.chunks(3).filter(|t| {
+                let side = |a: [f32; 2], b: [f32; 2]| {
+                    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0]) > 0.0
+                };
+                let s = side(t[0], t[1]);
+                s == side(t[1], t[2]) && s == side(t[2], t[0])
+            }).count()
+        };
+        let outer: &[[f64; 2]] = &[[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let inner: &[[f64; 2]] = &[[3.0, 3.0], [7.0, 3.0], [7.0, 7.0], [3.0, 7.0]];
+        let ring = contour_fans(&[outer, inner], identity());
+        assert_eq!(ring.len(), 12);
+        assert_eq!(coverage(&ring, [1.0, 5.0]) % 2, 1);
+        assert_eq!(coverage(&ring, [5.5, 8.5]) % 2, 1);
+        assert_eq!(coverage(&ring, [5.5, 4.5]) % 2, 0);
+        assert_eq!(coverage(&ring, [11.0, 5.0]), 0);
+
+        // Degenerate contours add no triangles, and vertices are transformed.
+        let ring = contour_fans(&[outer, &[[0.0, 0.0], [1.0, 1.0]]], scale(0.5, 0.5));
+        assert_eq!(ring.len(), 6);
+        assert_eq!(ring[2], [5.0, 5.0]);
+    }
+
+    #[test]
+    fn colored_batch_flushed_before_clears() {
+        use self::device::Command;
+        use gfx::traits::*;
+
+        let mut factory = device::Factory::new();
+        let mut g2d = Gfx2d::new(OpenGL::V3_2, &mut factory);
+        let mut encoder = factory.encoder();
+        let (_, _, color) = factory.create_render_target::<Srgba8>(64, 64).unwrap();
+        let stencil = factory.create_depth_stencil_view_only::<DepthStencil>(64, 64).unwrap();
+        let viewport = Viewport {
+            rect: [0, 0, 64, 64],
+            draw_size: [64, 64],
+            window_size: [64.0, 64.0],
+        };
+        let triangle = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]];
+        g2d.draw(&mut encoder, &color, &stencil, viewport, |_, g| {
+            // A pending clip mask is drawn before the stencil clear, not after it.
+            g.tri_list(&DrawState::new_clip(), &[1.0; 4], |f| f(&triangle));
+            g.clear_stencil(0);
+            g.tri_list(&DrawState::default(), &[1.0; 4], |f| f(&triangle));
+            g.clear_color([0.0; 4]);
+        });
+        let log = factory.log.lock().unwrap();
+        match log.commands()[..] {
+            [Command::Draw { pso: clip, vertices: 3, .. }, Command::ClearStencil(0),
+             Command::Draw { pso, vertices: 3, .. }, Command::ClearColor] => {
+                use gfx::state::Comparison;
+
+                assert_eq!(log.stencil_test(clip), Some(Comparison::Never));
+                assert_eq!(log.stencil_test(pso), Some(Comparison::Always));
+            }
+            ref commands => panic!("Unexpected commands: {:?}", commands),
+        }
+    }
+
+    #[test]
+    fn text_inside_circle_clip() {
+        use self::device::Command;
+        use gfx::state::Comparison;
+        use gfx::traits::*;
+        use graphics::{text, Transformed};
+        use std::f64::consts::PI;
+        use {GlyphCache, TextureContext, TextureSettings};
+
+        let mut factory = device::Factory::new();
+        let mut g2d = Gfx2d::new(OpenGL::V3_2, &mut factory);
+        let mut encoder = factory.encoder();
+        let mut glyphs = GlyphCache::from_bytes(
+            include_bytes!("../assets/FiraSans-Regular.ttf"),
+            TextureContext {factory: factory.clone(), encoder: factory.encoder()},
+            TextureSettings::new()
+        ).unwrap();
+        let (_, _, color) = factory.create_render_target::<Srgba8>(64, 64).unwrap();
+        let stencil = factory.create_depth_stencil_view_only::<DepthStencil>(64, 64).unwrap();
+        let viewport = Viewport {
+            rect: [0, 0, 64, 64],
+            draw_size: [64, 64],
+            window_size: [64.0, 64.0],
+        };
+        let point = |i: usize| {
+            let angle = i as f64 * PI / 8.0;
+            [32.0 + 16.0 * angle.cos(), 32.0 + 16.0 * angle.sin()]
+        };
+        let circle: Vec<_> = (0..16).flat_map(|i| vec![[32.0, 32.0], point(i), point(i + 1)])
+            .collect();
+        g2d.draw(&mut encoder, &color, &stencil, viewport, |c, g| {
+            g.clip(ClipRegion::Shape(&circle), c.transform, &c.draw_state, |draw_state, g| {
+                text::Text::new(24).draw("Hello", &mut glyphs, draw_state,
+                    c.transform.trans(8.0, 40.0), g).unwrap();
+            });
+        });
+        let log = factory.log.lock().unwrap();
+        let commands = log.commands();
+        let n = commands.len();
+        assert!(n > 4, "Unexpected commands: {:?}", commands);
+        assert_eq!(commands[0], Command::ClearStencil(0));
+        assert_eq!(commands[n - 1], Command::ClearStencil(0));
+
+        // The circle is written to the stencil buffer, and the glyphs are drawn
+        // only where it was written.
+        match commands[1] {
+            Command::Draw { pso, stencil_ref: 1, vertices: 48 } =>
+                assert_eq!(log.stencil_test(pso), Some(Comparison::Never)),
+            ref command => panic!("Unexpected mask: {:?}", command),
+        }
+        for command in &commands[2..n - 1] {
+            match *command {
+                Command::Draw { pso, stencil_ref: 1, .. } =>
+                    assert_eq!(log.stencil_test(pso), Some(Comparison::Equal)),
+                ref command => panic!("Unexpected glyph: {:?}", command),
+            }
+        }
+    }
+
+    // Records the commands of an encoder without a GPU, for testing the order
+    // of draws and clears and the PSO state they use.
+    mod device {
+        use gfx;
+        use gfx::handle::{self, Producer};
+        use gfx::{buffer, format, mapping, texture};
+        use gfx_core;
+        use gfx_core::{pso, shade};
+        use gfx_core::command::{self, ClearColor};
+        use std::sync::{Arc, Mutex};
+
+        // Resources where PSOs are indices into `Log::psos`.
+        #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+        pub enum Res {}
+
+        impl gfx::Resources for Res {
+            type Buffer = ();
+            type Shader = ();
+            type Program = ();
+            type PipelineStateObject = usize;
+            type Texture = ();
+            type ShaderResourceView = ();
+            type UnorderedAccessView = ();
+            type RenderTargetView = ();
+            type DepthStencilView = ();
+            type Sampler = ();
+            type Fence = ();
+            type Mapping = Mapping;
+        }
+
+        #[derive(Debug, Eq, Hash, PartialEq)]
+        pub struct Mapping;
+
+        impl mapping::Gate<Res> for Mapping {
+            unsafe fn set<T>(&self, _: usize, _: T) {unimplemented!()}
+            unsafe fn slice<'b, T>(&self, _: usize) -> &'b [T] {unimplemented!()}
+            unsafe fn mut_slice<'b, T>(&self, _: usize) -> &'b mut [T] {unimplemented!()}
+        }
+
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        pub enum Command {
+            Draw { pso: usize, stencil_ref: u8, vertices: u32 },
+            ClearColor,
+            ClearStencil(u8),
+        }
+
+        // The commands recorded so far and the descriptors of the created PSOs.
+        #[derive(Default)]
+        pub struct Log {
+            pub commands: Vec<Command>,
+            pub psos: Vec<pso::Descriptor>,
+        }
+
+        impl Log {
+            // Returns the commands without empty draws.
+            pub fn commands(&self) -> Vec<Command> {
+                self.commands.iter().cloned().filter(|&command| match command {
+                    Command::Draw { vertices, .. } => vertices > 0,
+                    _ => true,
+                }).collect()
+            }
+
+            // Returns the stencil test of a PSO.
+            pub fn stencil_test(&self, pso: usize) -> Option<gfx::state::Comparison> {
+                self.psos[pso].depth_stencil.and_then(|(_, info)| info.front).map(|side| side.fun)
+            }
+        }
+
+        pub struct Factory {
+            manager: handle::Manager<Res>,
+            capabilities: gfx_core::Capabilities,
+            pub log: Arc<Mutex<Log>>,
+        }
+
+        impl Factory {
+            pub fn new() -> Factory {
+                Factory {
+                    manager: handle::Manager::new(),
+                    capabilities: gfx_core::Capabilities {
+                        max_vertex_count: 0,
+                        max_index_count: 0,
+                        max_texture_size: 4096,
+                        max_patch_size: 0,
+                        instance_base_supported: true,
+                        instance_call_supported: true,
+                        instance_rate_supported: true,
+                        vertex_base_supported: true,
+                        srgb_color_supported: true,
+                        constant_buffer_supported: true,
+                        unordered_access_view_supported: false,
+                        separate_blending_slots_supported: false,
+                        copy_buffer_supported: true,
+                    },
+                    log: Arc::new(Mutex::new(Log::default())),
+                }
+            }
+
+            // Creates an encoder recording to the log of the factory.
+            // Handles keep no state, so they are valid with clones of the factory.
+            pub fn encoder(&self) -> gfx::Encoder<Res, Commands> {
+                Commands { log: self.log.clone(), pso: 0, stencil_ref: 0 }.into()
+            }
+        }
+
+        impl Clone for Factory {
+            fn clone(&self) -> Factory {
+                Factory {
+                    manager: handle::Manager::new(),
+                    capabilities: self.capabilities,
+                    log: self.log.clone(),
+                }
+            }
+        }
+
+        impl gfx::Factory<Res> for Factory {
+            fn get_capabilities(&self) -> &gfx_core::Capabilities {&self.capabilities}
+
+            fn create_buffer_raw(&mut self, info: buffer::Info)
+                -> Result<handle::RawBuffer<Res>, buffer::CreationError>
+            {
+                Ok(self.manager.make_buffer((), info, None))
+            }
+
+            fn create_buffer_immutable_raw(
+                &mut self,
+                data: &[u8],
+                stride: usize,
+                role: buffer::Role,
+                bind: gfx::memory::Bind
+            ) -> Result<handle::RawBuffer<Res>, buffer::CreationError> {
+                let info = buffer::Info {
+                    role,
+                    usage: gfx::memory::Usage::Data,
+                    bind,
+                    size: data.len(),
+                    stride,
+                };
+                self.create_buffer_raw(info)
+            }
+
+            fn create_pipeline_state_raw(
+                &mut self,
+                program: &handle::Program<Res>,
+                desc: &pso::Descriptor
+            ) -> Result<handle::RawPipelineState<Res>, pso::CreationError> {
+                let mut log = self.log.lock().unwrap();
+                log.psos.push(*desc);
+                Ok(self.manager.make_pso(log.psos.len() - 1, program))
+            }
+
+            fn create_program(&mut self, _: &gfx::ShaderSet<Res>)
+                -> Result<handle::Program<Res>, shade::CreateProgramError>
+            {
+                let info = gfx::ProgramInfo {
+                    vertex_attributes: vec![],
+                    globals: vec![],
+                    constant_buffers: vec![],
+                    textures: vec![],
+                    unordereds: vec![],
+                    samplers: vec![],
+                    outputs: vec![],
+                    output_depth: false,
+                    knows_outputs: true,
+                };
+                Ok(self.manager.make_program((), info))
+            }
+
+            fn create_shader(&mut self, _: shade::Stage, _: &[u8])
+                -> Result<handle::Shader<Res>, shade::CreateShaderError>
+            {
+                Ok(self.manager.make_shader(()))
+            }
+
+            fn create_sampler(&mut self, info: texture::SamplerInfo) -> handle::Sampler<Res> {
+                self.manager.make_sampler((), info)
+            }
+
+            fn read_mapping<'b, T>(&mut self, _: &'b handle::Buffer<Res, T>)
+                -> Result<mapping::Reader<'b, Res, T>, mapping::Error>
+                where T: Copy
+            {
+                Err(mapping::Error::AccessOverlap)
+            }
+
+            fn write_mapping<'b, T>(&mut self, _: &'b handle::Buffer<Res, T>)
+                -> Result<mapping::Writer<'b, Res, T>, mapping::Error>
+                where T: Copy
+            {
+                Err(mapping::Error::AccessOverlap)
+            }
+
+            fn create_texture_raw(
+                &mut self,
+                info: texture::Info,
+                _: Option<format::ChannelType>,
+                _: Option<(&[&[u8]], texture::Mipmap)>
+            ) -> Result<handle::RawTexture<Res>, texture::CreationError> {
+                Ok(self.manager.make_texture((), info))
+            }
+
+            fn view_buffer_as_shader_resource_raw(
+                &mut self,
+                buffer: &handle::RawBuffer<Res>,
+                _: format::Format
+            ) -> Result<handle::RawShaderResourceView<Res>, gfx::ResourceViewError> {
+                Ok(self.manager.make_buffer_srv((), buffer))
+            }
+
+            fn view_buffer_as_unordered_access_raw(&mut self, buffer: &handle::RawBuffer<Res>)
+                -> Result<handle::RawUnorderedAccessView<Res>, gfx::ResourceViewError>
+            {
+                Ok(self.manager.make_buffer_uav((), buffer))
+            }
+
+            fn view_texture_as_shader_resource_raw(
+                &mut self,
+                texture: &handle::RawTexture<Res>,
+                _: texture::ResourceDesc
+            ) -> Result<handle::RawShaderResourceView<Res>, gfx::ResourceViewError> {
+                Ok(self.manager.make_texture_srv((), texture))
+            }
+
+            fn view_texture_as_unordered_access_raw(&mut self, texture: &handle::RawTexture<Res>)
+                -> Result<handle::RawUnorderedAccessView<Res>, gfx::ResourceViewError>
+            {
+                Ok(self.manager.make_texture_uav((), texture))
+            }
+
+            fn view_texture_as_render_target_raw(
+                &mut self,
+                texture: &handle::RawTexture<Res>,
+                _: texture::RenderDesc
+            ) -> Result<handle::RawRenderTargetView<Res>, gfx::TargetViewError> {
+                let dim = texture.get_info().kind.get_dimensions();
+                Ok(self.manager.make_rtv((), texture, dim))
+            }
+
+            fn view_texture_as_depth_stencil_raw(
+                &mut self,
+                texture: &handle::RawTexture<Res>,
+                _: texture::DepthStencilDesc
+            ) -> Result<handle::RawDepthStencilView<Res>, gfx::TargetViewError> {
+                let dim = texture.get_info().kind.get_dimensions();
+                Ok(self.manager.make_dsv((), texture, dim))
+            }
+        }
+
+        pub struct Commands {
+            log: Arc<Mutex<Log>>,
+            pso: usize,
+            stencil_ref: u8,
+        }
+
+        impl Commands {
+            fn push(&self, command: Command) {
+                self.log.lock().unwrap().commands.push(command);
+            }
+        }
+
+        impl gfx::CommandBuffer<Res> for Commands {
+            fn reset(&mut self) {}
+            fn bind_pipeline_state(&mut self, pso: usize) {self.pso = pso}
+            fn bind_vertex_buffers(&mut self, _: pso::VertexBufferSet<Res>) {}
+            fn bind_constant_buffers(&mut self, _: &[pso::ConstantBufferParam<Res>]) {}
+            fn bind_global_constant(&mut self, _: shade::Location, _: gfx::UniformValue) {}
+            fn bind_resource_views(&mut self, _: &[pso::ResourceViewParam<Res>]) {}
+            fn bind_unordered_views(&mut self, _: &[pso::UnorderedViewParam<Res>]) {}
+            fn bind_samplers(&mut self, _: &[pso::SamplerParam<Res>]) {}
+            fn bind_pixel_targets(&mut self, _: pso::PixelTargetSet<Res>) {}
+            fn bind_index(&mut self, _: (), _: gfx_core::IndexType) {}
+            fn set_scissor(&mut self, _: gfx::Rect) {}
+            fn set_ref_values(&mut self, values: gfx::state::RefValues) {
+                self.stencil_ref = values.stencil.0;
+            }
+            fn copy_buffer(&mut self, _: (), _: (), _: usize, _: usize, _: usize) {}
+            fn copy_buffer_to_texture(&mut self, _: (), _: usize,
+                _: texture::TextureCopyRegion<()>) {}
+            fn copy_texture_to_buffer(&mut self, _: texture::TextureCopyRegion<()>,
+                _: (), _: usize) {}
+            fn copy_texture_to_texture(&mut self, _: texture::TextureCopyRegion<()>,
+                _: texture::TextureCopyRegion<()>) {}
+            fn update_buffer(&mut self, _: (), _: &[u8], _: usize) {}
+            fn update_texture(&mut self, _: texture::TextureCopyRegion<()>, _: &[u8]) {}
+            fn generate_mipmap(&mut self, _: ()) {}
+            fn clear_color(&mut self, _: (), _: ClearColor) {
+                self.push(Command::ClearColor);
+            }
+            fn clear_depth_stencil(&mut self, _: (), _: Option<gfx::Depth>,
+                stencil: Option<gfx::Stencil>)
+            {
+                if let Some(value) = stencil {
+                    self.push(Command::ClearStencil(value));
+                }
+            }
+            fn call_draw(&mut self, _: gfx::VertexCount, vertices: gfx::VertexCount,
+                _: Option<command::InstanceParams>)
+            {
+                self.push(Command::Draw { pso: self.pso, stencil_ref: self.stencil_ref, vertices });
+            }
+            fn call_draw_indexed(&mut self, _: gfx::VertexCount, vertices: gfx::VertexCount,
+                _: gfx::VertexCount, _: Option<command::InstanceParams>)
+            {
+                self.push(Command::Draw { pso: self.pso, stencil_ref: self.stencil_ref, vertices });
+            }
+        }
+    }
+}
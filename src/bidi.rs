@@ -0,0 +1,174 @@
+//! Bidirectional text, following the Unicode bidirectional algorithm.
+//!
+//! Text is stored in logical order, the order it is typed in, but right-to-left
+//! scripts like Arabic and Hebrew are displayed in the other direction.
+//! This module computes the embedding levels of the characters and the visual order
+//! to draw them in with the `unicode-bidi` crate.
+//!
+//! Arabic letters are not shaped into their joining forms,
+//! which needs a font with presentation forms.
+
+use std::ops::Range;
+
+use unicode_bidi::{bidi_class, BidiInfo, Level};
+
+/// The maximum embedding level.
+pub const MAX_LEVEL: u8 = 125;
+
+/// A base direction of a paragraph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Left to right, e.g. for Latin scripts.
+    LeftToRight,
+    /// Right to left, e.g. for Arabic and Hebrew.
+    RightToLeft,
+}
+
+/// A sequence of characters with the same embedding level, see `visual_runs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Run {
+    /// The byte range in the text.
+    pub range: Range<usize>,
+    /// The embedding level, which is odd for right-to-left runs.
+    pub level: u8,
+}
+
+impl Run {
+    /// Returns `true` if the characters of the run are drawn from right to left.
+    pub fn is_rtl(&self) -> bool {self.level % 2 == 1}
+}
+
+// Runs the algorithm with a base direction, or the one of the first strong character.
+fn bidi_info<'a>(text: &'a str, base: Option<Direction>) -> BidiInfo<'a> {
+    BidiInfo::new(text, base.map(|base| match base {
+        Direction::LeftToRight => Level::ltr(),
+        Direction::RightToLeft => Level::rtl(),
+    }))
+}
+
+// Returns the mirrored character for right-to-left text, e.g. `)` for `(`.
+fn mirror(ch: char) -> char {
+    match ch {
+        '(' => ')', ')' => '(',
+        '[' => ']', ']' => '[',
+        '{' => '}', '}' => '{',
+        '<' => '>', '>' => '<',
+        '\u{ab}' => '\u{bb}', '\u{bb}' => '\u{ab}',
+        '\u{2039}' => '\u{203a}', '\u{203a}' => '\u{2039}',
+        '\u{2264}' => '\u{2265}', '\u{2265}' => '\u{2264}',
+        _ => ch,
+    }
+}
+
+// Returns `true` for characters that are not displayed, like explicit embeddings.
+fn is_control(ch: char) -> bool {
+    use unicode_bidi::BidiClass::*;
+
+    ch == '\u{200e}' || ch == '\u{200f}' ||
+        matches!(bidi_class(ch), BN | LRE | LRO | RLE | RLO | PDF | LRI | RLI | FSI | PDI)
+}
+
+/// Returns the base direction of the text, from its first strong character.
+///
+/// Text without strong characters, e.g. only numbers, is left to right.
+pub fn paragraph_direction(text: &str) -> Direction {
+    match bidi_info(text, None).paragraphs.first() {
+        Some(para) if para.level.is_rtl() => Direction::RightToLeft,
+        _ => Direction::LeftToRight,
+    }
+}
+
+/// Returns the embedding level of each character of the text.
+///
+/// Each paragraph has the base direction from its first strong character
+/// if `base` is `None`.
+pub fn levels(text: &str, base: Option<Direction>) -> Vec<u8> {
+    let info = bidi_info(text, base);
+    text.char_indices().map(|(i, _)| info.levels[i].number()).collect()
+}
+
+/// Returns the runs of characters with the same embedding level in visual order,
+/// from left to right, one paragraph after another.
+///
+/// The characters of right-to-left runs are drawn in reverse,
+/// see `reorder` for a string in visual order.
+pub fn visual_runs(text: &str, base: Option<Direction>) -> Vec<Run> {
+    let info = bidi_info(text, base);
+    let mut runs = vec![];
+    for para in &info.paragraphs {
+        let (levels, para_runs) = info.visual_runs(para, para.range.clone());
+        runs.extend(para_runs.into_iter().map(|range| Run {
+            level: levels[range.start].number(),
+            range,
+        }));
+    }
+    runs
+}
+
+/// Returns the text in visual order, to lay out from left to right.
+///
+/// Each line is a paragraph. Brackets in right-to-left runs are mirrored,
+/// and explicit embeddings and other invisible controls are removed.
+pub fn reorder(text: &str, base: Option<Direction>) -> String {
+    let mut res = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {res.push('\n')};
+        for run in visual_runs(line, base) {
+            let chars = line[run.range.clone()].chars().filter(|&ch| !is_control(ch));
+            if run.is_rtl() {
+                res.extend(chars.rev().map(mirror));
+            } else {
+                res.extend(chars);
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hebrew letters alef, bet and gimel.
+    const A: &str = "\u{5d0}";
+    const B: &str = "\u{5d1}";
+    const G: &str = "\u{5d2}";
+
+    #[test]
+    fn reorder_single_direction() {
+        assert_eq!(reorder("abc", None), "abc");
+        assert_eq!(reorder(&[A, B, G].concat(), None), [G, B, A].concat());
+    }
+
+    #[test]
+    fn reorder_mixed_runs() {
+        let text = ["ab ", A, B, " cd"].concat();
+        assert_eq!(reorder(&text, None), ["ab ", B, A, " cd"].concat());
+        // Numbers keep their order within right-to-left text.
+        let text = [A, " 12 ", B].concat();
+        assert_eq!(reorder(&text, None), [B, " 12 ", A].concat());
+        assert_eq!(levels(&text, None), vec![1, 1, 2, 2, 1, 1]);
+    }
+
+    #[test]
+    fn reorder_mirrors_and_removes_controls() {
+        let text = [A, "(", B, ")"].concat();
+        assert_eq!(reorder(&text, None), ["(", B, ")", A].concat());
+        assert_eq!(reorder("a\u{202b}b\u{202c}c", None), "abc");
+    }
+
+    #[test]
+    fn visual_runs_in_display_order() {
+        let text = ["ab ", A, B].concat();
+        assert_eq!(visual_runs(&text, None), vec![
+            Run { range: 0..3, level: 0 },
+            Run { range: 3..7, level: 1 },
+        ]);
+        let text = [A, " ab"].concat();
+        assert_eq!(paragraph_direction(&text), Direction::RightToLeft);
+        assert_eq!(visual_runs(&text, None), vec![
+            Run { range: 3..5, level: 2 },
+            Run { range: 0..3, level: 1 },
+        ]);
+    }
+}
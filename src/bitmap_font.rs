@@ -0,0 +1,49 @@
+//! Fonts stored as a fixed grid of glyphs in a texture.
+
+use gfx;
+use graphics::ImageSize;
+use Texture;
+
+/// A bitmap font with glyphs of the same size in a grid, e.g. for retro games.
+///
+/// The glyphs are stored in rows from the upper left corner of the texture,
+/// starting with `first_char` and continuing in the order of character codes.
+pub struct BitmapFont<R: gfx::Resources> {
+    /// The texture storing the glyphs.
+    pub texture: Texture<R>,
+    /// The size of a glyph in pixels.
+    pub glyph_size: [u32; 2],
+    /// The character of the first glyph.
+    pub first_char: char,
+}
+
+impl<R: gfx::Resources> BitmapFont<R> {
+    /// Creates a bitmap font.
+    pub fn new(texture: Texture<R>, glyph_size: [u32; 2], first_char: char) -> BitmapFont<R> {
+        BitmapFont { texture, glyph_size, first_char }
+    }
+
+    /// Returns the number of glyphs per row and column of the grid.
+    pub fn grid_size(&self) -> [u32; 2] {
+        let (w, h) = self.texture.get_size();
+        [w / self.glyph_size[0].max(1), h / self.glyph_size[1].max(1)]
+    }
+
+    /// Returns the texture coordinates `[u1, v1, u2, v2]` of the glyph of a character,
+    /// or `None` if the font has no glyph for it.
+    pub fn glyph_uv(&self, ch: char) -> Option<[f32; 4]> {
+        let [columns, rows] = self.grid_size();
+        let index = (ch as u32).checked_sub(self.first_char as u32)?;
+        if columns == 0 || index >= columns * rows {return None};
+
+        let (w, h) = self.texture.get_size();
+        let [gw, gh] = self.glyph_size;
+        let (x, y) = ((index % columns) * gw, (index / columns) * gh);
+        Some([
+            x as f32 / w as f32,
+            y as f32 / h as f32,
+            (x + gw) as f32 / w as f32,
+            (y + gh) as f32 / h as f32,
+        ])
+    }
+}
@@ -0,0 +1,127 @@
+//! A 2D camera with pan, zoom and rotation.
+
+use graphics::Viewport;
+use graphics::math::{multiply, rotate_radians, scale, transform_pos, translate, Matrix2d};
+
+/// A 2D camera looking at a position in the world, see `Gfx2d::draw_with_camera`.
+///
+/// Screen coordinates are window coordinates like those of mouse events,
+/// with the origin in the upper left corner of the viewport.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera2d {
+    /// The world position shown at the center of the viewport.
+    pub position: [f64; 2],
+    /// The scale from world to screen units, where `2.0` shows things twice as large.
+    pub zoom: f64,
+    /// The rotation of the camera in radians, which rotates the world the other way.
+    pub rotation: f64,
+}
+
+impl Camera2d {
+    /// Creates a camera at the world origin without zoom or rotation.
+    pub fn new() -> Camera2d {
+        Camera2d {
+            position: [0.0, 0.0],
+            zoom: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    /// Returns the transform from world to screen coordinates.
+    pub fn view(&self, viewport: &Viewport) -> Matrix2d {
+        let center = screen_center(viewport);
+        multiply(multiply(multiply(
+            translate(center),
+            rotate_radians(-self.rotation)),
+            scale(self.zoom, self.zoom)),
+            translate([-self.position[0], -self.position[1]]))
+    }
+
+    /// Returns the transform from world to normalized device coordinates,
+    /// to use with `Gfx2d::draw_with_projection`.
+    pub fn projection(&self, viewport: &Viewport) -> Matrix2d {
+        multiply(viewport.abs_transform(), self.view(viewport))
+    }
+
+    /// Converts a point from world to screen coordinates.
+    pub fn world_to_screen(&self, viewport: &Viewport, pos: [f64; 2]) -> [f64; 2] {
+        transform_pos(self.view(viewport), pos)
+    }
+
+    /// Converts a point from screen to world coordinates, e.g. the mouse cursor.
+    ///
+    /// A zoom of zero maps every point to infinity.
+    pub fn screen_to_world(&self, viewport: &Viewport, pos: [f64; 2]) -> [f64; 2] {
+        let center = screen_center(viewport);
+        let inverse = multiply(multiply(multiply(
+            translate(self.position),
+            scale(1.0 / self.zoom, 1.0 / self.zoom)),
+            rotate_radians(self.rotation)),
+            translate([-center[0], -center[1]]));
+        transform_pos(inverse, pos)
+    }
+}
+
+impl Default for Camera2d {
+    fn default() -> Camera2d {
+        Camera2d::new()
+    }
+}
+
+// Returns the center of the viewport in window coordinates.
+fn screen_center(viewport: &Viewport) -> [f64; 2] {
+    let w = viewport.rect[2] as f64 * viewport.window_size[0] / viewport.draw_size[0] as f64;
+    let h = viewport.rect[3] as f64 * viewport.window_size[1] / viewport.draw_size[1] as f64;
+    [0.5 * w, 0.5 * h]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: [f64; 2], b: [f64; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-9 && (a[1] - b[1]).abs() < 1e-9
+    }
+
+    // A window of 400x300 logical pixels drawn at twice the resolution.
+    fn viewport() -> Viewport {
+        Viewport {
+            rect: [0, 0, 800, 600],
+            draw_size: [800, 600],
+            window_size: [400.0, 300.0],
+        }
+    }
+
+    #[test]
+    fn position_at_screen_center() {
+        let camera = Camera2d {
+            position: [30.0, -20.0],
+            zoom: 2.0,
+            rotation: 0.7,
+        };
+        let viewport = viewport();
+        assert!(near(camera.world_to_screen(&viewport, camera.position), [200.0, 150.0]));
+        assert!(near(camera.screen_to_world(&viewport, [200.0, 150.0]), camera.position));
+        // Zooming in shows world units larger.
+        let camera = Camera2d { rotation: 0.0, ..camera };
+        assert!(near(camera.world_to_screen(&viewport, [40.0, -20.0]), [220.0, 150.0]));
+    }
+
+    #[test]
+    fn screen_world_round_trip() {
+        let viewport = viewport();
+        for &(position, zoom, rotation) in &[
+            ([0.0, 0.0], 1.0, 0.0),
+            ([12.5, -7.0], 0.25, 1.0),
+            ([-300.0, 45.0], 3.0, -2.5),
+        ] {
+            let camera = Camera2d { position, zoom, rotation };
+            for &p in &[[0.0, 0.0], [400.0, 300.0], [123.0, 45.6]] {
+                let world = camera.screen_to_world(&viewport, p);
+                assert!(near(camera.world_to_screen(&viewport, world), p));
+                let screen = camera.world_to_screen(&viewport, p);
+                assert!(near(camera.screen_to_world(&viewport, screen), p));
+            }
+        }
+    }
+}
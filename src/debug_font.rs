@@ -0,0 +1,82 @@
+//! A tiny built-in font for debug text, see `GfxGraphics::debug_text`.
+//!
+//! The glyphs are 3x5 pixels and cover printable ASCII up to `'_'`,
+//! with lowercase letters drawn as uppercase. This is meant for quick debugging only.
+
+// The size of a glyph in font pixels.
+pub const GLYPH_SIZE: [u32; 2] = [3, 5];
+
+// The rows of each glyph from the top, with the leftmost pixel in the highest bit,
+// starting at `' '`.
+const GLYPHS: [[u8; 5]; 64] = [
+    [0b000, 0b000, 0b000, 0b000, 0b000], // ' '
+    [0b010, 0b010, 0b010, 0b000, 0b010], // '!'
+    [0b101, 0b101, 0b000, 0b000, 0b000], // '"'
+    [0b101, 0b111, 0b101, 0b111, 0b101], // '#'
+    [0b011, 0b110, 0b010, 0b011, 0b110], // '$'
+    [0b101, 0b001, 0b010, 0b100, 0b101], // '%'
+    [0b010, 0b101, 0b010, 0b101, 0b011], // '&'
+    [0b010, 0b010, 0b000, 0b000, 0b000], // '\''
+    [0b001, 0b010, 0b010, 0b010, 0b001], // '('
+    [0b100, 0b010, 0b010, 0b010, 0b100], // ')'
+    [0b000, 0b101, 0b010, 0b101, 0b000], // '*'
+    [0b000, 0b010, 0b111, 0b010, 0b000], // '+'
+    [0b000, 0b000, 0b000, 0b010, 0b100], // ','
+    [0b000, 0b000, 0b111, 0b000, 0b000], // '-'
+    [0b000, 0b000, 0b000, 0b000, 0b010], // '.'
+    [0b001, 0b001, 0b010, 0b100, 0b100], // '/'
+    [0b111, 0b101, 0b101, 0b101, 0b111], // '0'
+    [0b010, 0b110, 0b010, 0b010, 0b111], // '1'
+    [0b111, 0b001, 0b111, 0b100, 0b111], // '2'
+    [0b111, 0b001, 0b111, 0b001, 0b111], // '3'
+    [0b101, 0b101, 0b111, 0b001, 0b001], // '4'
+    [0b111, 0b100, 0b111, 0b001, 0b111], // '5'
+    [0b111, 0b100, 0b111, 0b101, 0b111], // '6'
+    [0b111, 0b001, 0b001, 0b001, 0b001], // '7'
+    [0b111, 0b101, 0b111, 0b101, 0b111], // '8'
+    [0b111, 0b101, 0b111, 0b001, 0b111], // '9'
+    [0b000, 0b010, 0b000, 0b010, 0b000], // ':'
+    [0b000, 0b010, 0b000, 0b010, 0b100], // ';'
+    [0b001, 0b010, 0b100, 0b010, 0b001], // '<'
+    [0b000, 0b111, 0b000, 0b111, 0b000], // '='
+    [0b100, 0b010, 0b001, 0b010, 0b100], // '>'
+    [0b111, 0b001, 0b011, 0b000, 0b010], // '?'
+    [0b111, 0b101, 0b111, 0b100, 0b111], // '@'
+    [0b010, 0b101, 0b111, 0b101, 0b101], // 'A'
+    [0b110, 0b101, 0b110, 0b101, 0b110], // 'B'
+    [0b011, 0b100, 0b100, 0b100, 0b011], // 'C'
+    [0b110, 0b101, 0b101, 0b101, 0b110], // 'D'
+    [0b111, 0b100, 0b110, 0b100, 0b111], // 'E'
+    [0b111, 0b100, 0b110, 0b100, 0b100], // 'F'
+    [0b011, 0b100, 0b101, 0b101, 0b011], // 'G'
+    [0b101, 0b101, 0b111, 0b101, 0b101], // 'H'
+    [0b111, 0b010, 0b010, 0b010, 0b111], // 'I'
+    [0b001, 0b001, 0b001, 0b101, 0b010], // 'J'
+    [0b101, 0b101, 0b110, 0b101, 0b101], // 'K'
+    [0b100, 0b100, 0b100, 0b100, 0b111], // 'L'
+    [0b101, 0b111, 0b111, 0b101, 0b101], // 'M'
+    [0b110, 0b101, 0b101, 0b101, 0b101], // 'N'
+    [0b010, 0b101, 0b101, 0b101, 0b010], // 'O'
+    [0b110, 0b101, 0b110, 0b100, 0b100], // 'P'
+    [0b010, 0b101, 0b101, 0b110, 0b011], // 'Q'
+    [0b110, 0b101, 0b110, 0b101, 0b101], // 'R'
+    [0b011, 0b100, 0b010, 0b001, 0b110], // 'S'
+    [0b111, 0b010, 0b010, 0b010, 0b010], // 'T'
+    [0b101, 0b101, 0b101, 0b101, 0b111], // 'U'
+    [0b101, 0b101, 0b101, 0b101, 0b010], // 'V'
+    [0b101, 0b101, 0b111, 0b111, 0b101], // 'W'
+    [0b101, 0b101, 0b010, 0b101, 0b101], // 'X'
+    [0b101, 0b101, 0b010, 0b010, 0b010], // 'Y'
+    [0b111, 0b001, 0b010, 0b100, 0b111], // 'Z'
+    [0b011, 0b010, 0b010, 0b010, 0b011], // '['
+    [0b100, 0b100, 0b010, 0b001, 0b001], // '\\'
+    [0b110, 0b010, 0b010, 0b010, 0b110], // ']'
+    [0b010, 0b101, 0b000, 0b000, 0b000], // '^'
+    [0b000, 0b000, 0b000, 0b000, 0b111], // '_'
+];
+
+// Returns the rows of the glyph of a character, if there is one.
+pub fn glyph(ch: char) -> Option<[u8; 5]> {
+    let index = (ch.to_ascii_uppercase() as u32).checked_sub(' ' as u32)?;
+    GLYPHS.get(index as usize).cloned()
+}
@@ -0,0 +1,230 @@
+//! Sorting of draws by depth, for correct alpha blending.
+
+use gfx;
+use graphics::{DrawState, Graphics};
+use graphics::BACK_END_MAX_VERTEX_COUNT as BUFFER_SIZE;
+use graphics::math::{transform_pos, Matrix2d};
+use graphics::types::Color;
+use GfxGraphics;
+use Texture;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Colored(Color),
+    // The index of the texture and the tint.
+    Textured(usize, Color),
+}
+
+struct Item {
+    z: f64,
+    draw_state: DrawState,
+    kind: Kind,
+    // The range of the vertices, and of the texture coordinates for textured items.
+    start: usize,
+    end: usize,
+}
+
+/// Collects draws with a depth during a frame and draws them sorted by depth.
+///
+/// Draws with a larger `z` are drawn in front of draws with a smaller `z`.
+/// Since there is no depth buffer, all draws are drawn back to front.
+/// Opaque draws, which are those without blending, are drawn before transparent
+/// draws of the same depth, grouped by texture and draw state to use fewer draw calls.
+/// Transparent draws of the same depth are drawn in the order they were added.
+pub struct DrawQueue<R: gfx::Resources> {
+    textures: Vec<Texture<R>>,
+    items: Vec<Item>,
+    vertices: Vec<[f32; 2]>,
+    uvs: Vec<[f32; 2]>,
+}
+
+impl<R: gfx::Resources> DrawQueue<R> {
+    /// Creates a new empty queue.
+    pub fn new() -> DrawQueue<R> {
+        DrawQueue {
+            textures: vec![],
+            items: vec![],
+            vertices: vec![],
+            uvs: vec![],
+        }
+    }
+
+    /// Returns the number of draws added since the last flush.
+    pub fn len(&self) -> usize {self.items.len()}
+
+    /// Returns `true` if no draws are added.
+    pub fn is_empty(&self) -> bool {self.items.is_empty()}
+
+    /// Adds a list of colored triangles, three vertices per triangle.
+    pub fn add(
+        &mut self,
+        z: f64,
+        draw_state: &DrawState,
+        color: Color,
+        triangles: &[[f64; 2]],
+        transform: Matrix2d
+    ) {
+        let start = self.vertices.len();
+        let n = triangles.len() - triangles.len() % 3;
+        self.vertices.extend(triangles[..n].iter().map(|&p| {
+            let p = transform_pos(transform, p);
+            [p[0] as f32, p[1] as f32]
+        }));
+        self.items.push(Item {
+            z,
+            draw_state: *draw_state,
+            kind: Kind::Colored(color),
+            start,
+            end: self.vertices.len(),
+        });
+    }
+
+    /// Adds a colored rectangle `[x, y, w, h]`.
+    pub fn add_rectangle(
+        &mut self,
+        z: f64,
+        draw_state: &DrawState,
+        color: Color,
+        rect: [f64; 4],
+        transform: Matrix2d
+    ) {
+        let [x, y, w, h] = rect;
+        let triangles = [
+            [x, y], [x + w, y], [x, y + h],
+            [x + w, y], [x + w, y + h], [x, y + h]
+        ];
+        self.add(z, draw_state, color, &triangles, transform);
+    }
+
+    /// Adds an image drawing the texture into the rectangle `[x, y, w, h]`.
+    pub fn add_image(
+        &mut self,
+        z: f64,
+        draw_state: &DrawState,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        tint: Color,
+        transform: Matrix2d
+    ) {
+        let texture = match self.textures.iter().position(|t| t == texture) {
+            Some(i) => i,
+            None => {
+                self.textures.push(texture.clone());
+                self.textures.len() - 1
+            }
+        };
+        let [x, y, w, h] = dst_rect;
+        let corner = |u: f64, v: f64| {
+            let p = transform_pos(transform, [x + u * w, y + v * h]);
+            [p[0] as f32, p[1] as f32]
+        };
+        let start = self.vertices.len();
+        self.vertices.extend_from_slice(&[
+            corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0),
+            corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)
+        ]);
+        // Colored items have no texture coordinates, so they are padded here.
+        self.uvs.resize(start, [0.0; 2]);
+        self.uvs.extend_from_slice(&[
+            [0.0, 0.0], [1.0, 0.0], [0.0, 1.0],
+            [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]
+        ]);
+        self.items.push(Item {
+            z,
+            draw_state: *draw_state,
+            kind: Kind::Textured(texture, tint),
+            start,
+            end: self.vertices.len(),
+        });
+    }
+
+    /// Draws all draws sorted by depth and clears the queue.
+    pub fn flush<C>(&mut self, g: &mut GfxGraphics<R, C>)
+        where C: gfx::CommandBuffer<R>
+    {
+        let mut order: Vec<usize> = (0..self.items.len()).collect();
+        {
+            let items = &self.items;
+            // The sort is stable, so draws of the same depth keep their order.
+            // NaN depths are sorted after all others.
+            order.sort_by(|&a, &b| items[a].z.total_cmp(&items[b].z));
+        }
+
+        let mut sorted = Vec::with_capacity(order.len());
+        let mut groups: Vec<(Kind, DrawState, Vec<usize>)> = vec![];
+        let mut transparent = vec![];
+        let mut i = 0;
+        while i < order.len() {
+            // Split the draws of the same depth into opaque groups and transparent draws.
+            // Depths are compared by bits like in the sort, so NaN depths are grouped too.
+            let z = self.items[order[i]].z.to_bits();
+            while i < order.len() && self.items[order[i]].z.to_bits() == z {
+                let item = &self.items[order[i]];
+                if g.is_blending(&item.draw_state) {
+                    transparent.push(order[i]);
+                } else {
+                    match groups.iter_mut()
+                        .find(|group| group.0 == item.kind && group.1 == item.draw_state)
+                    {
+                        Some(group) => group.2.push(order[i]),
+                        None => groups.push((item.kind, item.draw_state, vec![order[i]])),
+                    }
+                }
+                i += 1;
+            }
+            for (_, _, indices) in groups.drain(..) {
+                sorted.extend(indices);
+            }
+            sorted.append(&mut transparent);
+        }
+
+        // Consecutive textured draws with the same texture, tint and draw state
+        // are drawn together. Colored draws are batched by `GfxGraphics`.
+        // Only whole triangles are drawn per chunk.
+        let chunk = BUFFER_SIZE - BUFFER_SIZE % 3;
+        let mut vertices: Vec<[f32; 2]> = Vec::with_capacity(chunk);
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(chunk);
+        let mut i = 0;
+        while i < sorted.len() {
+            let item = &self.items[sorted[i]];
+            match item.kind {
+                Kind::Colored(color) => {
+                    let positions = &self.vertices[item.start..item.end];
+                    g.tri_list(&item.draw_state, &color, |f| f(positions));
+                    i += 1;
+                }
+                Kind::Textured(texture, tint) => {
+                    let mut j = i;
+                    while j < sorted.len() {
+                        let next = &self.items[sorted[j]];
+                        if next.kind != item.kind || next.draw_state != item.draw_state {break}
+                        j += 1;
+                    }
+                    let texture = &self.textures[texture];
+                    vertices.clear();
+                    uvs.clear();
+                    for &k in &sorted[i..j] {
+                        let next = &self.items[k];
+                        vertices.extend_from_slice(&self.vertices[next.start..next.end]);
+                        uvs.extend_from_slice(&self.uvs[next.start..next.end]);
+                    }
+                    for (vertices, uvs) in vertices.chunks(chunk).zip(uvs.chunks(chunk)) {
+                        g.tri_list_uv(&item.draw_state, &tint, texture, |f| f(vertices, uvs));
+                    }
+                    i = j;
+                }
+            }
+        }
+
+        self.items.clear();
+        self.vertices.clear();
+        self.uvs.clear();
+        self.textures.clear();
+    }
+}
+
+impl<R: gfx::Resources> Default for DrawQueue<R> {
+    fn default() -> DrawQueue<R> {
+        DrawQueue::new()
+    }
+}
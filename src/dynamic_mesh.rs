@@ -0,0 +1,143 @@
+//! Meshes with a few vertices changing per frame, e.g. tilemaps.
+
+use std::ops::Range;
+
+use gfx;
+use gfx::memory::{Bind, Usage};
+use graphics::DrawState;
+use graphics::math::Matrix2d;
+use GfxGraphics;
+use MeshVertex;
+use Texture;
+
+/// A textured mesh uploading only the vertices changed since the last draw.
+///
+/// The vertices are kept on the CPU as well, and the ranges changed by `set_vertices`
+/// are tracked. Before drawing, overlapping and adjacent ranges are merged,
+/// and each merged range is uploaded with one buffer update, so neither the
+/// whole buffer is uploaded nor the whole mesh regenerated when a few tiles change.
+/// The number of vertices is fixed when the mesh is created.
+pub struct DynamicMesh<R: gfx::Resources> {
+    buffer: gfx::handle::Buffer<R, MeshVertex>,
+    vertices: Vec<MeshVertex>,
+    // The ranges changed since the last upload, not merged yet.
+    dirty: Vec<Range<usize>>,
+}
+
+// Sorts and drains ranges, merging overlapping and adjacent ones.
+fn merge_ranges(ranges: &mut Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for r in ranges.drain(..) {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+impl<R: gfx::Resources> DynamicMesh<R> {
+    /// Creates a mesh from `MeshVertex`es in triangle list order,
+    /// uploaded with the first draw.
+    pub fn new<F>(factory: &mut F, vertices: &[MeshVertex])
+        -> Result<DynamicMesh<R>, gfx::buffer::CreationError>
+        where F: gfx::Factory<R>
+    {
+        let buffer = factory.create_buffer(
+            vertices.len(), gfx::buffer::Role::Vertex, Usage::Dynamic, Bind::empty())?;
+        let mut dirty = vec![];
+        if !vertices.is_empty() {
+            dirty.push(0..vertices.len());
+        }
+        Ok(DynamicMesh {
+            buffer,
+            vertices: vertices.to_vec(),
+            dirty,
+        })
+    }
+
+    /// Returns the number of vertices.
+    pub fn len(&self) -> usize {self.vertices.len()}
+
+    /// Returns `true` if the mesh has no vertices.
+    pub fn is_empty(&self) -> bool {self.vertices.is_empty()}
+
+    /// Returns the vertices, including changes not uploaded yet.
+    pub fn vertices(&self) -> &[MeshVertex] {&self.vertices}
+
+    /// Returns `true` if some vertices changed since the last upload.
+    pub fn is_dirty(&self) -> bool {!self.dirty.is_empty()}
+
+    /// Replaces a range of vertices, uploaded with the next draw.
+    ///
+    /// Panics if the range is out of bounds or does not have the length of `data`.
+    pub fn set_vertices(&mut self, range: Range<usize>, data: &[MeshVertex]) {
+        assert_eq!(range.end - range.start, data.len(),
+            "range must have the length of the vertices");
+        self.vertices[range.clone()].copy_from_slice(data);
+        if !data.is_empty() {
+            self.dirty.push(range);
+        }
+    }
+
+    /// Uploads the changed vertices and returns the number of buffer updates.
+    ///
+    /// This is called by `draw`, so it is only needed to upload without drawing.
+    pub fn upload<C>(&mut self, encoder: &mut gfx::Encoder<R, C>) -> usize
+        where C: gfx::CommandBuffer<R>
+    {
+        if self.dirty.is_empty() {return 0};
+        let merged = merge_ranges(&mut self.dirty);
+        for r in &merged {
+            encoder.update_buffer(&self.buffer, &self.vertices[r.clone()], r.start).unwrap();
+        }
+        merged.len()
+    }
+
+    /// Uploads the changed vertices and draws the mesh like `GfxGraphics::draw_mesh`.
+    pub fn draw<C>(
+        &mut self,
+        g: &mut GfxGraphics<R, C>,
+        texture: &Texture<R>,
+        color: &[f32; 4],
+        transform: Matrix2d,
+        draw_state: &DrawState
+    )
+        where C: gfx::CommandBuffer<R>
+    {
+        self.upload(g.encoder);
+        let slice = gfx::Slice {
+            instances: None,
+            start: 0,
+            end: self.vertices.len() as u32,
+            buffer: gfx::IndexBuffer::Auto,
+            base_vertex: 0,
+        };
+        g.draw_mesh(&self.buffer, &slice, texture, color, transform, draw_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge_ranges;
+
+    #[test]
+    fn merge_overlapping() {
+        let mut ranges = vec![4..10, 0..6, 5..7];
+        assert_eq!(merge_ranges(&mut ranges), vec![0..10]);
+        assert!(ranges.is_empty());
+    }
+
+    #[test]
+    fn merge_adjacent() {
+        let mut ranges = vec![6..9, 0..3, 3..6];
+        assert_eq!(merge_ranges(&mut ranges), vec![0..9]);
+    }
+
+    #[test]
+    fn keep_disjoint() {
+        let mut ranges = vec![8..12, 0..2, 3..5];
+        assert_eq!(merge_ranges(&mut ranges), vec![0..2, 3..5, 8..12]);
+    }
+}
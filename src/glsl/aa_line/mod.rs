@@ -0,0 +1,6 @@
+//! Shaders for anti-aliased lines using coverage.
+
+/// Fragment shader for GLSL 1.20
+pub const FRAGMENT_GLSL_120: &[u8] = include_bytes!("120.glslf");
+/// Fragment shader for GLSL 1.50
+pub const FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslf");
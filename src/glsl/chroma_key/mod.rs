@@ -0,0 +1,9 @@
+//! Shaders for textures with a hue range treated as transparent, e.g. green screens.
+//!
+//! Keying and despill happen in the Oklab color space,
+//! where hue angles match perceived hues more closely than in RGB.
+
+/// Fragment shader for GLSL 1.20
+pub const FRAGMENT_GLSL_120: &[u8] = include_bytes!("120.glslf");
+/// Fragment shader for GLSL 1.50
+pub const FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslf");
@@ -0,0 +1,20 @@
+//! Shaders for rectangles and images with a depth, drawn with the depth test.
+//!
+//! The depth is a uniform, since all vertices of a draw have the same depth.
+
+/// Vertex shader of colored rectangles for GLSL 1.20
+pub const COLORED_VERTEX_GLSL_120: &[u8] = include_bytes!("colored_120.glslv");
+/// Vertex shader of colored rectangles for GLSL 1.50
+pub const COLORED_VERTEX_GLSL_150_CORE: &[u8] = include_bytes!("colored_150_core.glslv");
+/// Fragment shader of colored rectangles for GLSL 1.20
+pub const COLORED_FRAGMENT_GLSL_120: &[u8] = include_bytes!("colored_120.glslf");
+/// Fragment shader of colored rectangles for GLSL 1.50
+pub const COLORED_FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("colored_150_core.glslf");
+/// Vertex shader of images for GLSL 1.20
+pub const TEXTURED_VERTEX_GLSL_120: &[u8] = include_bytes!("textured_120.glslv");
+/// Vertex shader of images for GLSL 1.50
+pub const TEXTURED_VERTEX_GLSL_150_CORE: &[u8] = include_bytes!("textured_150_core.glslv");
+/// Fragment shader of images for GLSL 1.20
+pub const TEXTURED_FRAGMENT_GLSL_120: &[u8] = include_bytes!("textured_120.glslf");
+/// Fragment shader of images for GLSL 1.50
+pub const TEXTURED_FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("textured_150_core.glslf");
@@ -0,0 +1,8 @@
+//! Shaders for colored triangles with ordered dithering.
+//!
+//! A 4x4 Bayer pattern is added before quantization to reduce banding of gradients.
+
+/// Fragment shader for GLSL 1.20
+pub const FRAGMENT_GLSL_120: &[u8] = include_bytes!("120.glslf");
+/// Fragment shader for GLSL 1.50
+pub const FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslf");
@@ -0,0 +1,8 @@
+//! Shaders for downsampling a texture with a box filter.
+//!
+//! Each pixel of the target averages a square of texels, found from the pixel position.
+
+/// Fragment shader for GLSL 1.20
+pub const FRAGMENT_GLSL_120: &[u8] = include_bytes!("120.glslf");
+/// Fragment shader for GLSL 1.50
+pub const FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslf");
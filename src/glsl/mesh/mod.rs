@@ -0,0 +1,8 @@
+//! Vertex shaders for textured meshes in vertex buffers owned by the app.
+//!
+//! The vertices are transformed on the GPU, since the buffers are not updated per frame.
+
+/// Vertex shader for GLSL 1.20
+pub const VERTEX_GLSL_120: &[u8] = include_bytes!("120.glslv");
+/// Vertex shader for GLSL 1.50
+pub const VERTEX_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslv");
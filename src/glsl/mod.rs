@@ -0,0 +1,27 @@
+//! Shaders for effects that are not covered by `piston-shaders_graphics2d`.
+//!
+//! Vertex shaders are shared with the colored and textured shaders where possible.
+
+pub mod aa_line;
+pub mod checkerboard;
+pub mod chroma_key;
+pub mod color_key;
+pub mod depth;
+pub mod dissolve;
+pub mod downsample;
+pub mod dither;
+pub mod gradient;
+pub mod grain;
+pub mod instanced_sprite;
+pub mod masked;
+pub mod mesh;
+pub mod mesh_colored;
+pub mod mul_add;
+pub mod outline;
+pub mod pattern;
+pub mod rounded_image;
+pub mod sdf;
+pub mod soft_point;
+pub mod textured_color;
+pub mod textured_lod;
+pub mod vignette;
@@ -0,0 +1,8 @@
+//! Shaders for textures with an outline around opaque texels.
+//!
+//! The outline is found by dilating the alpha channel, sampling neighbouring texels.
+
+/// Fragment shader for GLSL 1.20
+pub const FRAGMENT_GLSL_120: &[u8] = include_bytes!("120.glslf");
+/// Fragment shader for GLSL 1.50
+pub const FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslf");
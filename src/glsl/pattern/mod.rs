@@ -0,0 +1,9 @@
+//! Shaders for pattern fills.
+//!
+//! The pattern coordinates are computed from the position,
+//! so the fragment shader is shared with the textured shaders.
+
+/// Vertex shader for GLSL 1.20
+pub const VERTEX_GLSL_120: &[u8] = include_bytes!("120.glslv");
+/// Vertex shader for GLSL 1.50
+pub const VERTEX_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslv");
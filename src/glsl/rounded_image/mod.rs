@@ -0,0 +1,9 @@
+//! Shaders for textures with rounded corners.
+//!
+//! The corners are cut with the distance to a rounded rectangle,
+//! anti-aliased over one pixel.
+
+/// Fragment shader for GLSL 1.20
+pub const FRAGMENT_GLSL_120: &[u8] = include_bytes!("120.glslf");
+/// Fragment shader for GLSL 1.50
+pub const FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslf");
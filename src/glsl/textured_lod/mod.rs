@@ -0,0 +1,8 @@
+//! Shaders for textures sampled at a fixed mipmap level.
+//!
+//! GLSL 1.20 requires the `GL_ARB_shader_texture_lod` extension.
+
+/// Fragment shader for GLSL 1.20
+pub const FRAGMENT_GLSL_120: &[u8] = include_bytes!("120.glslf");
+/// Fragment shader for GLSL 1.50
+pub const FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslf");
@@ -0,0 +1,9 @@
+//! Shaders for vignettes darkening the edges of the screen.
+//!
+//! The falloff is computed from the screen position,
+//! and the vertex shader of the checkerboard is reused for the full-screen triangle.
+
+/// Fragment shader for GLSL 1.20
+pub const FRAGMENT_GLSL_120: &[u8] = include_bytes!("120.glslf");
+/// Fragment shader for GLSL 1.50
+pub const FRAGMENT_GLSL_150_CORE: &[u8] = include_bytes!("150_core.glslf");
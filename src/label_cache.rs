@@ -0,0 +1,137 @@
+//! Caching of whole text labels in textures.
+//!
+//! Each label is rasterized once into its own texture and drawn as a single quad,
+//! which is cheaper than one quad per glyph for many small, static labels,
+//! e.g. data labels of charts. This trades texture memory for fewer vertices.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use gfx;
+use gfx_texture::{self, CreateTexture, Format, TextureContext, TextureSettings};
+use graphics::types::{FontSize, Scalar};
+use rusttype;
+use Texture;
+
+/// A pre-rasterized text label.
+pub struct Label<R: gfx::Resources> {
+    /// The texture storing the label, white with coverage in the alpha channel.
+    pub texture: Texture<R>,
+    /// The size of the label in pixels.
+    pub size: [Scalar; 2],
+    /// The distance from the top of the label to the baseline in pixels.
+    pub ascent: Scalar,
+}
+
+/// A cache of text labels rasterized into textures, looked up by id.
+pub struct LabelCache<'a, R: gfx::Resources, K: Hash + Eq> {
+    /// The font.
+    pub font: rusttype::Font<'a>,
+    settings: TextureSettings,
+    labels: HashMap<K, Label<R>>,
+}
+
+impl<'a, R, K> LabelCache<'a, R, K>
+    where R: gfx::Resources,
+          K: Hash + Eq
+{
+    /// Constructs a label cache from a font.
+    pub fn from_font(font: rusttype::Font<'a>, settings: TextureSettings) -> Self {
+        LabelCache {
+            font,
+            settings,
+            labels: HashMap::new(),
+        }
+    }
+
+    /// Constructs a label cache from a font stored in memory.
+    ///
+    /// Returns an error if the data is not a font, or a collection of several fonts.
+    pub fn from_bytes(font: &'a [u8], settings: TextureSettings)
+        -> Result<Self, rusttype::Error>
+    {
+        let collection = rusttype::FontCollection::from_bytes(font)?;
+        let font = collection.into_font()?;
+        Ok(LabelCache::from_font(font, settings))
+    }
+
+    /// Rasterizes a label and stores it with the id, replacing an old label.
+    ///
+    /// Font size is in points, like for `GlyphCache`.
+    /// With the `bidi` feature, right-to-left text is laid out in visual order.
+    pub fn insert<F, C>(
+        &mut self,
+        context: &mut TextureContext<F, R, C>,
+        id: K,
+        text: &str,
+        font_size: FontSize
+    ) -> Result<(), gfx_texture::Error>
+        where F: gfx::Factory<R>,
+              C: gfx::CommandBuffer<R>
+    {
+        use self::rusttype as rt;
+
+        #[cfg(feature = "bidi")]
+        let text = &::bidi::reorder(text, None);
+        let scale = rt::Scale::uniform((font_size as f32 * 1.333).round());
+        let v_metrics = self.font.v_metrics(scale);
+        let glyphs: Vec<_> = self.font
+            .layout(text, scale, rt::point(0.0, v_metrics.ascent))
+            .collect();
+        let width = glyphs.last().map(|glyph| {
+            glyph.position().x + glyph.unpositioned().h_metrics().advance_width
+        }).unwrap_or(0.0);
+        // Textures can not be empty.
+        let size = [
+            (width.ceil() as u32).max(1),
+            ((v_metrics.ascent - v_metrics.descent).ceil() as u32).max(1),
+        ];
+
+        let mut coverage = vec![0; (size[0] * size[1]) as usize];
+        for glyph in &glyphs {
+            let bb = match glyph.pixel_bounding_box() {
+                Some(bb) => bb,
+                None => continue,
+            };
+            glyph.draw(|x, y, v| {
+                let x = x as i32 + bb.min.x;
+                let y = y as i32 + bb.min.y;
+                if x >= 0 && y >= 0 && (x as u32) < size[0] && (y as u32) < size[1] {
+                    let i = (x as u32 + y as u32 * size[0]) as usize;
+                    coverage[i] = coverage[i].max((v * 255.0) as u8);
+                }
+            });
+        }
+
+        let buffer = gfx_texture::ops::alpha_to_rgba8(&coverage, size);
+        let texture = CreateTexture::create(context, Format::Rgba8,
+            &buffer, size, &self.settings)?;
+        self.labels.insert(id, Label {
+            texture,
+            size: [size[0] as Scalar, size[1] as Scalar],
+            ascent: v_metrics.ascent as Scalar,
+        });
+        Ok(())
+    }
+
+    /// Returns the label with the id, if it is cached.
+    pub fn get(&self, id: &K) -> Option<&Label<R>> {
+        self.labels.get(id)
+    }
+
+    /// Removes the label with the id, e.g. when its text changed.
+    pub fn remove(&mut self, id: &K) -> Option<Label<R>> {
+        self.labels.remove(id)
+    }
+
+    /// Removes all labels.
+    pub fn clear(&mut self) {
+        self.labels.clear();
+    }
+
+    /// Returns the number of cached labels.
+    pub fn len(&self) -> usize {self.labels.len()}
+
+    /// Returns `true` if no labels are cached.
+    pub fn is_empty(&self) -> bool {self.labels.is_empty()}
+}
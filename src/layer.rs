@@ -0,0 +1,49 @@
+//! Offscreen layers for drawing groups of elements with one opacity.
+
+use gfx;
+use gfx::format::{DepthStencil, Srgba8};
+use gfx::texture::{FilterMethod, SamplerInfo, WrapMode};
+use Texture;
+
+/// An offscreen render target storing premultiplied alpha,
+/// drawn to and composited by `GfxGraphics::with_layer`.
+///
+/// Create it once with the size of the target it is composited onto,
+/// and reuse it across frames. Nested layers need one object per level.
+pub struct Layer<R: gfx::Resources> {
+    texture: Texture<R>,
+    target: gfx::handle::RenderTargetView<R, Srgba8>,
+    stencil: gfx::handle::DepthStencilView<R, DepthStencil>,
+    size: [u16; 2],
+}
+
+impl<R: gfx::Resources> Layer<R> {
+    /// Creates an `Srgba8` render target with bilinear, clamped sampling.
+    pub fn new<F>(factory: &mut F, width: u16, height: u16)
+        -> Result<Layer<R>, gfx::CombinedError>
+        where F: gfx::Factory<R>
+    {
+        let sampler = factory.create_sampler(
+            SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        let (surface, view, target) = factory.create_render_target::<Srgba8>(width, height)?;
+        let stencil = factory.create_depth_stencil_view_only(width, height)?;
+        Ok(Layer {
+            texture: Texture { surface, sampler, view },
+            target,
+            stencil,
+            size: [width, height],
+        })
+    }
+
+    /// Returns the size of the layer in pixels.
+    pub fn size(&self) -> [u16; 2] {self.size}
+
+    /// Returns the texture with the premultiplied contents of the last draw.
+    pub fn texture(&self) -> &Texture<R> {&self.texture}
+
+    /// Returns the render target of the layer.
+    pub fn target(&self) -> &gfx::handle::RenderTargetView<R, Srgba8> {&self.target}
+
+    /// Returns the depth-stencil buffer of the layer.
+    pub fn stencil(&self) -> &gfx::handle::DepthStencilView<R, DepthStencil> {&self.stencil}
+}
@@ -26,7 +26,7 @@
 //!     if let Some(args) = e.render_args() {
 //!         g2d.draw(&mut encoder, &output_color, &output_stencil, args.viewport(), |c, g| {
 //!             ...
-//!         }
+//!         });
 //!     }
 //! }
 //! ```
@@ -55,10 +55,39 @@ extern crate gfx_texture;
 extern crate graphics;
 extern crate shaders_graphics2d as shaders;
 extern crate shader_version;
+extern crate rusttype;
+extern crate image;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "bidi")]
+extern crate unicode_bidi;
+#[cfg(test)]
+extern crate gfx_core;
 
 pub use gfx_texture::*;
 
-pub use back_end::{ Gfx2d, GfxGraphics };
+pub use back_end::{ ChromaKey, ClipPlane, ClipRegion, CustomPipeline, CustomTargets, DrawFn,
+    DrawStats, FormatError, FrameCallback, FullScreenVertex, Gfx2d, Gfx2dError, GfxGraphics,
+    GridStyle, MeshColor, MeshPosition, MeshVertex, Origin, PatternAnchor, PsoVariant,
+    RenderState, ResolveError, ResolvedState, SpriteAnchor, SpriteInstance, SpriteOptions,
+    SpritePlacement, StencilConfig };
+#[cfg(feature = "debug")]
+pub use back_end::{ DebugBuffers, DebugPipeline };
+pub use bitmap_font::BitmapFont;
+pub use camera::Camera2d;
+pub use draw_queue::DrawQueue;
+pub use dynamic_mesh::DynamicMesh;
+pub use label_cache::{ Label, LabelCache };
+pub use layer::Layer;
+pub use painter::Painter;
+#[cfg(feature = "pass_graph")]
+pub use pass_graph::{ PassFn, PassGraph, PassGraphError, PassTarget, TargetPool };
+pub use ping_pong::PingPong;
+pub use recorder::{ CaptureError, CaptureFormat, FrameRecorder };
+pub use sdf::{ SdfGlyph, SdfGlyphCache, SdfStyle };
+pub use sprite_batch::SpriteBatch;
+pub use texture::{ max_texture_size, TextureBuilder, TextureError, UpdateRegion,
+    UploadAsync, UploadPath };
 // pub use glyph::Error as GlyphError;
 // pub use glyph::GlyphCache;
 
@@ -67,3 +96,21 @@ pub type GlyphCache<'a, F, R, C> =
     graphics::glyph_cache::rusttype::GlyphCache<'a, TextureContext<F, R, C>, Texture<R>>;
 
 mod back_end;
+#[cfg(feature = "bidi")]
+pub mod bidi;
+mod bitmap_font;
+mod camera;
+mod debug_font;
+mod draw_queue;
+mod dynamic_mesh;
+mod glsl;
+mod label_cache;
+mod layer;
+mod painter;
+#[cfg(feature = "pass_graph")]
+mod pass_graph;
+mod ping_pong;
+mod recorder;
+pub mod sdf;
+mod sprite_batch;
+mod texture;
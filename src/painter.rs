@@ -0,0 +1,179 @@
+//! An immediate-mode drawing front end with a state stack, like the HTML canvas.
+
+use gfx;
+use graphics::{Context, DrawState};
+use graphics::draw_state::Blend;
+use graphics::math::{multiply, rotate_radians, scale, transform_pos, translate, Matrix2d};
+use graphics::types::Color;
+use back_end::{GfxGraphics, SpriteOptions};
+use Texture;
+
+// The settings saved by `Painter::push`.
+#[derive(Copy, Clone)]
+struct PainterState {
+    // The transform relative to the transform of the context.
+    transform: Matrix2d,
+    draw_state: DrawState,
+    tint: Color,
+}
+
+/// Draws with a current transform, blend, tint and clip rectangle,
+/// which are saved with `push` and restored with `pop`.
+///
+/// Shapes are in the coordinates of the current transform, which starts as the
+/// transform of the context. Drawing goes through the helpers of `GfxGraphics`,
+/// so the DPI scale applies like for them.
+pub struct Painter<'g, 'a: 'g, R, C>
+    where R: gfx::Resources + 'a,
+          C: gfx::CommandBuffer<R> + 'a
+{
+    g: &'g mut GfxGraphics<'a, R, C>,
+    view: Matrix2d,
+    state: PainterState,
+    stack: Vec<PainterState>,
+}
+
+impl<'g, 'a, R, C> Painter<'g, 'a, R, C>
+    where R: gfx::Resources,
+          C: gfx::CommandBuffer<R>
+{
+    /// Creates a painter with the transform and draw state of a context.
+    pub fn new(c: &Context, g: &'g mut GfxGraphics<'a, R, C>) -> Self {
+        Painter {
+            g,
+            view: c.transform,
+            state: PainterState {
+                transform: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+                draw_state: c.draw_state,
+                tint: [1.0; 4],
+            },
+            stack: vec![],
+        }
+    }
+
+    /// Returns the graphics, e.g. to call other helpers with `transform` and `draw_state`.
+    pub fn graphics(&mut self) -> &mut GfxGraphics<'a, R, C> {self.g}
+
+    /// Saves the current transform, blend, tint and clip rectangle.
+    pub fn push(&mut self) {
+        self.stack.push(self.state);
+    }
+
+    /// Restores the settings saved by the last `push`.
+    ///
+    /// Does nothing if there is no saved state, like `restore` of the HTML canvas.
+    pub fn pop(&mut self) {
+        if let Some(state) = self.stack.pop() {
+            self.state = state;
+        }
+    }
+
+    /// Returns the number of saved states.
+    pub fn depth(&self) -> usize {self.stack.len()}
+
+    /// Saves the current settings and multiplies the transform with a transform.
+    pub fn push_transform(&mut self, transform: Matrix2d) {
+        self.push();
+        self.state.transform = multiply(self.state.transform, transform);
+    }
+
+    /// Moves the origin of the current transform.
+    pub fn translate(&mut self, x: f64, y: f64) {
+        self.state.transform = multiply(self.state.transform, translate([x, y]));
+    }
+
+    /// Scales the current transform.
+    pub fn scale(&mut self, sx: f64, sy: f64) {
+        self.state.transform = multiply(self.state.transform, scale(sx, sy));
+    }
+
+    /// Rotates the current transform, clockwise in radians with the y axis pointing down.
+    pub fn rotate(&mut self, angle: f64) {
+        self.state.transform = multiply(self.state.transform, rotate_radians(angle));
+    }
+
+    /// Returns the current transform, including the transform of the context.
+    pub fn transform(&self) -> Matrix2d {
+        multiply(self.view, self.state.transform)
+    }
+
+    /// Returns the current draw state, with the blend setting and clip rectangle.
+    pub fn draw_state(&self) -> DrawState {self.state.draw_state}
+
+    /// Sets the blend setting, which is the one of the context at first.
+    pub fn set_blend(&mut self, blend: Option<Blend>) {
+        self.state.draw_state.blend = blend;
+    }
+
+    /// Sets the color multiplied with all colors and images, which is white at first.
+    pub fn set_tint(&mut self, tint: Color) {
+        self.state.tint = tint;
+    }
+
+    /// Clips drawing to the rectangle `[x, y, w, h]`, intersected with the current clip.
+    ///
+    /// Clipping uses a scissor rectangle, so rotated or skewed rectangles are clipped
+    /// to their bounding box. The transform of the context must not rotate or skew.
+    pub fn clip(&mut self, rect: [f64; 4]) {
+        let [x, y, w, h] = rect;
+        let t = self.state.transform;
+        let corners = [[x, y], [x + w, y], [x, y + h], [x + w, y + h]];
+        let mut min = [f64::INFINITY; 2];
+        let mut max = [f64::NEG_INFINITY; 2];
+        for &p in &corners {
+            let p = transform_pos(t, p);
+            for i in 0..2 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+        let bounds = [min[0], min[1], max[0] - min[0], max[1] - min[1]];
+        self.state.draw_state = self.g.clip_scissor(bounds, self.view, &self.state.draw_state);
+    }
+
+    /// Fills the rectangle `[x, y, w, h]`.
+    pub fn fill_rect(&mut self, rect: [f64; 4], color: Color) {
+        let [x, y, w, h] = rect;
+        let color = self.tinted(color);
+        let (transform, draw_state) = (self.transform(), self.state.draw_state);
+        let strip = [[x, y], [x + w, y], [x, y + h], [x + w, y + h]];
+        self.g.draw_strips(&color, &[&strip], transform, &draw_state);
+    }
+
+    /// Draws the outline of the rectangle `[x, y, w, h]`, inside its bounds.
+    pub fn stroke_rect(&mut self, rect: [f64; 4], thickness: f64, color: Color) {
+        let color = self.tinted(color);
+        let (transform, draw_state) = (self.transform(), self.state.draw_state);
+        self.g.rectangle_outline(&color, rect, thickness, transform, &draw_state);
+    }
+
+    /// Fills an ellipse with radii `[rx, ry]` around a center.
+    pub fn fill_ellipse(&mut self, center: [f64; 2], radii: [f64; 2], color: Color) {
+        let color = self.tinted(color);
+        let (transform, draw_state) = (self.transform(), self.state.draw_state);
+        self.g.ellipse(&color, center, radii, transform, &draw_state);
+    }
+
+    /// Fills a convex polygon with antialiased edges.
+    pub fn fill_polygon(&mut self, polygon: &[[f64; 2]], color: Color) {
+        let color = self.tinted(color);
+        let (transform, draw_state) = (self.transform(), self.state.draw_state);
+        self.g.fill_polygon_aa(&color, polygon, transform, &draw_state);
+    }
+
+    /// Draws a texture into the rectangle `[x, y, w, h]`, tinted by the current tint.
+    pub fn draw_image(&mut self, texture: &Texture<R>, dst_rect: [f64; 4]) {
+        let options = SpriteOptions {
+            tint: self.state.tint,
+            ..SpriteOptions::default()
+        };
+        let (transform, draw_state) = (self.transform(), self.state.draw_state);
+        self.g.draw_sprite(texture, dst_rect, &options, transform, &draw_state);
+    }
+
+    // Multiplies a color with the current tint.
+    fn tinted(&self, color: Color) -> Color {
+        let t = self.state.tint;
+        [color[0] * t[0], color[1] * t[1], color[2] * t[2], color[3] * t[3]]
+    }
+}
@@ -0,0 +1,400 @@
+//! Declarative chains of render passes with automatic reuse of intermediate targets.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use gfx;
+use gfx::format::{DepthStencil, Srgba8};
+use gfx::handle::{DepthStencilView, RenderTargetView};
+use gfx::texture::{FilterMethod, SamplerInfo, WrapMode};
+use graphics::{Context, Viewport};
+use Gfx2d;
+use GfxGraphics;
+use Texture;
+
+/// An intermediate render target declared with `PassGraph::target`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PassTarget(usize);
+
+/// A closure drawing a pass, see `PassGraph::add_pass`.
+///
+/// The textures are the targets read by the pass, in the order they were declared.
+pub type PassFn<'a, R, C> = dyn FnMut(Context, &mut GfxGraphics<R, C>, &[&Texture<R>]) + 'a;
+
+/// An error when executing a pass graph.
+#[derive(Debug)]
+pub enum PassGraphError {
+    /// A pass uses a target that was not declared by the graph.
+    UnknownTarget {
+        /// The name of the pass.
+        pass: String,
+    },
+    /// A pass writes a target that another pass writes too.
+    MultipleWriters {
+        /// The name of the pass.
+        pass: String,
+    },
+    /// A pass reads a target that no pass writes.
+    NotWritten {
+        /// The name of the pass.
+        pass: String,
+    },
+    /// A pass depends on its own output, directly or through other passes.
+    Cycle {
+        /// The name of the pass.
+        pass: String,
+    },
+    /// An error when creating a render target.
+    Target(gfx::CombinedError),
+}
+
+impl From<gfx::CombinedError> for PassGraphError {
+    fn from(val: gfx::CombinedError) -> PassGraphError {
+        PassGraphError::Target(val)
+    }
+}
+
+impl fmt::Display for PassGraphError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PassGraphError::UnknownTarget { ref pass } =>
+                write!(w, "Pass `{}` uses a target from another graph", pass),
+            PassGraphError::MultipleWriters { ref pass } =>
+                write!(w, "Pass `{}` writes a target that is already written", pass),
+            PassGraphError::NotWritten { ref pass } =>
+                write!(w, "Pass `{}` reads a target that no pass writes", pass),
+            PassGraphError::Cycle { ref pass } =>
+                write!(w, "Pass `{}` depends on its own output", pass),
+            PassGraphError::Target(ref err) => write!(w, "Could not create target: {}", err),
+        }
+    }
+}
+
+impl StdError for PassGraphError {}
+
+struct PooledTarget<R: gfx::Resources> {
+    size: [u16; 2],
+    texture: Texture<R>,
+    color: RenderTargetView<R, Srgba8>,
+    stencil: DepthStencilView<R, DepthStencil>,
+    in_use: bool,
+}
+
+/// Render targets kept across frames for the intermediate targets of pass graphs.
+///
+/// Targets of the same size are reused by passes that do not overlap in time,
+/// so a chain of any length at one size needs only two or three targets.
+pub struct TargetPool<R: gfx::Resources> {
+    targets: Vec<PooledTarget<R>>,
+}
+
+impl<R: gfx::Resources> TargetPool<R> {
+    /// Creates an empty pool.
+    pub fn new() -> TargetPool<R> {
+        TargetPool { targets: vec![] }
+    }
+
+    /// Returns the number of render targets allocated.
+    pub fn len(&self) -> usize {self.targets.len()}
+
+    /// Returns `true` if no render targets are allocated.
+    pub fn is_empty(&self) -> bool {self.targets.is_empty()}
+
+    /// Frees all render targets, e.g. after the window is resized.
+    pub fn clear(&mut self) {
+        self.targets.clear();
+    }
+
+    // Returns the index of a free target of a size, creating one if there is none.
+    fn acquire<F>(&mut self, factory: &mut F, size: [u16; 2]) -> Result<usize, gfx::CombinedError>
+        where F: gfx::Factory<R>
+    {
+        if let Some(i) = self.targets.iter().position(|t| !t.in_use && t.size == size) {
+            self.targets[i].in_use = true;
+            return Ok(i);
+        }
+        let sampler = factory.create_sampler(
+            SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        let (surface, view, color) = factory.create_render_target::<Srgba8>(size[0], size[1])?;
+        let stencil = factory.create_depth_stencil_view_only(size[0], size[1])?;
+        self.targets.push(PooledTarget {
+            size,
+            texture: Texture { surface, sampler, view },
+            color,
+            stencil,
+            in_use: true,
+        });
+        Ok(self.targets.len() - 1)
+    }
+}
+
+impl<R: gfx::Resources> Default for TargetPool<R> {
+    fn default() -> TargetPool<R> {
+        TargetPool::new()
+    }
+}
+
+struct Pass<'a, R: gfx::Resources, C: 'a + gfx::CommandBuffer<R>> {
+    name: String,
+    reads: Vec<PassTarget>,
+    // The target written, or `None` for the output.
+    write: Option<PassTarget>,
+    f: Box<PassFn<'a, R, C>>,
+}
+
+// The targets read and written by a pass.
+struct PassDeps<'a> {
+    name: &'a str,
+    reads: &'a [PassTarget],
+    write: Option<PassTarget>,
+}
+
+// Returns the passes contributing to the output, in the order to execute them.
+fn schedule(targets: usize, passes: &[PassDeps]) -> Result<Vec<usize>, PassGraphError> {
+    let mut writers = vec![None; targets];
+    for (i, pass) in passes.iter().enumerate() {
+        let unknown = pass.reads.iter().chain(pass.write.iter())
+            .any(|t| t.0 >= targets);
+        if unknown {
+            return Err(PassGraphError::UnknownTarget { pass: pass.name.to_string() });
+        }
+        if let Some(PassTarget(t)) = pass.write {
+            if writers[t].is_some() {
+                return Err(PassGraphError::MultipleWriters { pass: pass.name.to_string() });
+            }
+            writers[t] = Some(i);
+        }
+    }
+
+    // Depth first from the passes drawing to the output, visiting 0 is unvisited,
+    // 1 is in progress and 2 is done.
+    fn visit(
+        i: usize,
+        passes: &[PassDeps],
+        writers: &[Option<usize>],
+        state: &mut [u8],
+        order: &mut Vec<usize>
+    ) -> Result<(), PassGraphError> {
+        match state[i] {
+            1 => return Err(PassGraphError::Cycle { pass: passes[i].name.to_string() }),
+            2 => return Ok(()),
+            _ => {}
+        }
+        state[i] = 1;
+        for t in passes[i].reads {
+            match writers[t.0] {
+                Some(writer) => visit(writer, passes, writers, state, order)?,
+                None => return Err(PassGraphError::NotWritten { pass: passes[i].name.to_string() }),
+            }
+        }
+        state[i] = 2;
+        order.push(i);
+        Ok(())
+    }
+
+    let mut state = vec![0; passes.len()];
+    let mut order = vec![];
+    for (i, pass) in passes.iter().enumerate() {
+        if pass.write.is_none() {
+            visit(i, passes, &writers, &mut state, &mut order)?;
+        }
+    }
+    Ok(order)
+}
+
+/// Render passes declared with the targets they read and write,
+/// executed in dependency order.
+///
+/// Declare intermediate targets with `target` and passes with `add_pass`,
+/// then call `execute` once per frame. Passes run after the passes writing
+/// the targets they read, and passes that do not contribute to the output are skipped.
+/// Intermediate targets are taken from a `TargetPool` and returned to it
+/// after their last reader, so later targets reuse them. Since a reused target
+/// keeps its old contents, passes should clear their target or cover it fully.
+pub struct PassGraph<'a, R: gfx::Resources, C: 'a + gfx::CommandBuffer<R>> {
+    targets: Vec<[u16; 2]>,
+    passes: Vec<Pass<'a, R, C>>,
+}
+
+impl<'a, R, C> PassGraph<'a, R, C>
+    where R: gfx::Resources,
+          C: gfx::CommandBuffer<R>
+{
+    /// Creates an empty graph.
+    pub fn new() -> PassGraph<'a, R, C> {
+        PassGraph {
+            targets: vec![],
+            passes: vec![],
+        }
+    }
+
+    /// Declares an intermediate `Srgba8` render target with a size in pixels.
+    pub fn target(&mut self, size: [u16; 2]) -> PassTarget {
+        self.targets.push(size);
+        PassTarget(self.targets.len() - 1)
+    }
+
+    /// Adds a pass reading some targets and drawing to a target,
+    /// or to the output of `execute` if `write` is `None`.
+    ///
+    /// The context of the closure has one unit per pixel of the target drawn to.
+    pub fn add_pass<F>(&mut self, name: &str, reads: &[PassTarget], write: Option<PassTarget>, f: F)
+        where F: FnMut(Context, &mut GfxGraphics<R, C>, &[&Texture<R>]) + 'a
+    {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            reads: reads.to_vec(),
+            write,
+            f: Box::new(f),
+        });
+    }
+
+    // Returns the passes contributing to the output, in the order to execute them.
+    fn schedule(&self) -> Result<Vec<usize>, PassGraphError> {
+        let deps: Vec<_> = self.passes.iter().map(|pass| PassDeps {
+            name: &pass.name,
+            reads: &pass.reads,
+            write: pass.write,
+        }).collect();
+        schedule(self.targets.len(), &deps)
+    }
+
+    /// Executes the passes contributing to the output and returns their number.
+    ///
+    /// Each pass is drawn with `Gfx2d::draw`, so the frame callbacks are called per pass.
+    pub fn execute<F>(
+        mut self,
+        pool: &mut TargetPool<R>,
+        g2d: &mut Gfx2d<R>,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &RenderTargetView<R, Srgba8>,
+        output_stencil: &DepthStencilView<R, DepthStencil>
+    ) -> Result<usize, PassGraphError>
+        where F: gfx::Factory<R>
+    {
+        let order = self.schedule()?;
+
+        // The position in the order after which each target is no longer read.
+        let mut last_read = vec![0; self.targets.len()];
+        for (k, &i) in order.iter().enumerate() {
+            for t in &self.passes[i].reads {
+                last_read[t.0] = k;
+            }
+        }
+
+        let mut physical: Vec<Option<usize>> = vec![None; self.targets.len()];
+        let res = (|| -> Result<(), PassGraphError> {
+            for (k, &i) in order.iter().enumerate() {
+                let pass = &mut self.passes[i];
+                if let Some(PassTarget(t)) = pass.write {
+                    physical[t] = Some(pool.acquire(factory, self.targets[t])?);
+                }
+                let inputs: Vec<&Texture<R>> = pass.reads.iter()
+                    .map(|t| &pool.targets[physical[t.0].unwrap()].texture)
+                    .collect();
+                let (color, stencil) = match pass.write {
+                    Some(PassTarget(t)) => {
+                        let target = &pool.targets[physical[t].unwrap()];
+                        (&target.color, &target.stencil)
+                    }
+                    None => (output_color, output_stencil),
+                };
+                let (w, h, _, _) = color.get_dimensions();
+                let viewport = Viewport {
+                    rect: [0, 0, w as i32, h as i32],
+                    draw_size: [w as u32, h as u32],
+                    window_size: [w as f64, h as f64],
+                };
+                let f = &mut pass.f;
+                g2d.draw(encoder, color, stencil, viewport, |c, g| f(c, g, &inputs));
+
+                // Targets are free for later passes after their last reader.
+                for t in &pass.reads {
+                    if last_read[t.0] == k {
+                        pool.targets[physical[t.0].unwrap()].in_use = false;
+                    }
+                }
+            }
+            Ok(())
+        })();
+
+        // Targets still in use after an error, or never read, are returned too.
+        for t in physical.into_iter().flatten() {
+            pool.targets[t].in_use = false;
+        }
+        res.map(|()| order.len())
+    }
+}
+
+impl<'a, R, C> Default for PassGraph<'a, R, C>
+    where R: gfx::Resources,
+          C: gfx::CommandBuffer<R>
+{
+    fn default() -> PassGraph<'a, R, C> {
+        PassGraph::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: PassTarget = PassTarget(0);
+    const B: PassTarget = PassTarget(1);
+
+    fn deps<'a>(name: &'a str, reads: &'a [PassTarget], write: Option<usize>) -> PassDeps<'a> {
+        PassDeps { name, reads, write: write.map(PassTarget) }
+    }
+
+    #[test]
+    fn schedule_in_dependency_order() {
+        // Declared out of order, with a pass not contributing to the output.
+        let passes = [
+            deps("output", &[B], None),
+            deps("blur", &[A], Some(1)),
+            deps("unused", &[], Some(2)),
+            deps("scene", &[], Some(0)),
+        ];
+        assert_eq!(schedule(3, &passes).unwrap(), vec![3, 1, 0]);
+    }
+
+    #[test]
+    fn schedule_shared_input_once() {
+        let passes = [
+            deps("scene", &[], Some(0)),
+            deps("left", &[A], None),
+            deps("right", &[A, A], None),
+        ];
+        assert_eq!(schedule(1, &passes).unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn schedule_detects_cycle() {
+        let passes = [
+            deps("first", &[B], Some(0)),
+            deps("second", &[A], Some(1)),
+            deps("output", &[A], None),
+        ];
+        match schedule(2, &passes) {
+            Err(PassGraphError::Cycle { ref pass }) => assert_eq!(pass, "first"),
+            res => panic!("expected a cycle, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn schedule_invalid_targets() {
+        match schedule(1, &[deps("output", &[A], None)]) {
+            Err(PassGraphError::NotWritten { .. }) => {}
+            res => panic!("expected an unwritten target, got {:?}", res),
+        }
+        match schedule(1, &[deps("x", &[], Some(0)), deps("y", &[], Some(0))]) {
+            Err(PassGraphError::MultipleWriters { ref pass }) => assert_eq!(pass, "y"),
+            res => panic!("expected multiple writers, got {:?}", res),
+        }
+        match schedule(1, &[deps("output", &[PassTarget(1)], None)]) {
+            Err(PassGraphError::UnknownTarget { .. }) => {}
+            res => panic!("expected an unknown target, got {:?}", res),
+        }
+    }
+}
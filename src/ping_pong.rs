@@ -0,0 +1,69 @@
+//! Pairs of render targets for multi-pass effects.
+//!
+//! Sampling a texture while it is bound as the render target is undefined behavior.
+//! A ping-pong pair avoids this by reading from one target while writing to the other,
+//! and swapping them after each pass.
+
+use gfx;
+use gfx::format::{DepthStencil, Srgba8};
+use gfx::texture::{FilterMethod, SamplerInfo, WrapMode};
+use Texture;
+
+/// Two render targets of the same size, one sampled and one drawn to.
+///
+/// This allocates two color textures and one depth-stencil buffer,
+/// shared by both targets, so it uses about twice the memory of a single target.
+/// Create it once and reuse it across frames.
+pub struct PingPong<R: gfx::Resources> {
+    targets: [(Texture<R>, gfx::handle::RenderTargetView<R, Srgba8>); 2],
+    stencil: gfx::handle::DepthStencilView<R, DepthStencil>,
+    // The index of the target drawn to next.
+    current: usize,
+    size: [u16; 2],
+}
+
+impl<R: gfx::Resources> PingPong<R> {
+    /// Creates two `Srgba8` render targets with bilinear, clamped sampling.
+    pub fn new<F>(factory: &mut F, width: u16, height: u16)
+        -> Result<PingPong<R>, gfx::CombinedError>
+        where F: gfx::Factory<R>
+    {
+        let sampler = factory.create_sampler(
+            SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        let mut target = || -> Result<_, gfx::CombinedError> {
+            let (surface, view, target) = factory.create_render_target::<Srgba8>(width, height)?;
+            Ok((Texture { surface, sampler: sampler.clone(), view }, target))
+        };
+        let targets = [target()?, target()?];
+        let stencil = factory.create_depth_stencil_view_only(width, height)?;
+        Ok(PingPong {
+            targets,
+            stencil,
+            current: 0,
+            size: [width, height],
+        })
+    }
+
+    /// Returns the size of the targets in pixels.
+    pub fn size(&self) -> [u16; 2] {self.size}
+
+    /// Returns the texture drawn to before the last swap, to sample in the next pass.
+    pub fn source(&self) -> &Texture<R> {
+        &self.targets[1 - self.current].0
+    }
+
+    /// Returns the render target to draw to in the next pass.
+    pub fn target(&self) -> &gfx::handle::RenderTargetView<R, Srgba8> {
+        &self.targets[self.current].1
+    }
+
+    /// Returns the depth-stencil buffer shared by both targets.
+    pub fn stencil(&self) -> &gfx::handle::DepthStencilView<R, DepthStencil> {
+        &self.stencil
+    }
+
+    /// Swaps the targets, so the last drawn target becomes the source.
+    pub fn swap(&mut self) {
+        self.current = 1 - self.current;
+    }
+}
@@ -0,0 +1,362 @@
+//! Recording of rendered frames, e.g. to pass to a video encoder.
+
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt;
+
+use gfx;
+use gfx::format::{Formatted, RenderFormat, Rgba16F, Srgba8, SurfaceType};
+use gfx::handle::{Buffer, RenderTargetView};
+use gfx::memory::Typed;
+use gfx::texture::RawImageInfo;
+use gfx::traits::FactoryExt;
+use graphics::color::gamma_linear_to_srgb;
+use back_end::half_to_f32;
+
+/// The pixel format of recorded frames.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// Tightly packed 8 bit RGBA, with the top row first and sRGB encoded colors.
+    Rgba,
+    /// Planar YUV 4:2:0 with BT.601 limited range, as expected by most video encoders:
+    /// the full size Y plane followed by the U and V planes, each with half the width
+    /// and height rounded up.
+    I420,
+}
+
+/// An error when capturing a frame.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureError {
+    /// The render target does not have the size of the recorder.
+    Size {
+        /// The size of the recorder.
+        expected: [u16; 2],
+        /// The size of the render target.
+        found: [u16; 2],
+    },
+    /// The render target does not have the format of the recorder, see `new_linear`.
+    Format {
+        /// The surface type of the recorder.
+        expected: SurfaceType,
+        /// The surface type of the render target.
+        found: SurfaceType,
+    },
+    /// An error when copying the render target, e.g. without the `TRANSFER_SRC` bind flag.
+    Copy(gfx::CopyError<[u16; 3], usize>),
+}
+
+impl From<gfx::CopyError<[u16; 3], usize>> for CaptureError {
+    fn from(val: gfx::CopyError<[u16; 3], usize>) -> CaptureError {
+        CaptureError::Copy(val)
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CaptureError::Size { expected, found } =>
+                write!(w, "Render target size {}x{} does not match the recorder size {}x{}",
+                    found[0], found[1], expected[0], expected[1]),
+            CaptureError::Format { expected, found } =>
+                write!(w, "Render target format {:?} does not match the recorder format {:?}",
+                    found, expected),
+            CaptureError::Copy(ref err) => write!(w, "Could not copy the render target: {:?}", err),
+        }
+    }
+}
+
+impl StdError for CaptureError {}
+
+/// Reads back rendered frames through a ring of download buffers.
+///
+/// Call `capture` after drawing each frame and before flushing the encoder,
+/// then `receive` after flushing. A frame is only read back once the ring is full,
+/// so the GPU has had a few frames to finish the copy and reading rarely stalls.
+/// With a ring of `n` buffers, frames arrive `n - 1` frames late.
+///
+/// Recording still costs a copy of the color buffer on the GPU, a read of
+/// `4 * width * height` bytes per frame over the bus, and for `I420` a conversion
+/// on the CPU, which can take a noticeable part of the frame time at high resolutions.
+/// If `receive` is not called, the ring fills up and further frames are dropped.
+pub struct FrameRecorder<R: gfx::Resources> {
+    buffers: Vec<Buffer<R, u8>>,
+    // The slots with copies that are not read yet, oldest first.
+    pending: VecDeque<usize>,
+    next: usize,
+    size: [u16; 2],
+    format: CaptureFormat,
+    // The format of the render targets.
+    target: gfx::format::Format,
+    recording: bool,
+    dropped: usize,
+    frame: Vec<u8>,
+    // Linear frames converted to `Srgba8`.
+    srgba: Vec<u8>,
+}
+
+impl<R: gfx::Resources> FrameRecorder<R> {
+    /// Creates a recorder for frames of a size, with a ring of download buffers.
+    ///
+    /// Three buffers are usually enough to avoid stalls. Recording is stopped at first.
+    /// Render targets must have the `Srgba8` format, see `new_linear` for `Rgba16F`.
+    pub fn new<F>(
+        factory: &mut F,
+        size: [u16; 2],
+        format: CaptureFormat,
+        ring_len: usize
+    ) -> Result<FrameRecorder<R>, gfx::buffer::CreationError>
+        where F: gfx::Factory<R>
+    {
+        FrameRecorder::with_target(factory, size, format, ring_len, Srgba8::get_format())
+    }
+
+    /// Creates a recorder for `Rgba16F` render targets, e.g. of `Gfx2d::new_linear`.
+    ///
+    /// Linear colors are clamped to the range `0` to `1` and encoded as sRGB,
+    /// so HDR content should be tonemapped before it is captured.
+    pub fn new_linear<F>(
+        factory: &mut F,
+        size: [u16; 2],
+        format: CaptureFormat,
+        ring_len: usize
+    ) -> Result<FrameRecorder<R>, gfx::buffer::CreationError>
+        where F: gfx::Factory<R>
+    {
+        FrameRecorder::with_target(factory, size, format, ring_len, Rgba16F::get_format())
+    }
+
+    fn with_target<F>(
+        factory: &mut F,
+        size: [u16; 2],
+        format: CaptureFormat,
+        ring_len: usize,
+        target: gfx::format::Format
+    ) -> Result<FrameRecorder<R>, gfx::buffer::CreationError>
+        where F: gfx::Factory<R>
+    {
+        let bytes = if target == Srgba8::get_format() {4} else {8};
+        let len = bytes * size[0] as usize * size[1] as usize;
+        let buffers = (0..ring_len.max(1))
+            .map(|_| factory.create_download_buffer::<u8>(len))
+            .collect::<Result<_, _>>()?;
+        Ok(FrameRecorder {
+            buffers,
+            pending: VecDeque::new(),
+            next: 0,
+            size,
+            format,
+            target,
+            recording: false,
+            dropped: 0,
+            frame: vec![],
+            srgba: vec![],
+        })
+    }
+
+    /// Returns the size of recorded frames in pixels.
+    pub fn size(&self) -> [u16; 2] {self.size}
+
+    /// Returns the pixel format of recorded frames.
+    pub fn format(&self) -> CaptureFormat {self.format}
+
+    /// Starts recording frames passed to `capture`.
+    pub fn start(&mut self) {
+        self.recording = true;
+    }
+
+    /// Stops recording. Frames captured before are still returned by `receive` and `finish`.
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    /// Returns `true` if frames are recorded.
+    pub fn is_recording(&self) -> bool {self.recording}
+
+    /// Returns the number of frames dropped because the ring was full.
+    pub fn dropped(&self) -> usize {self.dropped}
+
+    /// Copies the render target into the next download buffer, if recording.
+    ///
+    /// Returns `false` if the frame is not recorded, because recording is stopped
+    /// or the ring is full. Returns an error without copying if the render target
+    /// does not have the size and format of the recorder, or if its texture lacks
+    /// the `TRANSFER_SRC` bind flag.
+    pub fn capture<C, T>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        output_color: &RenderTargetView<R, T>
+    ) -> Result<bool, CaptureError>
+        where C: gfx::CommandBuffer<R>,
+              T: RenderFormat
+    {
+        if !self.recording {return Ok(false)};
+        if self.pending.len() >= self.buffers.len() {
+            self.dropped += 1;
+            return Ok(false);
+        }
+
+        let (w, h, _, _) = output_color.get_dimensions();
+        if [w, h] != self.size {
+            return Err(CaptureError::Size { expected: self.size, found: [w, h] });
+        }
+        if T::get_format() != self.target {
+            return Err(CaptureError::Format { expected: self.target.0, found: T::get_format().0 });
+        }
+        let info = RawImageInfo {
+            xoffset: 0,
+            yoffset: 0,
+            zoffset: 0,
+            width: w,
+            height: h,
+            depth: 0,
+            format: self.target,
+            mipmap: 0,
+        };
+        let texture = output_color.raw().get_texture();
+        let buffer = &self.buffers[self.next];
+        encoder.copy_texture_to_buffer_raw(texture, None, info, buffer.raw(), 0)?;
+        self.pending.push_back(self.next);
+        self.next = (self.next + 1) % self.buffers.len();
+        Ok(true)
+    }
+
+    /// Passes recorded frames to a closure once the ring is full, oldest first,
+    /// and returns the number of frames passed.
+    ///
+    /// Call this after the encoder is flushed.
+    pub fn receive<F, G>(&mut self, factory: &mut F, mut f: G)
+        -> Result<usize, gfx::mapping::Error>
+        where F: gfx::Factory<R>,
+              G: FnMut(&[u8])
+    {
+        let mut n = 0;
+        while self.pending.len() >= self.buffers.len() {
+            self.read_oldest(factory, &mut f)?;
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Passes all recorded frames to a closure, oldest first,
+    /// and returns the number of frames passed, e.g. after stopping.
+    ///
+    /// This waits for the GPU to finish the copies.
+    pub fn finish<F, G>(&mut self, factory: &mut F, mut f: G)
+        -> Result<usize, gfx::mapping::Error>
+        where F: gfx::Factory<R>,
+              G: FnMut(&[u8])
+    {
+        let mut n = 0;
+        while !self.pending.is_empty() {
+            self.read_oldest(factory, &mut f)?;
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    // Reads the oldest pending frame and converts it to the capture format.
+    fn read_oldest<F, G>(&mut self, factory: &mut F, f: &mut G)
+        -> Result<(), gfx::mapping::Error>
+        where F: gfx::Factory<R>,
+              G: FnMut(&[u8])
+    {
+        let slot = self.pending[0];
+        {
+            let reader = factory.read_mapping(&self.buffers[slot])?;
+            let (w, h) = (self.size[0] as usize, self.size[1] as usize);
+            let rgba: &[u8] = if self.target == Srgba8::get_format() {
+                &reader
+            } else {
+                self.srgba.clear();
+                linear_to_srgba(&reader, &mut self.srgba);
+                &self.srgba
+            };
+            self.frame.clear();
+            // Render targets are stored with the bottom row first.
+            match self.format {
+                CaptureFormat::Rgba => {
+                    for y in (0..h).rev() {
+                        self.frame.extend_from_slice(&rgba[4 * w * y..4 * w * (y + 1)]);
+                    }
+                }
+                CaptureFormat::I420 => rgba_to_i420(rgba, w, h, &mut self.frame),
+            }
+        }
+        self.pending.pop_front();
+        f(&self.frame);
+        Ok(())
+    }
+}
+
+// Converts `Rgba16F` pixels to `Srgba8`, clamping the linear colors.
+fn linear_to_srgba(rgba16f: &[u8], out: &mut Vec<u8>) {
+    for pixel in rgba16f.chunks(8) {
+        let c = |i: usize| half_to_f32(pixel[2 * i] as u16 | (pixel[2 * i + 1] as u16) << 8);
+        let srgb = gamma_linear_to_srgb([c(0), c(1), c(2), c(3)]);
+        out.extend(srgb.iter().map(|&c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+    }
+}
+
+// Converts bottom-up RGBA to top-down I420.
+fn rgba_to_i420(rgba: &[u8], w: usize, h: usize, out: &mut Vec<u8>) {
+    let pixel = |x: usize, y: usize| {
+        let i = 4 * (x + w * (h - 1 - y));
+        (rgba[i] as i32, rgba[i + 1] as i32, rgba[i + 2] as i32)
+    };
+    for y in 0..h {
+        for x in 0..w {
+            let (r, g, b) = pixel(x, y);
+            out.push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+        }
+    }
+
+    // Chroma is averaged over blocks of 2x2 pixels, clamped at the edges.
+    let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+    let mut v_plane = Vec::with_capacity(cw * ch);
+    for cy in 0..ch {
+        for cx in 0..cw {
+            let (mut r, mut g, mut b) = (0, 0, 0);
+            for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let p = pixel((2 * cx + dx).min(w - 1), (2 * cy + dy).min(h - 1));
+                r += p.0;
+                g += p.1;
+                b += p.2;
+            }
+            let (r, g, b) = (r / 4, g / 4, b / 4);
+            out.push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+            v_plane.push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+        }
+    }
+    out.extend_from_slice(&v_plane);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn i420_planes() {
+        // Three pixels wide, with a black bottom row stored first and a white top row.
+        let mut rgba = vec![];
+        rgba.extend_from_slice(&[0, 0, 0, 255].repeat(3));
+        rgba.extend_from_slice(&[255; 4].repeat(3));
+        let mut out = vec![];
+        rgba_to_i420(&rgba, 3, 2, &mut out);
+        // The Y plane top row first, then U and V with the odd column clamped.
+        assert_eq!(out, [235, 235, 235, 16, 16, 16, 128, 128, 128, 128]);
+
+        let mut out = vec![];
+        rgba_to_i420(&[255, 0, 0, 255], 1, 1, &mut out);
+        assert_eq!(out, [82, 90, 240]);
+    }
+
+    #[test]
+    fn linear_to_srgba_clamped() {
+        // Halves of 0, 0.5, 1 and 2.
+        let halves: [u16; 4] = [0x0000, 0x3800, 0x3c00, 0x4000];
+        let bytes: Vec<u8> = halves.iter().flat_map(|&h| vec![h as u8, (h >> 8) as u8]).collect();
+        let mut out = vec![];
+        linear_to_srgba(&bytes, &mut out);
+        assert_eq!(out, [0, 188, 255, 255]);
+    }
+}
@@ -0,0 +1,350 @@
+//! Signed distance field glyph caching.
+//!
+//! Glyphs are rasterized once at a fixed pixel size and converted to a
+//! distance field, where the alpha channel stores the distance to the glyph
+//! outline. A value of `0.5` lies exactly on the outline, larger values are
+//! inside. Because the distance interpolates well, the same atlas can be used
+//! to draw crisp text at any scale.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use gfx;
+use gfx_texture::{self, CreateTexture, Format, ImageSize, TextureContext,
+    TextureSettings, UpdateTexture};
+use graphics::texture_packer::TexturePacker;
+use graphics::types::{Color, FontSize, Scalar};
+use rusttype;
+use Texture;
+
+/// The default pixel size glyphs are rasterized at.
+pub const SDF_SIZE: u32 = 48;
+/// The default distance in pixels covered by the distance field.
+pub const SDF_SPREAD: u32 = 6;
+/// The minimum atlas size.
+pub const ATLAS_SIZE: [u32; 2] = [512; 2];
+
+/// Stores glyph metrics in pixels of the rasterization size.
+#[derive(Copy, Clone, Debug)]
+pub struct SdfGlyph {
+    /// The offset of the glyph quad relative to the pen position.
+    pub offset: [Scalar; 2],
+    /// The advance size of the glyph, including space.
+    pub advance_size: [Scalar; 2],
+    /// The offset of the glyph within the texture atlas.
+    pub atlas_offset: [Scalar; 2],
+    /// The size of the glyph within the texture atlas.
+    pub atlas_size: [Scalar; 2],
+    /// The index of the texture atlas.
+    pub texture: usize,
+}
+
+/// Style settings for distance field text.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SdfStyle {
+    /// The fill color.
+    pub color: Color,
+    /// The outline color.
+    pub outline_color: Color,
+    /// The outline width in distance units, from `0.0` (none) to `0.5`.
+    pub outline_width: f32,
+    /// The glow color.
+    pub glow_color: Color,
+    /// The glow width in distance units, from `0.0` (none) to `0.5`.
+    ///
+    /// The glow starts where the outline ends.
+    pub glow_width: f32,
+}
+
+impl SdfStyle {
+    /// Creates a new style with fill color only.
+    pub fn new(color: Color) -> SdfStyle {
+        SdfStyle {
+            color,
+            outline_color: [0.0; 4],
+            outline_width: 0.0,
+            glow_color: [0.0; 4],
+            glow_width: 0.0,
+        }
+    }
+
+    /// Sets outline color and width.
+    pub fn outline(mut self, color: Color, width: f32) -> SdfStyle {
+        self.outline_color = color;
+        self.outline_width = width;
+        self
+    }
+
+    /// Sets glow color and width.
+    pub fn glow(mut self, color: Color, width: f32) -> SdfStyle {
+        self.glow_color = color;
+        self.glow_width = width;
+        self
+    }
+}
+
+/// A glyph cache storing signed distance fields.
+pub struct SdfGlyphCache<'a, F, R, C>
+    where F: gfx::Factory<R>,
+          R: gfx::Resources,
+          C: gfx::CommandBuffer<R>
+{
+    /// The font.
+    pub font: rusttype::Font<'a>,
+    /// The context used to create and update textures.
+    pub factory: TextureContext<F, R, C>,
+    settings: TextureSettings,
+    size: u32,
+    spread: u32,
+    texture_packer: TexturePacker<Texture<R>>,
+    data: HashMap<char, SdfGlyph>,
+}
+
+impl<'a, F, R, C> SdfGlyphCache<'a, F, R, C>
+    where F: gfx::Factory<R>,
+          R: gfx::Resources,
+          C: gfx::CommandBuffer<R>
+{
+    /// Constructs a glyph cache from a font.
+    pub fn from_font(
+        font: rusttype::Font<'a>,
+        factory: TextureContext<F, R, C>,
+        settings: TextureSettings
+    ) -> Self {
+        SdfGlyphCache {
+            font,
+            factory,
+            settings,
+            size: SDF_SIZE,
+            spread: SDF_SPREAD,
+            texture_packer: TexturePacker::new(),
+            data: HashMap::new(),
+        }
+    }
+
+    /// Constructs a glyph cache from a font file.
+    pub fn new<P>(
+        font: P,
+        factory: TextureContext<F, R, C>,
+        settings: TextureSettings
+    ) -> ::std::io::Result<SdfGlyphCache<'static, F, R, C>>
+        where P: AsRef<Path>
+    {
+        use std::io::{Error, ErrorKind};
+
+        let mut file = File::open(font)?;
+        let mut file_buffer = Vec::new();
+        file.read_to_end(&mut file_buffer)?;
+
+        let font = rusttype::FontCollection::from_bytes(file_buffer)
+            .and_then(|collection| collection.into_font())
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?;
+        Ok(SdfGlyphCache::from_font(font, factory, settings))
+    }
+
+    /// Constructs a glyph cache from a font stored in memory.
+    ///
+    /// Returns an error if the data is not a font, or a collection of several fonts.
+    pub fn from_bytes(
+        font: &'a [u8],
+        factory: TextureContext<F, R, C>,
+        settings: TextureSettings
+    ) -> Result<Self, rusttype::Error> {
+        let collection = rusttype::FontCollection::from_bytes(font)?;
+        let font = collection.into_font()?;
+        Ok(SdfGlyphCache::from_font(font, factory, settings))
+    }
+
+    /// Sets the pixel size glyphs are rasterized at and the spread of the distance field.
+    ///
+    /// Clears the cache, so call this before loading glyphs.
+    pub fn set_resolution(&mut self, size: u32, spread: u32) {
+        self.size = size.max(1);
+        self.spread = spread.max(1);
+        self.texture_packer = TexturePacker::new();
+        self.data.clear();
+    }
+
+    /// Returns the pixel size glyphs are rasterized at.
+    pub fn size(&self) -> u32 {self.size}
+
+    /// Returns the scale from rasterization size to the pixel size of `font_size`.
+    ///
+    /// Font size is in points, like for `GlyphCache`.
+    pub fn scale(&self, font_size: FontSize) -> Scalar {
+        (font_size as Scalar * 1.333).round() / self.size as Scalar
+    }
+
+    /// Returns the texture atlas with the given index.
+    pub fn texture(&self, index: usize) -> &Texture<R> {
+        &self.texture_packer.textures[index]
+    }
+
+    /// Returns glyph if it's already cached. Don't load.
+    pub fn opt_glyph(&self, ch: char) -> Option<SdfGlyph> {
+        self.data.get(&ch).cloned()
+    }
+
+    /// Load all characters in the `chars` iterator.
+    pub fn preload_chars<I>(&mut self, chars: I) -> Result<(), gfx_texture::Error>
+        where I: Iterator<Item = char>
+    {
+        for ch in chars {
+            self.glyph(ch)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the width of text for some given font size.
+    pub fn width(&mut self, font_size: FontSize, text: &str) -> Result<Scalar, gfx_texture::Error> {
+        let scale = self.scale(font_size);
+        let mut width = 0.0;
+        for ch in text.chars() {
+            width += self.glyph(ch)?.advance_size[0];
+        }
+        Ok(width * scale)
+    }
+
+    /// Gets a glyph, rendering the distance field if it is not cached.
+    pub fn glyph(&mut self, ch: char) -> Result<SdfGlyph, gfx_texture::Error> {
+        use self::rusttype as rt;
+
+        if let Some(glyph) = self.data.get(&ch) {
+            return Ok(*glyph);
+        }
+
+        let scale = rt::Scale::uniform(self.size as f32);
+        let mut glyph = self.font.glyph(ch).scaled(scale);
+
+        // some fonts do not contain glyph zero as fallback, instead try U+FFFD.
+        if glyph.id() == rt::GlyphId(0) && glyph.shape().is_none() {
+            glyph = self.font.glyph('\u{FFFD}').scaled(scale);
+        }
+
+        let h_metrics = glyph.h_metrics();
+        let glyph = glyph.positioned(rt::point(0.0, 0.0));
+        let pixel_bounding_box = glyph.pixel_bounding_box().unwrap_or(rt::Rect {
+            min: rt::Point { x: 0, y: 0 },
+            max: rt::Point { x: 0, y: 0 },
+        });
+        let pad = self.spread;
+        let size = [
+            pixel_bounding_box.width() as u32 + 2 * pad,
+            pixel_bounding_box.height() as u32 + 2 * pad,
+        ];
+
+        let mut coverage = vec![0.0; (size[0] * size[1]) as usize];
+        glyph.draw(|x, y, v| {
+            coverage[((x + pad) + (y + pad) * size[0]) as usize] = v;
+        });
+        let field = distance_field(&coverage, size, self.spread);
+
+        let (texture, atlas_offset) = match self.texture_packer.find_space(size) {
+            None => {
+                let w = size[0].max(ATLAS_SIZE[0]);
+                let h = size[1].max(ATLAS_SIZE[1]);
+                let mut image_buffer = vec![0; (w * h) as usize];
+                for y in 0..size[1] {
+                    for x in 0..size[0] {
+                        image_buffer[(x + y * w) as usize] = field[(x + y * size[0]) as usize];
+                    }
+                }
+                let buffer = gfx_texture::ops::alpha_to_rgba8(&image_buffer, [w, h]);
+                let atlas = CreateTexture::create(&mut self.factory, Format::Rgba8,
+                    &buffer, [w, h], &self.settings)?;
+                (self.texture_packer.create(size, atlas), [0, 0])
+            }
+            Some(ind) => {
+                let (texture, offset) = self.texture_packer.update(ind, size);
+                let buffer = gfx_texture::ops::alpha_to_rgba8(&field, size);
+                UpdateTexture::update(&mut self.texture_packer.textures[texture],
+                    &mut self.factory, Format::Rgba8, &buffer, offset, size)?;
+                (texture, offset)
+            }
+        };
+
+        let data = SdfGlyph {
+            offset: [
+                (pixel_bounding_box.min.x - pad as i32) as Scalar,
+                (pad as i32 - pixel_bounding_box.min.y) as Scalar,
+            ],
+            advance_size: [h_metrics.advance_width as Scalar, 0.0],
+            atlas_offset: [atlas_offset[0] as Scalar, atlas_offset[1] as Scalar],
+            atlas_size: [size[0] as Scalar, size[1] as Scalar],
+            texture,
+        };
+        self.data.insert(ch, data);
+        Ok(data)
+    }
+
+    /// Computes the texture coordinates of a glyph.
+    pub fn glyph_uv(&self, glyph: &SdfGlyph) -> [f32; 4] {
+        let (w, h) = self.texture(glyph.texture).get_size();
+        [
+            (glyph.atlas_offset[0] / w as Scalar) as f32,
+            (glyph.atlas_offset[1] / h as Scalar) as f32,
+            ((glyph.atlas_offset[0] + glyph.atlas_size[0]) / w as Scalar) as f32,
+            ((glyph.atlas_offset[1] + glyph.atlas_size[1]) / h as Scalar) as f32,
+        ]
+    }
+}
+
+// Converts coverage to a distance field, mapping `[-spread, spread]` to `[0, 255]`.
+//
+// Uses a brute force search within `spread` pixels,
+// which is fast enough since glyphs are rendered once.
+fn distance_field(coverage: &[f32], size: [u32; 2], spread: u32) -> Vec<u8> {
+    let (w, h) = (size[0] as i32, size[1] as i32);
+    let r = spread as i32;
+    let inside = |x: i32, y: i32| -> bool {
+        x >= 0 && y >= 0 && x < w && y < h &&
+        coverage[(x + y * w) as usize] >= 0.5
+    };
+    let mut field = Vec::with_capacity((w * h) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            let state = inside(x, y);
+            let mut best = (r * r + 1) as f32;
+            for dy in -r..(r + 1) {
+                for dx in -r..(r + 1) {
+                    let d = (dx * dx + dy * dy) as f32;
+                    if d < best && inside(x + dx, y + dy) != state {
+                        best = d;
+                    }
+                }
+            }
+            let dist = best.sqrt().min(spread as f32) - 0.5;
+            let signed = if state {dist} else {-dist};
+            let value = 0.5 + signed / (2.0 * spread as f32);
+            field.push((value.clamp(0.0, 1.0) * 255.0) as u8);
+        }
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_field_clamped_at_spread() {
+        // A covered grid with a hole in the center, more than the spread from the edges.
+        let size = 15;
+        let mut coverage = vec![1.0; size * size];
+        coverage[7 + 7 * size] = 0.0;
+        let field = distance_field(&coverage, [size as u32; 2], 3);
+        let at = |x: usize, y: usize| field[x + y * size];
+        // Half a pixel outside and inside of the outline.
+        assert_eq!(at(7, 7), 106);
+        assert_eq!(at(7, 8), 148);
+        assert!(at(7, 9) > at(7, 8));
+        assert_eq!(at(7, 10), 233);
+        // Farther pixels get the value at the spread.
+        assert_eq!(at(7, 11), 233);
+        assert_eq!(at(11, 11), 233);
+        // Pixels without coverage within the spread are as far outside as possible.
+        assert!(distance_field(&[0.0; 9], [3, 3], 3).iter().all(|&v| v == 21));
+    }
+}
@@ -0,0 +1,144 @@
+//! Batching of sprites by texture.
+
+use gfx;
+use graphics::DrawState;
+use graphics::BACK_END_MAX_VERTEX_COUNT as BUFFER_SIZE;
+use graphics::math::{transform_pos, Matrix2d};
+use graphics::types::Color;
+use GfxGraphics;
+use Texture;
+
+// The default maximum number of sprites in a batch.
+const MAX_SPRITES: usize = 4096;
+
+struct Sprite {
+    texture: usize,
+    tint: Color,
+    vertices: [[f32; 2]; 6],
+    uvs: [[f32; 2]; 6],
+}
+
+/// Accumulates sprites during a frame and draws them with one draw call per texture.
+///
+/// Tints are passed per vertex, so sprites with different tints share a draw call.
+/// Sprites with the same texture are drawn in the order they were added,
+/// but sprites with different textures may be reordered.
+/// Use separate batches for sprites that must overlap in a specific order.
+pub struct SpriteBatch<R: gfx::Resources> {
+    textures: Vec<Texture<R>>,
+    sprites: Vec<Sprite>,
+    max_sprites: usize,
+}
+
+impl<R: gfx::Resources> SpriteBatch<R> {
+    /// Creates a new sprite batch for up to 4096 sprites.
+    pub fn new() -> SpriteBatch<R> {
+        SpriteBatch::with_max_sprites(MAX_SPRITES)
+    }
+
+    /// Creates a new sprite batch with a maximum number of sprites.
+    pub fn with_max_sprites(max_sprites: usize) -> SpriteBatch<R> {
+        SpriteBatch {
+            textures: vec![],
+            sprites: Vec::with_capacity(max_sprites),
+            max_sprites,
+        }
+    }
+
+    /// Returns the maximum number of sprites.
+    pub fn max_sprites(&self) -> usize {self.max_sprites}
+
+    /// Returns the number of sprites added since the last flush.
+    pub fn len(&self) -> usize {self.sprites.len()}
+
+    /// Returns `true` if no sprites are added.
+    pub fn is_empty(&self) -> bool {self.sprites.is_empty()}
+
+    /// Returns `true` if the batch is full and must be flushed.
+    pub fn is_full(&self) -> bool {self.sprites.len() >= self.max_sprites}
+
+    /// Adds a sprite drawing the texture coordinates `[u1, v1, u2, v2]`
+    /// into the rectangle `[x, y, w, h]`.
+    ///
+    /// Returns `false` if the batch is full, in which case the sprite is not added.
+    pub fn add(
+        &mut self,
+        texture: &Texture<R>,
+        dst_rect: [f64; 4],
+        uv: [f32; 4],
+        tint: Color,
+        transform: Matrix2d
+    ) -> bool {
+        if self.is_full() {return false};
+
+        let texture = match self.textures.iter().position(|t| t == texture) {
+            Some(i) => i,
+            None => {
+                self.textures.push(texture.clone());
+                self.textures.len() - 1
+            }
+        };
+        let [x, y, w, h] = dst_rect;
+        let corner = |u: f64, v: f64| {
+            let p = transform_pos(transform, [x + u * w, y + v * h]);
+            [p[0] as f32, p[1] as f32]
+        };
+        self.sprites.push(Sprite {
+            texture,
+            tint,
+            vertices: [
+                corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0),
+                corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)
+            ],
+            uvs: [
+                [uv[0], uv[1]], [uv[2], uv[1]], [uv[0], uv[3]],
+                [uv[2], uv[1]], [uv[2], uv[3]], [uv[0], uv[3]]
+            ],
+        });
+        true
+    }
+
+    /// Draws all sprites and clears the batch.
+    pub fn flush<C>(&mut self, g: &mut GfxGraphics<R, C>, draw_state: &DrawState)
+        where C: gfx::CommandBuffer<R>
+    {
+        // Group by texture, keeping the order within each group.
+        let mut groups: Vec<(usize, Vec<usize>)> = vec![];
+        for (i, sprite) in self.sprites.iter().enumerate() {
+            match groups.iter_mut().find(|group| group.0 == sprite.texture) {
+                Some(group) => group.1.push(i),
+                None => groups.push((sprite.texture, vec![i])),
+            }
+        }
+
+        // Only whole sprites are drawn per chunk.
+        let chunk = BUFFER_SIZE / 6;
+        let mut vertices: Vec<[f32; 2]> = Vec::with_capacity(chunk * 6);
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(chunk * 6);
+        let mut colors: Vec<Color> = Vec::with_capacity(chunk * 6);
+        for &(texture, ref indices) in &groups {
+            let texture = &self.textures[texture];
+            for indices in indices.chunks(chunk) {
+                vertices.clear();
+                uvs.clear();
+                colors.clear();
+                for &i in indices {
+                    let sprite = &self.sprites[i];
+                    vertices.extend_from_slice(&sprite.vertices);
+                    uvs.extend_from_slice(&sprite.uvs);
+                    colors.extend_from_slice(&[sprite.tint; 6]);
+                }
+                g.tri_list_uv_c(draw_state, texture, |f| f(&vertices, &uvs, &colors));
+            }
+        }
+
+        self.sprites.clear();
+        self.textures.clear();
+    }
+}
+
+impl<R: gfx::Resources> Default for SpriteBatch<R> {
+    fn default() -> SpriteBatch<R> {
+        SpriteBatch::new()
+    }
+}
@@ -0,0 +1,398 @@
+//! Texture creation with checks against device limits.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::path::Path;
+
+use gfx;
+use gfx::format::{ChannelSource, Swizzle};
+use gfx_texture::{self, CreateTexture, Flip, Format, TextureContext, TextureSettings,
+    UpdateTexture};
+use image::{self, DynamicImage, RgbaImage};
+use Texture;
+
+/// An error when creating a texture.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextureError {
+    /// The requested size exceeds the maximum texture dimension.
+    TooLarge {
+        /// The requested size in pixels.
+        requested: [u32; 2],
+        /// The maximum width or height supported.
+        max: u32,
+    },
+    /// The region `[x, y, w, h]` is outside the texture.
+    OutOfBounds {
+        /// The region in pixels.
+        rect: [u32; 4],
+        /// The texture size in pixels.
+        size: [u32; 2],
+    },
+    /// The length of pixel data does not match the region.
+    InvalidLength {
+        /// The expected length in bytes.
+        expected: usize,
+        /// The actual length in bytes.
+        actual: usize,
+    },
+    /// An error when loading an image.
+    Load(String),
+    /// An error when creating or updating the texture.
+    Texture(gfx_texture::Error),
+}
+
+impl From<gfx_texture::Error> for TextureError {
+    fn from(val: gfx_texture::Error) -> TextureError {
+        TextureError::Texture(val)
+    }
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TextureError::TooLarge { requested, max } =>
+                write!(w, "Texture size {}x{} exceeds the maximum texture size {}",
+                    requested[0], requested[1], max),
+            TextureError::OutOfBounds { rect, size } =>
+                write!(w, "Region [{}, {}, {}, {}] is outside the texture of size {}x{}",
+                    rect[0], rect[1], rect[2], rect[3], size[0], size[1]),
+            TextureError::InvalidLength { expected, actual } =>
+                write!(w, "Expected {} bytes of pixel data, got {}", expected, actual),
+            TextureError::Load(ref err) => write!(w, "{}", err),
+            TextureError::Texture(ref err) => fmt::Display::fmt(err, w),
+        }
+    }
+}
+
+impl StdError for TextureError {}
+
+/// Returns the maximum texture width or height supported by the device.
+pub fn max_texture_size<F, R>(factory: &F) -> u32
+    where F: gfx::Factory<R>,
+          R: gfx::Resources
+{
+    factory.get_capabilities().max_texture_size as u32
+}
+
+/// Creates textures, checking the size against the maximum texture size.
+///
+/// By default, the maximum size is queried from the factory.
+/// Use `max_size` to set a lower limit, e.g. to reserve memory.
+#[derive(Clone, Copy)]
+pub struct TextureBuilder {
+    settings: TextureSettings,
+    flip: Flip,
+    max_size: Option<u32>,
+    swizzle: Swizzle,
+}
+
+impl TextureBuilder {
+    /// Creates a new texture builder.
+    pub fn new() -> TextureBuilder {
+        TextureBuilder {
+            settings: TextureSettings::new(),
+            flip: Flip::None,
+            max_size: None,
+            swizzle: Swizzle::new(),
+        }
+    }
+
+    /// Sets texture settings.
+    pub fn settings(mut self, settings: TextureSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Sets how to flip images loaded from path.
+    pub fn flip(mut self, flip: Flip) -> Self {
+        self.flip = flip;
+        self
+    }
+
+    /// Sets a maximum texture width or height below the one of the device.
+    ///
+    /// The lower of this and the device limit is used.
+    pub fn max_size(mut self, max_size: u32) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Sets how to rearrange the channels of the pixels,
+    /// e.g. `Swizzle(X, X, X, One)` to use the red channel as luminance.
+    ///
+    /// The swizzle is applied to the pixels before they are uploaded,
+    /// so all swizzles work on every backend and sampling costs nothing extra.
+    /// Later updates of the texture, e.g. with `UpdateRegion`, are not swizzled.
+    /// For `from_memory_alpha`, the swizzle applies to white pixels with the given alpha.
+    pub fn swizzle(mut self, swizzle: Swizzle) -> Self {
+        self.swizzle = swizzle;
+        self
+    }
+
+    /// Checks that a size is within the maximum texture size.
+    pub fn check_size<F, R, C>(
+        &self,
+        context: &TextureContext<F, R, C>,
+        size: [u32; 2]
+    ) -> Result<(), TextureError>
+        where F: gfx::Factory<R>,
+              R: gfx::Resources,
+              C: gfx::CommandBuffer<R>
+    {
+        let max = max_texture_size(&context.factory);
+        let max = self.max_size.map_or(max, |max_size| max_size.min(max));
+        if size[0] > max || size[1] > max {
+            Err(TextureError::TooLarge { requested: size, max })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates a texture from path.
+    pub fn from_path<F, R, C, P>(
+        &self,
+        context: &mut TextureContext<F, R, C>,
+        path: P
+    ) -> Result<Texture<R>, TextureError>
+        where F: gfx::Factory<R>,
+              R: gfx::Resources,
+              C: gfx::CommandBuffer<R>,
+              P: AsRef<Path>
+    {
+        let img = image::open(path).map_err(|e| TextureError::Load(e.to_string()))?;
+        let img = match img {
+            DynamicImage::ImageRgba8(img) => img,
+            img => img.to_rgba()
+        };
+        self.check_size(context, [img.width(), img.height()])?;
+
+        let mut img = match self.flip {
+            Flip::Vertical => image::imageops::flip_vertical(&img),
+            Flip::Horizontal => image::imageops::flip_horizontal(&img),
+            Flip::Both => {
+                let img = image::imageops::flip_vertical(&img);
+                image::imageops::flip_horizontal(&img)
+            }
+            Flip::None => img,
+        };
+        swizzle_pixels(self.swizzle, &mut img);
+        Ok(Texture::from_image(context, &img, &self.settings)?)
+    }
+
+    /// Creates a texture from image.
+    pub fn from_image<F, R, C>(
+        &self,
+        context: &mut TextureContext<F, R, C>,
+        img: &RgbaImage
+    ) -> Result<Texture<R>, TextureError>
+        where F: gfx::Factory<R>,
+              R: gfx::Resources,
+              C: gfx::CommandBuffer<R>
+    {
+        self.check_size(context, [img.width(), img.height()])?;
+        if self.swizzle == Swizzle::new() {
+            return Ok(Texture::from_image(context, img, &self.settings)?);
+        }
+        let mut img = img.clone();
+        swizzle_pixels(self.swizzle, &mut img);
+        Ok(Texture::from_image(context, &img, &self.settings)?)
+    }
+
+    /// Creates a texture from memory alpha.
+    pub fn from_memory_alpha<F, R, C>(
+        &self,
+        context: &mut TextureContext<F, R, C>,
+        buffer: &[u8],
+        width: u32,
+        height: u32
+    ) -> Result<Texture<R>, TextureError>
+        where F: gfx::Factory<R>,
+              R: gfx::Resources,
+              C: gfx::CommandBuffer<R>
+    {
+        self.check_size(context, [width, height])?;
+        if self.swizzle == Swizzle::new() {
+            return Ok(Texture::from_memory_alpha(context, buffer, width, height,
+                &self.settings)?);
+        }
+        let mut pixels = gfx_texture::ops::alpha_to_rgba8(buffer, [width, height]);
+        swizzle_pixels(self.swizzle, &mut pixels);
+        Ok(CreateTexture::create(context, Format::Rgba8, &pixels, [width, height],
+            &self.settings)?)
+    }
+}
+
+impl Default for TextureBuilder {
+    fn default() -> TextureBuilder {
+        TextureBuilder::new()
+    }
+}
+
+// Rearranges the channels of RGBA8 pixels.
+fn swizzle_pixels(swizzle: Swizzle, pixels: &mut [u8]) {
+    if swizzle == Swizzle::new() {return};
+
+    let Swizzle(r, g, b, a) = swizzle;
+    let channel = |source: ChannelSource, px: &[u8]| match source {
+        ChannelSource::Zero => 0,
+        ChannelSource::One => 255,
+        ChannelSource::X => px[0],
+        ChannelSource::Y => px[1],
+        ChannelSource::Z => px[2],
+        ChannelSource::W => px[3],
+    };
+    for px in pixels.chunks_mut(4) {
+        let out = [channel(r, px), channel(g, px), channel(b, px), channel(a, px)];
+        px.copy_from_slice(&out);
+    }
+}
+
+// Checks that a region is within the texture and matches the length of RGBA8 pixel data.
+fn check_region<R>(texture: &Texture<R>, rect: [u32; 4], len: usize) -> Result<(), TextureError>
+    where R: gfx::Resources
+{
+    use gfx_texture::ImageSize;
+
+    let (w, h) = texture.get_size();
+    // Regions ending past `u32::MAX` are out of bounds too.
+    let inside = |start: u32, len: u32, size: u32| {
+        start.checked_add(len).is_some_and(|end| end <= size)
+    };
+    if !inside(rect[0], rect[2], w) || !inside(rect[1], rect[3], h) {
+        return Err(TextureError::OutOfBounds { rect, size: [w, h] });
+    }
+    let expected = rect[2] as usize * rect[3] as usize * 4;
+    if len != expected {
+        return Err(TextureError::InvalidLength { expected, actual: len });
+    }
+    Ok(())
+}
+
+/// Updates a region of a texture.
+pub trait UpdateRegion<R: gfx::Resources> {
+    /// Updates the region `[x, y, w, h]` with RGBA8 pixels.
+    ///
+    /// Only the region is uploaded, which saves bandwidth when e.g. a tile changes.
+    fn update_region<C>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        rect: [u32; 4],
+        memory: &[u8]
+    ) -> Result<(), TextureError>
+        where C: gfx::CommandBuffer<R>;
+}
+
+impl<R: gfx::Resources> UpdateRegion<R> for Texture<R> {
+    fn update_region<C>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        rect: [u32; 4],
+        memory: &[u8]
+    ) -> Result<(), TextureError>
+        where C: gfx::CommandBuffer<R>
+    {
+        use gfx::format::Rgba8;
+
+        check_region(self, rect, memory.len())?;
+        let img_info = gfx::texture::ImageInfoCommon {
+            xoffset: rect[0] as u16,
+            yoffset: rect[1] as u16,
+            zoffset: 0,
+            width: rect[2] as u16,
+            height: rect[3] as u16,
+            depth: 0,
+            format: (),
+            mipmap: 0,
+        };
+        let data = gfx::memory::cast_slice(memory);
+        encoder.update_texture::<_, Rgba8>(&self.surface, None, img_info, data)
+            .map_err(|err| TextureError::Texture(err.into()))
+    }
+}
+
+/// How `UploadAsync::upload_async` updated a texture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UploadPath {
+    /// The copy from a staging buffer is recorded on the encoder.
+    Staged,
+    /// The texture was updated synchronously.
+    Direct,
+}
+
+/// Uploads texture data through a staging buffer.
+pub trait UploadAsync<F, R, C>
+    where F: gfx::Factory<R>,
+          R: gfx::Resources,
+          C: gfx::CommandBuffer<R>
+{
+    /// Uploads RGBA8 pixels to a region of the texture without blocking.
+    ///
+    /// The pixels are written to a staging buffer and the copy is recorded
+    /// on the encoder of the context. Draws recorded after this call can use
+    /// the texture, and the copy is done on the GPU when the encoder is flushed,
+    /// so the new pixels are visible in the next submitted frame.
+    ///
+    /// The staging buffer requires a texture with the `TRANSFER_DST` bind flag.
+    /// Otherwise, e.g. for textures created by `gfx_texture`,
+    /// or if the staging buffer can not be created,
+    /// this falls back to a synchronous update. Returns the path that was taken.
+    fn upload_async(
+        &mut self,
+        context: &mut TextureContext<F, R, C>,
+        memory: &[u8],
+        offset: [u32; 2],
+        size: [u32; 2]
+    ) -> Result<UploadPath, TextureError>;
+}
+
+impl<F, R, C> UploadAsync<F, R, C> for Texture<R>
+    where F: gfx::Factory<R>,
+          R: gfx::Resources,
+          C: gfx::CommandBuffer<R>
+{
+    fn upload_async(
+        &mut self,
+        context: &mut TextureContext<F, R, C>,
+        memory: &[u8],
+        offset: [u32; 2],
+        size: [u32; 2]
+    ) -> Result<UploadPath, TextureError> {
+        use gfx::format::{Formatted, Srgba8};
+        use gfx::memory::{Bind, Typed};
+        use gfx::traits::FactoryExt;
+        use gfx::texture::RawImageInfo;
+
+        check_region(self, [offset[0], offset[1], size[0], size[1]], memory.len())?;
+        let len = memory.len();
+
+        let raw = self.surface.raw().clone();
+        if raw.get_info().bind.contains(Bind::TRANSFER_DST) {
+            if let Ok(staging) = context.factory.create_upload_buffer::<u8>(len) {
+                let written = match context.factory.write_mapping(&staging) {
+                    Ok(mut writer) => {
+                        writer.copy_from_slice(memory);
+                        true
+                    }
+                    Err(_) => false,
+                };
+                let info = RawImageInfo {
+                    xoffset: offset[0] as u16,
+                    yoffset: offset[1] as u16,
+                    zoffset: 0,
+                    width: size[0] as u16,
+                    height: size[1] as u16,
+                    depth: 0,
+                    format: Srgba8::get_format(),
+                    mipmap: 0,
+                };
+                if written && context.encoder.copy_buffer_to_texture_raw(
+                    staging.raw(), 0, &raw, None, info).is_ok()
+                {
+                    return Ok(UploadPath::Staged);
+                }
+            }
+        }
+        UpdateTexture::update(self, context, Format::Rgba8, memory, offset, size)?;
+        Ok(UploadPath::Direct)
+    }
+}